@@ -1,24 +1,21 @@
-//THIS CONTRACT IS NOT COMPLETED  BECAUSE IT NEEDS INTEGRATING WITH LENDING PROTOCOL WHERE WE CAN DEPOSIT RWA TOKENS 
+//THIS CONTRACT IS NOT COMPLETED  BECAUSE IT NEEDS INTEGRATING WITH LENDING PROTOCOL WHERE WE CAN DEPOSIT RWA TOKENS
 #[cfg(not(feature = "library"))]
 use cosmwasm_std::entry_point;
 use cosmwasm_std::{
-    to_json_binary, Addr, Binary, CosmosMsg, Deps, DepsMut, Env, MessageInfo, Response, StdError,
-    StdResult, Uint128, WasmMsg,
+    to_json_binary, Addr, Api, Binary, CosmosMsg, Deps, DepsMut, Env, MessageInfo, Response,
+    StdError, StdResult, Uint128, WasmMsg,
 };
 use cw2::set_contract_version;
 
-use cw20::{Cw20ExecuteMsg, Denom, Expiration, MinterResponse};
-use cw20_base::contract::query_balance;
-use cw20_base::msg;
-use serde::de;
+use cw20::{Cw20ExecuteMsg, Expiration};
 
 use crate::error::ContractError;
 use crate::msg::{ExecuteMsg, InstantiateMsg, QueryMsg};
 use crate::state::{Config, Swapvar, BALANCE_OF, CONFIG, SWAPVAR, TOTAL_SUPPLY};
 
 use wasmswap::msg::{
-    ExecuteMsg as swapExecute, InstantiateMsg as swapInstantiateMSg, QueryMsg as swapQueryMsg,
-    Token1ForToken2PriceResponse, Token2ForToken1PriceResponse, TokenSelect,
+    ExecuteMsg as swapExecute, QueryMsg as swapQueryMsg, Token1ForToken2PriceResponse,
+    Token2ForToken1PriceResponse, TokenSelect,
 };
 
 const CONTRACT_NAME: &str = "crates.io:cw-vault";
@@ -28,30 +25,22 @@ const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
 pub fn instantiate(
     deps: DepsMut,
     _env: Env,
-    info: MessageInfo,
+    _info: MessageInfo,
     msg: InstantiateMsg,
 ) -> Result<Response, ContractError> {
-    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
-    let owner = msg.owner_addr;
-    let validate_owner = deps.api.addr_validate(&owner)?;
-    let token = msg.token_addr;
-    let validate_token = deps.api.addr_validate(&token)?;
-
-    let lp_pool_1 = msg.lp_pool_1;
-    let validate_lp_1 = deps.api.addr_validate(&lp_pool_1)?;
+    let validate_owner = validate_required(deps.api, "owner_addr", &msg.owner_addr)?;
+    let validate_token = validate_required(deps.api, "token_addr", &msg.token_addr)?;
 
-    let lp_pool_2 = msg.lp_pool_2;
-    let validated_lp_2 = deps.api.addr_validate(&lp_pool_2)?;
+    let validate_lp_1 = validate_required(deps.api, "lp_pool_1", &msg.lp_pool_1)?;
+    let validated_lp_2 = validate_required(deps.api, "lp_pool_2", &msg.lp_pool_2)?;
 
-    let rec_token_1 = msg.rec_token1;
-    let validate_token_1 = deps.api.addr_validate(&rec_token_1)?;
-
-    let rec_token_2 = msg.rec_token2;
-    let validate_token_2 = deps.api.addr_validate(&rec_token_2)?;
+    let validate_token_1 = validate_required(deps.api, "rec_token1", &msg.rec_token1)?;
+    let validate_token_2 = validate_required(deps.api, "rec_token2", &msg.rec_token2)?;
 
     let config = Config {
         token: validate_token,
         owner: validate_owner,
+        keeper: None,
     };
 
     let swapvar = Swapvar {
@@ -61,10 +50,23 @@ pub fn instantiate(
         rec_token_2: validate_token_2,
     };
 
+    // everything is validated above, so a rejected message leaves no partial state behind
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+    CONFIG.save(deps.storage, &config)?;
     SWAPVAR.save(deps.storage, &swapvar)?;
     TOTAL_SUPPLY.save(deps.storage, &Uint128::zero())?;
-    CONFIG.save(deps.storage, &config)?;
-    Ok(Response::new().add_attribute("action", "Instantitate"))
+    Ok(Response::new().add_attribute("action", "instantiate"))
+}
+
+/// Every address in `InstantiateMsg` is required; an empty value is reported by field name
+/// instead of surfacing as a generic address validation error.
+fn validate_required(api: &dyn Api, field: &str, value: &str) -> Result<Addr, ContractError> {
+    if value.trim().is_empty() {
+        return Err(ContractError::MissingField {
+            field: field.to_string(),
+        });
+    }
+    Ok(api.addr_validate(value)?)
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
@@ -77,6 +79,8 @@ pub fn execute(
     match msg {
         ExecuteMsg::Deposit { amount } => execute_deposit(deps, env, info, amount),
         ExecuteMsg::Withdraw { share } => execute_withdraw(deps, env, info, share),
+        ExecuteMsg::Rebalance {} => execute_rebalance(deps, env, info),
+        ExecuteMsg::SetKeeper { address } => execute_set_keeper(deps, info, address),
     }
 }
 
@@ -87,35 +91,40 @@ fn execute_deposit(
     amount: Uint128,
 ) -> Result<Response, ContractError> {
     let config = CONFIG.load(deps.storage)?;
-    let mut shares = Uint128::zero();
+    let swapvar = SWAPVAR.load(deps.storage)?;
     let mut total_supply = TOTAL_SUPPLY.load(deps.storage)?;
     let mut balance = BALANCE_OF
         .load(deps.storage, info.sender.clone())
         .unwrap_or(Uint128::zero());
 
-    let balance_contract =
-        get_token_balance_of(&deps, env.contract.address.clone(), config.token.clone())?;
-
-    if total_supply.is_zero() {
-        shares = amount;
+    // earlier deposits were swapped into the legs, so the shares are priced off their value,
+    // exactly as a withdrawal pays them out
+    let shares = if total_supply.is_zero() {
+        amount
     } else {
-        shares += amount
+        let legs_value: Uint128 = leg_holdings(&deps, &env.contract.address, &swapvar)?
+            .iter()
+            .map(|(_, value)| value)
+            .sum();
+        amount
             .checked_mul(total_supply)
             .map_err(StdError::overflow)?
-            .checked_div(balance_contract)
-            .map_err(StdError::divide_by_zero)?;
-    }
+            .checked_div(legs_value)
+            .map_err(StdError::divide_by_zero)?
+    };
 
-    total_supply += shares;
+    total_supply = total_supply
+        .checked_add(shares)
+        .map_err(StdError::overflow)?;
     TOTAL_SUPPLY.save(deps.storage, &total_supply)?;
-    balance += shares;
+    balance = balance.checked_add(shares).map_err(StdError::overflow)?;
 
     BALANCE_OF.save(deps.storage, info.sender.clone(), &balance)?;
 
     let transfer_cw20 = Cw20ExecuteMsg::TransferFrom {
         owner: info.sender.into(),
         recipient: env.contract.address.into(),
-        amount: amount,
+        amount,
     };
 
     let msg = WasmMsg::Execute {
@@ -125,19 +134,19 @@ fn execute_deposit(
     };
 
     let c_msg: CosmosMsg = msg.into();
-    let swapvar = SWAPVAR.load(deps.storage)?;
 
-    let ratio = Uint128::new(2);
+    // both legs are weighted equally
+    let inputs = allocate(amount, &[1, 1]);
 
-    let allow1 = get_cw20_increase_allowance_msg(&config.token, &swapvar.lp_pool_1, amount, None)?;
+    let allow1 =
+        get_cw20_increase_allowance_msg(&config.token, &swapvar.lp_pool_1, inputs[0], None)?;
 
-    let allow2 = get_cw20_increase_allowance_msg(&config.token, &swapvar.lp_pool_2, amount, None)?;
+    let allow2 =
+        get_cw20_increase_allowance_msg(&config.token, &swapvar.lp_pool_2, inputs[1], None)?;
 
     let swap1 = swapExecute::Swap {
         input_token: TokenSelect::Token1,
-        input_amount: amount
-            .checked_div(ratio)
-            .map_err(StdError::divide_by_zero)?,
+        input_amount: inputs[0],
         min_output: Uint128::zero(),
         expiration: None,
     };
@@ -152,9 +161,7 @@ fn execute_deposit(
 
     let swap2 = swapExecute::Swap {
         input_token: TokenSelect::Token1,
-        input_amount: amount
-            .checked_div(ratio)
-            .map_err(StdError::divide_by_zero)?,
+        input_amount: inputs[1],
         min_output: Uint128::zero(),
         expiration: None,
     };
@@ -184,30 +191,25 @@ fn execute_withdraw(
     share: Uint128,
 ) -> Result<Response, ContractError> {
     let config = CONFIG.load(deps.storage)?;
-    let token = config.token.clone();
 
     let swapvar = SWAPVAR.load(deps.storage)?;
 
     let mut total_supply = TOTAL_SUPPLY.load(deps.storage)?;
+    if share > total_supply {
+        return Err(ContractError::InvalidShareAmount {
+            share,
+            total_supply,
+        });
+    }
 
     let mut balance = BALANCE_OF
         .load(deps.storage, info.sender.clone())
         .unwrap_or(Uint128::zero());
 
-    let token_1_bal = get_token_balance_of(
-        &deps,
-        env.contract.address.clone(),
-        swapvar.rec_token_1.clone(),
-    )?;
-
-    let token_2_bal =
-        get_token_balance_of(&deps, env.contract.address, swapvar.rec_token_2.clone())?;
-
-    let am1: Uint128 = token_conversion(&deps, swapvar.lp_pool_1.clone(), token_1_bal)?;
+    let [(token_1_bal, am1), (token_2_bal, am2)] =
+        leg_holdings(&deps, &env.contract.address, &swapvar)?;
 
-    let am2: Uint128 = token_conversion(&deps, swapvar.lp_pool_2.clone(), token_2_bal)?;
-
-    let token_bal: Uint128 = am1 + am2;
+    let token_bal: Uint128 = am1.checked_add(am2).map_err(StdError::overflow)?;
 
     let amount = share
         .checked_mul(token_bal)
@@ -215,14 +217,23 @@ fn execute_withdraw(
         .checked_div(total_supply)
         .map_err(StdError::divide_by_zero)?;
 
-    total_supply -= share;
+    total_supply = total_supply
+        .checked_sub(share)
+        .map_err(StdError::overflow)?;
     TOTAL_SUPPLY.save(deps.storage, &total_supply)?;
-    balance -= share;
-    BALANCE_OF.save(deps.storage, info.sender.clone(), &balance)?;
+    balance = balance
+        .checked_sub(share)
+        .map_err(|_| ContractError::NotEnoughBalance {})?;
+    // a fully withdrawn holder is dropped so enumeration never sees a zero balance
+    if balance.is_zero() {
+        BALANCE_OF.remove(deps.storage, info.sender.clone());
+    } else {
+        BALANCE_OF.save(deps.storage, info.sender.clone(), &balance)?;
+    }
 
     let transfer_cw20 = Cw20ExecuteMsg::Transfer {
         recipient: info.sender.into(),
-        amount: amount,
+        amount,
     };
     let msg = WasmMsg::Execute {
         contract_addr: config.token.into(),
@@ -247,7 +258,7 @@ fn execute_withdraw(
     )?;
 
     let swap1 = swapExecute::Swap {
-        input_token: TokenSelect::Token1,
+        input_token: TokenSelect::Token2,
         input_amount: token_1_bal,
         min_output: Uint128::zero(),
         expiration: None,
@@ -286,6 +297,163 @@ fn execute_withdraw(
         .add_message(c_msg))
 }
 
+fn execute_rebalance(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+) -> Result<Response, ContractError> {
+    assert_owner_or_keeper(&deps, &info)?;
+    let config = CONFIG.load(deps.storage)?;
+
+    let swapvar = SWAPVAR.load(deps.storage)?;
+    let legs = [
+        (&swapvar.lp_pool_1, &swapvar.rec_token_1),
+        (&swapvar.lp_pool_2, &swapvar.rec_token_2),
+    ];
+    let (balances, values): (Vec<Uint128>, Vec<Uint128>) =
+        leg_holdings(&deps, &env.contract.address, &swapvar)?
+            .into_iter()
+            .unzip();
+    // both legs are weighted equally
+    let targets = allocate(values.iter().sum(), &[1, 1]);
+
+    // the heavier leg is sold before the lighter one buys, so the base token it frees is
+    // there to spend
+    let mut sells: Vec<CosmosMsg> = vec![];
+    let mut buys: Vec<CosmosMsg> = vec![];
+    for ((((lp_pool, rec_token), balance), value), target) in
+        legs.iter().zip(&balances).zip(&values).zip(&targets)
+    {
+        if value > target {
+            let excess = balance.multiply_ratio(*value - *target, *value);
+            if !excess.is_zero() {
+                sells.push(get_cw20_increase_allowance_msg(
+                    rec_token, lp_pool, excess, None,
+                )?);
+                sells.push(get_swap_msg(lp_pool, TokenSelect::Token2, excess)?);
+            }
+        } else if target > value {
+            let deficit = *target - *value;
+            buys.push(get_cw20_increase_allowance_msg(
+                &config.token,
+                lp_pool,
+                deficit,
+                None,
+            )?);
+            buys.push(get_swap_msg(lp_pool, TokenSelect::Token1, deficit)?);
+        }
+    }
+
+    let join = |amounts: &[Uint128]| {
+        amounts
+            .iter()
+            .map(Uint128::to_string)
+            .collect::<Vec<_>>()
+            .join(",")
+    };
+    Ok(Response::new()
+        .add_messages(sells)
+        .add_messages(buys)
+        .add_attribute("action", "rebalance")
+        .add_attribute("old_values", join(&values))
+        .add_attribute("new_values", join(&targets)))
+}
+
+/// Each leg's received-token balance and what it sells for in the base token, first leg first.
+/// The two values together are what the shares are worth.
+fn leg_holdings(
+    deps: &DepsMut,
+    contract: &Addr,
+    swapvar: &Swapvar,
+) -> Result<[(Uint128, Uint128); 2], ContractError> {
+    let mut holdings = [(Uint128::zero(), Uint128::zero()); 2];
+    let legs = [
+        (&swapvar.lp_pool_1, &swapvar.rec_token_1),
+        (&swapvar.lp_pool_2, &swapvar.rec_token_2),
+    ];
+    for ((lp_pool, rec_token), holding) in legs.into_iter().zip(holdings.iter_mut()) {
+        let balance = get_token_balance_of(deps, contract.clone(), rec_token.clone())?;
+        let value = if balance.is_zero() {
+            Uint128::zero()
+        } else {
+            token_conversion(deps, lp_pool.clone(), TokenSelect::Token2, balance)?
+        };
+        *holding = (balance, value);
+    }
+    Ok(holdings)
+}
+
+fn execute_set_keeper(
+    deps: DepsMut,
+    info: MessageInfo,
+    address: Option<String>,
+) -> Result<Response, ContractError> {
+    let mut config = CONFIG.load(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    config.keeper = address
+        .map(|address| validate_required(deps.api, "address", &address))
+        .transpose()?;
+    CONFIG.save(deps.storage, &config)?;
+
+    Ok(Response::new()
+        .add_attribute("action", "set_keeper")
+        .add_attribute(
+            "keeper",
+            config.keeper.map(String::from).unwrap_or_default(),
+        ))
+}
+
+/// Maintenance that only moves the vault's assets around may be run by the keeper as well as
+/// the owner; everything that changes the vault's setup stays with the owner.
+fn assert_owner_or_keeper(deps: &DepsMut, info: &MessageInfo) -> Result<(), ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.owner && Some(&info.sender) != config.keeper.as_ref() {
+        return Err(ContractError::Unauthorized {});
+    }
+    Ok(())
+}
+
+fn get_swap_msg(
+    lp_pool: &Addr,
+    input_token: TokenSelect,
+    input_amount: Uint128,
+) -> StdResult<CosmosMsg> {
+    let swap = swapExecute::Swap {
+        input_token,
+        input_amount,
+        min_output: Uint128::zero(),
+        expiration: None,
+    };
+    let exec_swap = WasmMsg::Execute {
+        contract_addr: lp_pool.into(),
+        msg: to_json_binary(&swap)?,
+        funds: vec![],
+    };
+    Ok(exec_swap.into())
+}
+
+/// Splits `amount` in proportion to `weights`, flooring each share and handing the rounding
+/// remainder to the first entry so the allocations always add up to exactly `amount`. An
+/// all-zero weight set splits evenly.
+pub fn allocate(amount: Uint128, weights: &[u64]) -> Vec<Uint128> {
+    let total: u128 = weights.iter().map(|weight| *weight as u128).sum();
+    let mut allocations: Vec<Uint128> = weights
+        .iter()
+        .map(|weight| match total {
+            0 => amount.multiply_ratio(1u128, weights.len() as u128),
+            _ => amount.multiply_ratio(*weight as u128, total),
+        })
+        .collect();
+    let allocated: Uint128 = allocations.iter().sum();
+    if let Some(first) = allocations.first_mut() {
+        *first += amount - allocated;
+    }
+    allocations
+}
+
 fn get_cw20_increase_allowance_msg(
     token_addr: &Addr,
     spender: &Addr,
@@ -320,19 +488,34 @@ pub fn get_token_balance_of(
     Ok(resp.balance)
 }
 
+/// What `amount` of the pool's `input` side swaps into at the current price. Deposits sell
+/// the base token as token1; each leg's received token is valued and unwound as token2.
 pub fn token_conversion(
     deps: &DepsMut,
     lp: Addr,
+    input: TokenSelect,
     amount: Uint128,
 ) -> Result<Uint128, ContractError> {
-    let resp: Token2ForToken1PriceResponse = deps.querier.query_wasm_smart(
-        lp,
-        &swapQueryMsg::Token2ForToken1Price {
-            token2_amount: amount,
-        },
-    )?;
-
-    Ok(resp.token1_amount)
+    match input {
+        TokenSelect::Token1 => {
+            let resp: Token1ForToken2PriceResponse = deps.querier.query_wasm_smart(
+                lp,
+                &swapQueryMsg::Token1ForToken2Price {
+                    token1_amount: amount,
+                },
+            )?;
+            Ok(resp.token2_amount)
+        }
+        TokenSelect::Token2 => {
+            let resp: Token2ForToken1PriceResponse = deps.querier.query_wasm_smart(
+                lp,
+                &swapQueryMsg::Token2ForToken1Price {
+                    token2_amount: amount,
+                },
+            )?;
+            Ok(resp.token1_amount)
+        }
+    }
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
@@ -346,39 +529,683 @@ pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
 fn get_total_supply(deps: Deps) -> StdResult<Binary> {
     let total = TOTAL_SUPPLY.load(deps.storage)?;
 
-    return to_json_binary(&total);
+    to_json_binary(&total)
 }
 
 fn get_balance_of(deps: Deps, address: Addr) -> StdResult<Binary> {
-    let balance = BALANCE_OF.load(deps.storage, address)?;
+    let balance = BALANCE_OF
+        .may_load(deps.storage, address)?
+        .unwrap_or_default();
 
-    return to_json_binary(&balance);
+    to_json_binary(&balance)
 }
 
 #[cfg(test)]
 mod tests {
 
-    use crate::contract::{execute, instantiate};
+    use crate::contract::{allocate, execute, instantiate};
     use crate::msg::{ExecuteMsg, InstantiateMsg};
-    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
+    use crate::state::{BALANCE_OF, CONFIG, SWAPVAR, TOTAL_SUPPLY};
+    use crate::ContractError;
+    use cosmwasm_std::testing::{
+        mock_dependencies, mock_env, mock_info, MockApi, MockQuerier, MockStorage,
+    };
+    use cosmwasm_std::{
+        attr, from_json, to_json_binary, Addr, Binary, ContractResult, CosmosMsg, OwnedDeps,
+        SystemResult, Uint128, WasmMsg, WasmQuery,
+    };
+    use cw20::{BalanceResponse, Cw20ExecuteMsg};
+    use wasmswap::msg::{
+        ExecuteMsg as SwapExecuteMsg, QueryMsg as SwapQueryMsg, Token2ForToken1PriceResponse,
+        TokenSelect,
+    };
 
     pub const ADDR1: &str = "addr1";
     pub const ADDR2: &str = "addr2";
+    pub const POOL1: &str = "pool1";
+    pub const POOL2: &str = "pool2";
+    pub const TOKEN1: &str = "token1";
+    pub const TOKEN2: &str = "token2";
+
+    fn default_instantiate_msg() -> InstantiateMsg {
+        InstantiateMsg {
+            owner_addr: ADDR1.to_string(),
+            token_addr: ADDR2.to_string(),
+            lp_pool_1: POOL1.to_string(),
+            lp_pool_2: POOL2.to_string(),
+            rec_token1: TOKEN1.to_string(),
+            rec_token2: TOKEN2.to_string(),
+        }
+    }
 
     #[test]
 
     fn test_instantiate() {
         let mut deps = mock_dependencies();
         let env = mock_env();
-        let info = mock_info(ADDR1, &vec![]);
+        let info = mock_info(ADDR1, &[]);
+
+        let msg = default_instantiate_msg();
+
+        let res = instantiate(deps.as_mut(), env, info, msg).unwrap();
+
+        assert_eq!(res.attributes, vec![attr("action", "instantiate")]);
+    }
+
+    #[test]
+    fn test_msgs_round_trip_in_snake_case() {
+        let msg = default_instantiate_msg();
+        let json = to_json_binary(&msg).unwrap();
+        assert_eq!(
+            json,
+            Binary::from(
+                br#"{"owner_addr":"addr1","token_addr":"addr2","lp_pool_1":"pool1","lp_pool_2":"pool2","rec_token1":"token1","rec_token2":"token2"}"#
+            )
+        );
+        assert_eq!(from_json::<InstantiateMsg>(&json).unwrap(), msg);
+
+        let msg = ExecuteMsg::SetKeeper {
+            address: Some("keeper".to_string()),
+        };
+        let json = to_json_binary(&msg).unwrap();
+        assert_eq!(
+            json,
+            Binary::from(br#"{"set_keeper":{"address":"keeper"}}"#)
+        );
+        assert_eq!(from_json::<ExecuteMsg>(&json).unwrap(), msg);
+
+        // fields the contract does not know are refused rather than dropped
+        assert!(from_json::<ExecuteMsg>(br#"{"withdraw":{"share":"1","shares":"2"}}"#).is_err());
+    }
 
+    #[test]
+    fn test_rejected_instantiate_writes_nothing() {
+        let mut deps = mock_dependencies();
         let msg = InstantiateMsg {
-            owner_addr: ADDR1.to_string(),
-            token_addr: ADDR2.to_string(),
+            rec_token2: String::new(),
+            ..default_instantiate_msg()
         };
+        instantiate(deps.as_mut(), mock_env(), mock_info(ADDR1, &[]), msg).unwrap_err();
 
-        let res = instantiate(deps.as_mut(), env, info, msg).unwrap();
+        let storage = deps.as_ref().storage;
+        assert!(cw2::get_contract_version(storage).is_err());
+        assert!(CONFIG.may_load(storage).unwrap().is_none());
+        assert!(SWAPVAR.may_load(storage).unwrap().is_none());
+        assert!(TOTAL_SUPPLY.may_load(storage).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_instantiate_rejects_empty_fields() {
+        let fields = [
+            "owner_addr",
+            "token_addr",
+            "lp_pool_1",
+            "lp_pool_2",
+            "rec_token1",
+            "rec_token2",
+        ];
+        for field in fields {
+            let mut deps = mock_dependencies();
+            let mut msg = default_instantiate_msg();
+            match field {
+                "owner_addr" => msg.owner_addr = String::new(),
+                "token_addr" => msg.token_addr = String::new(),
+                "lp_pool_1" => msg.lp_pool_1 = String::new(),
+                "lp_pool_2" => msg.lp_pool_2 = " ".to_string(),
+                "rec_token1" => msg.rec_token1 = String::new(),
+                _ => msg.rec_token2 = String::new(),
+            }
+
+            let err =
+                instantiate(deps.as_mut(), mock_env(), mock_info(ADDR1, &[]), msg).unwrap_err();
+            assert!(
+                matches!(&err, ContractError::MissingField { field: f } if f == field),
+                "unexpected error for {}: {:?}",
+                field,
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn test_instantiate_persists_all_fields() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            default_instantiate_msg(),
+        )
+        .unwrap();
+
+        let config = CONFIG.load(deps.as_ref().storage).unwrap();
+        assert_eq!(config.owner, ADDR1);
+        assert_eq!(config.token, ADDR2);
+        let swapvar = SWAPVAR.load(deps.as_ref().storage).unwrap();
+        assert_eq!(swapvar.lp_pool_1, POOL1);
+        assert_eq!(swapvar.lp_pool_2, POOL2);
+        assert_eq!(swapvar.rec_token_1, TOKEN1);
+        assert_eq!(swapvar.rec_token_2, TOKEN2);
+    }
+
+    #[test]
+    fn test_withdraw_share_exceeds_total_supply() {
+        let mut deps = mock_dependencies();
+        let info = mock_info(ADDR1, &[]);
+
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            info.clone(),
+            default_instantiate_msg(),
+        )
+        .unwrap();
+        TOTAL_SUPPLY
+            .save(deps.as_mut().storage, &Uint128::new(1000))
+            .unwrap();
+
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            ExecuteMsg::Withdraw {
+                share: Uint128::MAX,
+            },
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::InvalidShareAmount { share, total_supply }
+                if share == Uint128::MAX && total_supply == Uint128::new(1000)
+        ));
+    }
+
+    #[test]
+    fn test_withdraw_sells_received_tokens() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            default_instantiate_msg(),
+        )
+        .unwrap();
+        TOTAL_SUPPLY
+            .save(deps.as_mut().storage, &Uint128::new(1000))
+            .unwrap();
+        BALANCE_OF
+            .save(
+                deps.as_mut().storage,
+                Addr::unchecked(ADDR1),
+                &Uint128::new(1000),
+            )
+            .unwrap();
+        mock_legs(&mut deps);
+
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            ExecuteMsg::Withdraw {
+                share: Uint128::new(1000),
+            },
+        )
+        .unwrap();
+        let swaps: Vec<(String, TokenSelect)> = res
+            .messages
+            .iter()
+            .filter_map(|sub| match &sub.msg {
+                CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr, msg, ..
+                }) => match from_json(msg) {
+                    Ok(SwapExecuteMsg::Swap { input_token, .. }) => {
+                        Some((contract_addr.clone(), input_token))
+                    }
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            swaps,
+            vec![
+                (POOL1.to_string(), TokenSelect::Token2),
+                (POOL2.to_string(), TokenSelect::Token2),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_withdraw_more_than_balance() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            default_instantiate_msg(),
+        )
+        .unwrap();
+        TOTAL_SUPPLY
+            .save(deps.as_mut().storage, &Uint128::new(1000))
+            .unwrap();
+        BALANCE_OF
+            .save(
+                deps.as_mut().storage,
+                Addr::unchecked(ADDR1),
+                &Uint128::new(400),
+            )
+            .unwrap();
+        mock_legs(&mut deps);
+
+        // within the total supply, but more than the sender holds
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            ExecuteMsg::Withdraw {
+                share: Uint128::new(500),
+            },
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::NotEnoughBalance {}));
+    }
+
+    /// Both legs hold 100 and are valued token2 in for token1 out.
+    fn mock_legs(deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>) {
+        deps.querier.update_wasm(|query| {
+            let res = match query {
+                WasmQuery::Smart { contract_addr, .. }
+                    if contract_addr == TOKEN1 || contract_addr == TOKEN2 =>
+                {
+                    to_json_binary(&BalanceResponse {
+                        balance: Uint128::new(100),
+                    })
+                }
+                WasmQuery::Smart { msg, .. } => match from_json(msg).unwrap() {
+                    SwapQueryMsg::Token2ForToken1Price { token2_amount } => {
+                        to_json_binary(&Token2ForToken1PriceResponse {
+                            token1_amount: token2_amount,
+                        })
+                    }
+                    other => panic!("unexpected query {:?}", other),
+                },
+                other => panic!("unexpected query {:?}", other),
+            };
+            SystemResult::Ok(ContractResult::Ok(res.unwrap()))
+        });
+    }
 
-        println!("Deployed {:?}", res);
+    #[test]
+    fn test_deposit_splits_odd_amount_exactly() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            default_instantiate_msg(),
+        )
+        .unwrap();
+        deps.querier.update_wasm(|_| {
+            SystemResult::Ok(ContractResult::Ok(
+                to_json_binary(&BalanceResponse {
+                    balance: Uint128::zero(),
+                })
+                .unwrap(),
+            ))
+        });
+
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR2, &[]),
+            ExecuteMsg::Deposit {
+                amount: Uint128::new(1_001),
+            },
+        )
+        .unwrap();
+
+        let inputs: Vec<Uint128> = res
+            .messages
+            .iter()
+            .filter_map(|sub| match &sub.msg {
+                CosmosMsg::Wasm(WasmMsg::Execute { msg, .. }) => {
+                    match from_json::<SwapExecuteMsg>(msg) {
+                        Ok(SwapExecuteMsg::Swap { input_amount, .. }) => Some(input_amount),
+                        _ => None,
+                    }
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(inputs, vec![Uint128::new(501), Uint128::new(500)]);
+    }
+
+    #[test]
+    fn test_second_deposit_priced_off_legs() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            default_instantiate_msg(),
+        )
+        .unwrap();
+        let deposit = |deps: &mut OwnedDeps<_, _, _>, sender: &str, amount: u128| {
+            execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info(sender, &[]),
+                ExecuteMsg::Deposit {
+                    amount: Uint128::new(amount),
+                },
+            )
+            .unwrap()
+        };
+        let shares_of = |deps: &OwnedDeps<_, _, _>, address: &str| {
+            BALANCE_OF
+                .load(deps.as_ref().storage, Addr::unchecked(address))
+                .unwrap()
+        };
+
+        let res = deposit(&mut deps, ADDR2, 1_000);
+        // each pool may pull only the half it swaps
+        let allowance = |spender: &str| {
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: ADDR2.to_string(),
+                msg: to_json_binary(&Cw20ExecuteMsg::IncreaseAllowance {
+                    spender: spender.to_string(),
+                    amount: Uint128::new(500),
+                    expires: None,
+                })
+                .unwrap(),
+                funds: vec![],
+            })
+        };
+        assert_eq!(res.messages[0].msg, allowance(POOL1));
+        assert_eq!(res.messages[1].msg, allowance(POOL2));
+        assert_eq!(shares_of(&deps, ADDR2), Uint128::new(1_000));
+
+        // the first deposit now sits in the legs, grown to 1200 worth
+        mock_leg_balances(&mut deps, 600, 600);
+        deposit(&mut deps, "bob", 600);
+        assert_eq!(shares_of(&deps, "bob"), Uint128::new(500));
+        assert_eq!(
+            TOTAL_SUPPLY.load(deps.as_ref().storage).unwrap(),
+            Uint128::new(1_500)
+        );
+    }
+
+    #[test]
+    fn test_rebalance_equalizes_legs() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            default_instantiate_msg(),
+        )
+        .unwrap();
+        // 300 of the first leg's token and 100 of the second, both pools quoting one-for-one
+        mock_leg_balances(&mut deps, 300, 100);
+
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR2, &[]),
+            ExecuteMsg::Rebalance {},
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            ExecuteMsg::Rebalance {},
+        )
+        .unwrap();
+        let msgs: Vec<(String, Binary)> = res
+            .messages
+            .iter()
+            .map(|sub| match &sub.msg {
+                CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr, msg, ..
+                }) => (contract_addr.clone(), msg.clone()),
+                other => panic!("unexpected message {:?}", other),
+            })
+            .collect();
+        let allowance = |spender: &str| {
+            to_json_binary(&Cw20ExecuteMsg::IncreaseAllowance {
+                spender: spender.to_string(),
+                amount: Uint128::new(100),
+                expires: None,
+            })
+            .unwrap()
+        };
+        let swap = |input_token: TokenSelect| {
+            to_json_binary(&SwapExecuteMsg::Swap {
+                input_token,
+                input_amount: Uint128::new(100),
+                min_output: Uint128::zero(),
+                expiration: None,
+            })
+            .unwrap()
+        };
+        // 100 of the first leg is sold and the proceeds buy 100 worth of the second
+        assert_eq!(
+            msgs,
+            vec![
+                (TOKEN1.to_string(), allowance(POOL1)),
+                (POOL1.to_string(), swap(TokenSelect::Token2)),
+                (ADDR2.to_string(), allowance(POOL2)),
+                (POOL2.to_string(), swap(TokenSelect::Token1)),
+            ]
+        );
+        assert_eq!(
+            res.attributes,
+            vec![
+                attr("action", "rebalance"),
+                attr("old_values", "300,100"),
+                attr("new_values", "200,200"),
+            ]
+        );
+    }
+
+    /// Answers cw20 balance queries for the two received tokens; both pools quote
+    /// one-for-one.
+    fn mock_leg_balances(
+        deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>,
+        leg_1: u128,
+        leg_2: u128,
+    ) {
+        mock_leg_prices(deps, leg_1, leg_2, [1, 1]);
+    }
+
+    /// Like `mock_leg_balances`, but each pool quotes `prices[i]` of the base token for one of
+    /// its leg's token.
+    fn mock_leg_prices(
+        deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>,
+        leg_1: u128,
+        leg_2: u128,
+        prices: [u128; 2],
+    ) {
+        deps.querier.update_wasm(move |query| {
+            let res = match query {
+                WasmQuery::Smart { contract_addr, .. } if contract_addr == TOKEN1 => {
+                    to_json_binary(&BalanceResponse {
+                        balance: Uint128::new(leg_1),
+                    })
+                }
+                WasmQuery::Smart { contract_addr, .. } if contract_addr == TOKEN2 => {
+                    to_json_binary(&BalanceResponse {
+                        balance: Uint128::new(leg_2),
+                    })
+                }
+                WasmQuery::Smart { contract_addr, msg } => match from_json(msg).unwrap() {
+                    SwapQueryMsg::Token2ForToken1Price { token2_amount } => {
+                        let price = if contract_addr == POOL1 {
+                            prices[0]
+                        } else {
+                            prices[1]
+                        };
+                        to_json_binary(&Token2ForToken1PriceResponse {
+                            token1_amount: token2_amount * Uint128::new(price),
+                        })
+                    }
+                    other => panic!("unexpected query {:?}", other),
+                },
+                other => panic!("unexpected query {:?}", other),
+            };
+            SystemResult::Ok(ContractResult::Ok(res.unwrap()))
+        });
+    }
+
+    #[test]
+    fn test_rebalance_values_legs_at_pool_price() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            default_instantiate_msg(),
+        )
+        .unwrap();
+        // equal token counts, but the second leg's token is worth three of the base token
+        mock_leg_prices(&mut deps, 90, 90, [1, 3]);
+
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            ExecuteMsg::Rebalance {},
+        )
+        .unwrap();
+        let swaps: Vec<(String, TokenSelect, Uint128)> = res
+            .messages
+            .iter()
+            .filter_map(|sub| match &sub.msg {
+                CosmosMsg::Wasm(WasmMsg::Execute {
+                    contract_addr, msg, ..
+                }) => match from_json(msg) {
+                    Ok(SwapExecuteMsg::Swap {
+                        input_token,
+                        input_amount,
+                        ..
+                    }) => Some((contract_addr.clone(), input_token, input_amount)),
+                    _ => None,
+                },
+                _ => None,
+            })
+            .collect();
+        // 90 worth of the second leg (30 tokens) is sold and 90 of the base buys into the first
+        assert_eq!(
+            swaps,
+            vec![
+                (POOL2.to_string(), TokenSelect::Token2, Uint128::new(30)),
+                (POOL1.to_string(), TokenSelect::Token1, Uint128::new(90)),
+            ]
+        );
+        assert!(res.attributes.contains(&attr("old_values", "90,270")));
+        assert!(res.attributes.contains(&attr("new_values", "180,180")));
+    }
+
+    #[test]
+    fn test_keeper_can_rebalance_but_not_set_keeper() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            default_instantiate_msg(),
+        )
+        .unwrap();
+        mock_leg_balances(&mut deps, 300, 100);
+        let set_keeper = |address: Option<&str>| ExecuteMsg::SetKeeper {
+            address: address.map(str::to_string),
+        };
+        let rebalance = |deps: &mut OwnedDeps<_, _, _>, sender: &str| {
+            execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info(sender, &[]),
+                ExecuteMsg::Rebalance {},
+            )
+        };
+
+        let err = rebalance(&mut deps, "keeper").unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            set_keeper(Some("keeper")),
+        )
+        .unwrap();
+        assert_eq!(
+            res.attributes,
+            vec![attr("action", "set_keeper"), attr("keeper", "keeper")]
+        );
+        rebalance(&mut deps, "keeper").unwrap();
+
+        // setup stays with the owner
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("keeper", &[]),
+            set_keeper(Some(ADDR2)),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+        assert_eq!(
+            CONFIG.load(deps.as_ref().storage).unwrap().keeper,
+            Some(Addr::unchecked("keeper"))
+        );
+
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            set_keeper(None),
+        )
+        .unwrap();
+        let err = rebalance(&mut deps, "keeper").unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+    }
+
+    #[test]
+    fn test_allocate_sums_to_amount() {
+        // xorshift so the sweep is deterministic without pulling in a property test crate
+        let mut seed = 0x9e37_79b9_7f4a_7c15u64;
+        let mut next = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+
+        for _ in 0..1_000 {
+            let amount = Uint128::new((next() as u128) << 64 | next() as u128);
+            let weights: Vec<u64> = (0..(next() % 4 + 1)).map(|_| next() % 100).collect();
+            let allocations = allocate(amount, &weights);
+            assert_eq!(allocations.len(), weights.len());
+            assert_eq!(allocations.iter().sum::<Uint128>(), amount);
+        }
+    }
+
+    #[test]
+    fn test_checked_in_schema_is_current() {
+        // the same api examples/schema.rs writes; rerun it when this fails
+        let api = cosmwasm_schema::generate_api! {
+            instantiate: InstantiateMsg,
+            execute: ExecuteMsg,
+            query: crate::msg::QueryMsg,
+            migrate: crate::msg::MigrateMsg,
+        }
+        .render();
+        for (file, json) in api.to_schema_files().unwrap() {
+            let path = format!("{}/schema/raw/{}", env!("CARGO_MANIFEST_DIR"), file);
+            let checked_in = std::fs::read_to_string(&path).unwrap();
+            assert!(json + "\n" == checked_in, "{} is stale", path);
+        }
     }
 }
@@ -1,4 +1,4 @@
-use cosmwasm_std::StdError;
+use cosmwasm_std::{StdError, Uint128};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -9,6 +9,18 @@ pub enum ContractError {
     #[error("NotEnoughBalance")]
     NotEnoughBalance {},
 
+    #[error("Unauthorized")]
+    Unauthorized {},
+
+    #[error("Invalid share amount: share: {share}, total_supply: {total_supply}")]
+    InvalidShareAmount {
+        share: Uint128,
+        total_supply: Uint128,
+    },
+
+    #[error("Missing required field: {field}")]
+    MissingField { field: String },
+
     #[error("Custom Error val: {val:?}")]
     CustomError { val: String },
 }
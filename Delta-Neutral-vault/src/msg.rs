@@ -1,10 +1,7 @@
 use cosmwasm_schema::{cw_serde, QueryResponses};
 use cosmwasm_std::{Addr, Uint128};
-use schemars::JsonSchema;
-use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
-#[serde(rename_all = "snake_case")]
+#[cw_serde]
 pub struct InstantiateMsg {
     pub owner_addr: String,
     pub token_addr: String,
@@ -14,11 +11,22 @@ pub struct InstantiateMsg {
     pub rec_token2: String,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
-#[serde(rename_all = "snake_case")]
+#[cw_serde]
 pub enum ExecuteMsg {
-    Deposit { amount: Uint128 },
-    Withdraw { share: Uint128 },
+    Deposit {
+        amount: Uint128,
+    },
+    Withdraw {
+        share: Uint128,
+    },
+    /// Owner or keeper only. Trades the heavier leg down and the lighter one up until both are worth
+    /// half of the position, valued at each pool's current price.
+    Rebalance {},
+    /// Owner only. Sets or, with no address, clears the keeper allowed to run `Rebalance`.
+    SetKeeper {
+        #[serde(default)]
+        address: Option<String>,
+    },
 }
 
 #[cw_serde]
@@ -31,12 +39,10 @@ pub enum QueryMsg {
     GetBalanceOf { address: Addr },
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
-#[serde(rename_all = "snake_case")]
+#[cw_serde]
 pub struct CustomResponse {
     val: String,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
-#[serde(rename_all = "snake_case")]
+#[cw_serde]
 pub enum MigrateMsg {}
@@ -8,6 +8,9 @@ use cw_storage_plus::{Item, Map};
 pub struct Config {
     pub token: Addr,
     pub owner: Addr,
+    /// May run `Rebalance` alongside the owner.
+    #[serde(default)]
+    pub keeper: Option<Addr>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
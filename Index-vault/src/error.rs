@@ -1,4 +1,5 @@
-use cosmwasm_std::StdError;
+use cosmwasm_std::{StdError, Uint128};
+use cw20::Expiration;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -9,6 +10,110 @@ pub enum ContractError {
     #[error("NotEnoughBalance")]
     NotEnoughBalance {},
 
+    #[error("Invalid share amount: share: {share}, total_supply: {total_supply}")]
+    InvalidShareAmount {
+        share: Uint128,
+        total_supply: Uint128,
+    },
+
+    #[error("Missing required field: {field}")]
+    MissingField { field: String },
+
+    #[error("Unauthorized")]
+    Unauthorized {},
+
+    #[error("A retokenize is in progress")]
+    RetokenizeInProgress {},
+
+    #[error("No retokenize is in progress")]
+    NoRetokenizeInProgress {},
+
+    #[error("A deposit or withdraw is already in progress")]
+    Reentrancy {},
+
+    #[error("No pool at index {index}")]
+    PoolNotFound { index: u32 },
+
+    #[error("Cannot remove the last pool of the index")]
+    LastPool {},
+
+    #[error("Pool {index} still holds {balance} of its received token")]
+    PoolNotEmpty { index: u32, balance: Uint128 },
+
+    #[error("Expected one min_output per pool ({expected}), got {got}")]
+    MinOutputsLength { expected: usize, got: usize },
+
+    #[error("Allocation mismatch: deposited {amount}, allocated {allocated}")]
+    AllocationMismatch { amount: Uint128, allocated: Uint128 },
+
+    #[error("Total supply changed from {before} to {after} outside a deposit or withdraw")]
+    SupplyChanged { before: Uint128, after: Uint128 },
+
+    #[error("Pool {pool} has no liquidity")]
+    DeadPool { pool: String },
+
+    #[error("Unknown reply id: {id}")]
+    UnknownReplyId { id: u64 },
+
+    #[error("Operation would emit {count} submessages, over the limit of {max}; split the pools across vaults")]
+    TooManySubmessages { count: u32, max: u32 },
+
+    #[error("Deposit would mint {shares} shares, below the minimum of {min}")]
+    SharesBelowMinimum { shares: Uint128, min: Uint128 },
+
+    #[error("Deposit swaps bought {value} worth of tokens, below the minimum of {min}")]
+    DeployedValueBelowMinimum { value: Uint128, min: Uint128 },
+
+    #[error("Every pool is paused")]
+    AllPoolsPaused {},
+
+    #[error("Invalid slippage: {bps} bps exceeds 10000")]
+    InvalidSlippage { bps: u16 },
+
+    #[error("Invalid reserve: {bps} bps exceeds 10000")]
+    InvalidReserve { bps: u16 },
+
+    #[error("Invalid protocol fee: {bps} bps exceeds 10000")]
+    InvalidProtocolFee { bps: u16 },
+
+    #[error("Swap on pool {pool} reported no token_bought")]
+    MissingSwapOutput { pool: String },
+
+    #[error("Invalid pool weight: {weight} must be greater than zero")]
+    InvalidWeight { weight: u64 },
+
+    #[error("Invalid pool weights: they sum to {total} instead of 10000")]
+    InvalidWeightTotal { total: u64 },
+
+    #[error("Invalid TWAP window: it must cover at least one observation")]
+    InvalidTwapWindow {},
+
+    #[error("Pool {pool} is listed more than once")]
+    DuplicatePool { pool: String },
+
+    #[error("Token {token} is received by more than one pool or is the base token")]
+    DuplicateToken { token: String },
+
+    #[error("Pool {pool} trades {actual} as {side}, expected {expected}")]
+    PoolDenomMismatch {
+        pool: String,
+        side: String,
+        expected: String,
+        actual: String,
+    },
+
+    #[error("Token {token} is the owner's address")]
+    TokenIsOwner { token: String },
+
+    #[error("Token {token} does not answer a cw20 TokenInfo query")]
+    NotCw20Token { token: String },
+
+    #[error("A pool price moved past the allowed deviation; the owner must reset prices")]
+    PriceDeviation {},
+
+    #[error("Deadline {deadline} has passed")]
+    DeadlineExpired { deadline: Expiration },
+
     #[error("Custom Error val: {val:?}")]
     CustomError { val: String },
 }
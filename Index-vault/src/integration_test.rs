@@ -0,0 +1,1063 @@
+#![cfg(test)]
+
+use cosmwasm_std::{from_json, Addr, Decimal, Empty, Uint128};
+use cw20::{BalanceResponse, Cw20Coin, Cw20ExecuteMsg, Cw20QueryMsg, Denom};
+use cw_multi_test::{App, Contract, ContractWrapper, Executor};
+use std::str::FromStr;
+
+use crate::msg::{ExecuteMsg, InstantiateMsg, PoolInit, QueryMsg, TotalSupplyResponse};
+use crate::state::{Config, DeadPoolPolicy, TOTAL_WEIGHT};
+use crate::ContractError;
+
+const OWNER: &str = "owner";
+const USER: &str = "user";
+
+fn mock_app() -> App {
+    App::default()
+}
+
+pub fn contract_vault() -> Box<dyn Contract<Empty>> {
+    let contract = ContractWrapper::new(
+        crate::contract::execute,
+        crate::contract::instantiate,
+        crate::contract::query,
+    )
+    .with_reply(crate::contract::reply);
+    Box::new(contract)
+}
+
+pub fn contract_amm() -> Box<dyn Contract<Empty>> {
+    let contract = ContractWrapper::new(
+        wasmswap::contract::execute,
+        wasmswap::contract::instantiate,
+        wasmswap::contract::query,
+    )
+    .with_reply(wasmswap::contract::reply);
+    Box::new(contract)
+}
+
+pub fn contract_cw20() -> Box<dyn Contract<Empty>> {
+    let contract = ContractWrapper::new(
+        cw20_base::contract::execute,
+        cw20_base::contract::instantiate,
+        cw20_base::contract::query,
+    );
+    Box::new(contract)
+}
+
+// CreateCW20 create new cw20 with given initial balance belonging to owner
+fn create_cw20(router: &mut App, owner: &Addr, symbol: &str, balance: Uint128) -> Addr {
+    let cw20_id = router.store_code(contract_cw20());
+    let msg = cw20_base::msg::InstantiateMsg {
+        name: format!("{} token", symbol),
+        symbol: symbol.to_string(),
+        decimals: 6,
+        initial_balances: vec![Cw20Coin {
+            address: owner.to_string(),
+            amount: balance,
+        }],
+        mint: None,
+        marketing: None,
+    };
+    router
+        .instantiate_contract(cw20_id, owner.clone(), &msg, &[], symbol, None)
+        .unwrap()
+}
+
+// create_amm creates a wasmswap pool with token1/token2 liquidity provided by owner
+fn create_amm(
+    router: &mut App,
+    owner: &Addr,
+    token1: &Addr,
+    token2: &Addr,
+    token1_amount: Uint128,
+    token2_amount: Uint128,
+) -> Addr {
+    let cw20_id = router.store_code(contract_cw20());
+    let amm_id = router.store_code(contract_amm());
+    let msg = wasmswap::msg::InstantiateMsg {
+        token1_denom: Denom::Cw20(token1.clone()),
+        token2_denom: Denom::Cw20(token2.clone()),
+        lp_token_code_id: cw20_id,
+        owner: Some(owner.to_string()),
+        lp_fee_percent: Decimal::from_str("0.3").unwrap(),
+        protocol_fee_percent: Decimal::zero(),
+        protocol_fee_recipient: owner.to_string(),
+    };
+    let amm = router
+        .instantiate_contract(amm_id, owner.clone(), &msg, &[], "amm", None)
+        .unwrap();
+    if token1_amount.is_zero() {
+        return amm;
+    }
+
+    increase_allowance(router, owner, token1, &amm, token1_amount);
+    increase_allowance(router, owner, token2, &amm, token2_amount);
+    router
+        .execute_contract(
+            owner.clone(),
+            amm.clone(),
+            &wasmswap::msg::ExecuteMsg::AddLiquidity {
+                token1_amount,
+                min_liquidity: Uint128::zero(),
+                max_token2: token2_amount,
+                expiration: None,
+            },
+            &[],
+        )
+        .unwrap();
+    amm
+}
+
+fn increase_allowance(
+    router: &mut App,
+    owner: &Addr,
+    token: &Addr,
+    spender: &Addr,
+    amount: Uint128,
+) {
+    router
+        .execute_contract(
+            owner.clone(),
+            token.clone(),
+            &Cw20ExecuteMsg::IncreaseAllowance {
+                spender: spender.to_string(),
+                amount,
+                expires: None,
+            },
+            &[],
+        )
+        .unwrap();
+}
+
+fn transfer(router: &mut App, owner: &Addr, token: &Addr, recipient: &Addr, amount: Uint128) {
+    router
+        .execute_contract(
+            owner.clone(),
+            token.clone(),
+            &Cw20ExecuteMsg::Transfer {
+                recipient: recipient.to_string(),
+                amount,
+            },
+            &[],
+        )
+        .unwrap();
+}
+
+fn cw20_balance(router: &App, token: &Addr, address: &Addr) -> Uint128 {
+    let resp: BalanceResponse = router
+        .wrap()
+        .query_wasm_smart(
+            token,
+            &Cw20QueryMsg::Balance {
+                address: address.to_string(),
+            },
+        )
+        .unwrap();
+    resp.balance
+}
+
+fn vault_config(router: &App, vault: &Addr) -> Config {
+    let raw = router
+        .wrap()
+        .query_wasm_raw(vault, b"Config".to_vec())
+        .unwrap()
+        .unwrap();
+    from_json(raw).unwrap()
+}
+
+/// Base token, two receive tokens, their base/receive pools and an index vault over them.
+struct IndexSuite {
+    router: App,
+    owner: Addr,
+    user: Addr,
+    base: Addr,
+    rec_token_1: Addr,
+    rec_token_2: Addr,
+    vault: Addr,
+}
+
+fn setup_index() -> IndexSuite {
+    setup_index_with(DeadPoolPolicy::Revert, Uint128::new(100_000), false)
+}
+
+/// Like `setup_index`, with the given dead-pool handling and `lp_pool_2` seeded with
+/// `pool_2_liquidity` of each token; zero leaves that pool without any liquidity.
+fn setup_index_with(
+    on_dead_pool: DeadPoolPolicy,
+    pool_2_liquidity: Uint128,
+    auto_pause_dead_pools: bool,
+) -> IndexSuite {
+    let mut router = mock_app();
+    let owner = Addr::unchecked(OWNER);
+    let user = Addr::unchecked(USER);
+    let supply = Uint128::new(1_000_000);
+    let liquidity = Uint128::new(100_000);
+
+    let base = create_cw20(&mut router, &owner, "BASE", supply);
+    let rec_token_1 = create_cw20(&mut router, &owner, "RECA", supply);
+    let rec_token_2 = create_cw20(&mut router, &owner, "RECB", supply);
+    let lp_pool_1 = create_amm(
+        &mut router,
+        &owner,
+        &base,
+        &rec_token_1,
+        liquidity,
+        liquidity,
+    );
+    let lp_pool_2 = create_amm(
+        &mut router,
+        &owner,
+        &base,
+        &rec_token_2,
+        pool_2_liquidity,
+        pool_2_liquidity,
+    );
+
+    let vault_id = router.store_code(contract_vault());
+    let vault = router
+        .instantiate_contract(
+            vault_id,
+            owner.clone(),
+            &InstantiateMsg {
+                owner_addr: owner.to_string(),
+                token_addr: base.to_string(),
+                pools: vec![
+                    PoolInit {
+                        lp_pool: lp_pool_1.to_string(),
+                        rec_token: Denom::Cw20(rec_token_1.clone()),
+                        weight: TOTAL_WEIGHT / 2,
+                        input_token: None,
+                    },
+                    PoolInit {
+                        lp_pool: lp_pool_2.to_string(),
+                        rec_token: Denom::Cw20(rec_token_2.clone()),
+                        weight: TOTAL_WEIGHT / 2,
+                        input_token: None,
+                    },
+                ],
+                on_dead_pool,
+                max_submessages: None,
+                auto_pause_dead_pools,
+                default_slippage_bps: None,
+                keeper: None,
+                oracle_addr: None,
+                twap_window: None,
+                max_price_deviation_bps: None,
+                reserve_bps: 0,
+                protocol_fee_bps: 0,
+                treasury: None,
+            },
+            &[],
+            "index-vault",
+            None,
+        )
+        .unwrap();
+
+    transfer(&mut router, &owner, &base, &user, Uint128::new(10_000));
+
+    IndexSuite {
+        router,
+        owner,
+        user,
+        base,
+        rec_token_1,
+        rec_token_2,
+        vault,
+    }
+}
+
+fn deposit(suite: &mut IndexSuite, amount: Uint128) {
+    deposit_with_floors(suite, amount, None, None, None).unwrap();
+}
+
+fn deposit_with_floors(
+    suite: &mut IndexSuite,
+    amount: Uint128,
+    min_shares_out: Option<Uint128>,
+    min_deployed_value: Option<Uint128>,
+    slippage_bps: Option<u16>,
+) -> Result<(), ContractError> {
+    let IndexSuite {
+        router,
+        user,
+        base,
+        vault,
+        ..
+    } = suite;
+    increase_allowance(router, user, base, vault, amount);
+    router
+        .execute_contract(
+            user.clone(),
+            vault.clone(),
+            &ExecuteMsg::Deposit {
+                amount,
+                min_shares_out,
+                min_deployed_value,
+                slippage_bps,
+                deadline: None,
+            },
+            &[],
+        )
+        .map(|_| ())
+        .map_err(|err| err.downcast().unwrap())
+}
+
+/// Overwrites the vault's share ledger through the test-only `SeedState` message.
+fn seed_shares(suite: &mut IndexSuite, total_supply: Uint128, balances: &[(&Addr, Uint128)]) {
+    let balances = balances
+        .iter()
+        .map(|(address, balance)| (address.to_string(), *balance))
+        .collect();
+    suite
+        .router
+        .execute_contract(
+            suite.owner.clone(),
+            suite.vault.clone(),
+            &ExecuteMsg::SeedState {
+                total_supply,
+                balances,
+            },
+            &[],
+        )
+        .unwrap();
+}
+
+/// Hands the vault received tokens straight from the owner, as if swapped in or accrued.
+fn seed_rec_balance(suite: &mut IndexSuite, token: &Addr, amount: Uint128) {
+    let IndexSuite {
+        router,
+        owner,
+        vault,
+        ..
+    } = suite;
+    transfer(router, owner, token, vault, amount);
+}
+
+fn share_balance(router: &App, vault: &Addr, address: &Addr) -> Uint128 {
+    let resp: crate::msg::BalanceResponse = router
+        .wrap()
+        .query_wasm_smart(
+            vault,
+            &QueryMsg::GetBalanceOf {
+                address: address.clone(),
+            },
+        )
+        .unwrap();
+    resp.balance
+}
+
+fn total_supply(router: &App, vault: &Addr) -> Uint128 {
+    let resp: TotalSupplyResponse = router
+        .wrap()
+        .query_wasm_smart(vault, &QueryMsg::GetTotalSupply {})
+        .unwrap();
+    resp.total_supply
+}
+
+/// What the vault's holdings are worth in the base token, priced through each pool.
+fn vault_value(suite: &IndexSuite, pools: &[(&Addr, &Addr)]) -> Uint128 {
+    let mut value = cw20_balance(&suite.router, &suite.base, &suite.vault);
+    for (pool, rec_token) in pools {
+        let rec_bal = cw20_balance(&suite.router, rec_token, &suite.vault);
+        if rec_bal.is_zero() {
+            continue;
+        }
+        let price: wasmswap::msg::Token2ForToken1PriceResponse = suite
+            .router
+            .wrap()
+            .query_wasm_smart(
+                *pool,
+                &wasmswap::msg::QueryMsg::Token2ForToken1Price {
+                    token2_amount: rec_bal,
+                },
+            )
+            .unwrap();
+        value += price.token1_amount;
+    }
+    value
+}
+
+#[test]
+fn repeated_deposits_accumulate_shares() {
+    let mut suite = setup_index();
+    let user = suite.user.clone();
+    let vault = suite.vault.clone();
+    let (rec_token_1, rec_token_2) = (suite.rec_token_1.clone(), suite.rec_token_2.clone());
+    let amount = Uint128::new(1_000);
+
+    // the first deposit mints shares 1:1
+    deposit(&mut suite, amount);
+    let first = share_balance(&suite.router, &vault, &user);
+    assert_eq!(first, amount);
+
+    // yield accrues to the vault's positions
+    seed_rec_balance(&mut suite, &rec_token_1, Uint128::new(500));
+    seed_rec_balance(&mut suite, &rec_token_2, Uint128::new(500));
+
+    let swapvar = crate::state::SWAPVAR
+        .query(&suite.router.wrap(), vault.clone())
+        .unwrap();
+    let pools: Vec<(&Addr, &Addr)> = swapvar
+        .pools
+        .iter()
+        .filter_map(|pool| match &pool.rec_token {
+            Denom::Cw20(rec_token) => Some((&pool.lp_pool, rec_token)),
+            Denom::Native(_) => None,
+        })
+        .collect();
+    let value = vault_value(&suite, &pools);
+    assert!(value > amount);
+
+    // the same amount now buys fewer shares, priced at the vault's current value
+    deposit(&mut suite, amount);
+    let second = share_balance(&suite.router, &vault, &user) - first;
+    assert!(second < first);
+    assert_eq!(second, amount.multiply_ratio(first, value));
+
+    let supply = total_supply(&suite.router, &vault);
+    assert_eq!(supply, first + second);
+    assert_eq!(share_balance(&suite.router, &vault, &user), supply);
+}
+
+#[test]
+fn deposit_and_withdraw_round_trip() {
+    let mut suite = setup_index();
+    let user = suite.user.clone();
+    let vault = suite.vault.clone();
+    let amount = Uint128::new(1_000);
+
+    let swapvar = crate::state::SWAPVAR
+        .query(&suite.router.wrap(), vault.clone())
+        .unwrap();
+    let quote = |router: &App, pool: &Addr| -> Uint128 {
+        let price: wasmswap::msg::Token1ForToken2PriceResponse = router
+            .wrap()
+            .query_wasm_smart(
+                pool,
+                &wasmswap::msg::QueryMsg::Token1ForToken2Price {
+                    token1_amount: amount.multiply_ratio(1u128, 2u128),
+                },
+            )
+            .unwrap();
+        price.token2_amount
+    };
+    let expected_1 = quote(&suite.router, &swapvar.pools[0].lp_pool);
+    let expected_2 = quote(&suite.router, &swapvar.pools[1].lp_pool);
+    let user_before = cw20_balance(&suite.router, &suite.base, &user);
+
+    // allowance -> transfer -> swap: each pool gets half the base, bought at its quote
+    deposit(&mut suite, amount);
+    assert_eq!(
+        cw20_balance(&suite.router, &suite.rec_token_1, &vault),
+        expected_1
+    );
+    assert_eq!(
+        cw20_balance(&suite.router, &suite.rec_token_2, &vault),
+        expected_2
+    );
+    assert!(cw20_balance(&suite.router, &suite.base, &vault).is_zero());
+    assert_eq!(share_balance(&suite.router, &vault, &user), amount);
+    assert_eq!(total_supply(&suite.router, &vault), amount);
+    assert_eq!(
+        user_before - cw20_balance(&suite.router, &suite.base, &user),
+        amount
+    );
+
+    let (rec_token_1, rec_token_2) = (suite.rec_token_1.clone(), suite.rec_token_2.clone());
+    let pools = [
+        (&swapvar.pools[0].lp_pool, &rec_token_1),
+        (&swapvar.pools[1].lp_pool, &rec_token_2),
+    ];
+    let value = vault_value(&suite, &pools);
+
+    // the sole holder withdraws everything and is paid what the positions were worth
+    suite
+        .router
+        .execute_contract(
+            user.clone(),
+            vault.clone(),
+            &ExecuteMsg::Withdraw {
+                share: amount,
+                deadline: None,
+                min_outputs: None,
+                slippage_bps: None,
+            },
+            &[],
+        )
+        .unwrap();
+    assert!(cw20_balance(&suite.router, &rec_token_1, &vault).is_zero());
+    assert!(cw20_balance(&suite.router, &rec_token_2, &vault).is_zero());
+    assert!(cw20_balance(&suite.router, &suite.base, &vault).is_zero());
+    assert_eq!(
+        cw20_balance(&suite.router, &suite.base, &user),
+        user_before - amount + value
+    );
+    assert!(share_balance(&suite.router, &vault, &user).is_zero());
+    assert!(total_supply(&suite.router, &vault).is_zero());
+}
+
+#[test]
+fn deposit_floors_trip_independently() {
+    let mut suite = setup_index();
+    let user = suite.user.clone();
+    let vault = suite.vault.clone();
+    let amount = Uint128::new(1_000);
+
+    // the share floor is checked up front, against the vault's current share price
+    let err = deposit_with_floors(
+        &mut suite,
+        amount,
+        Some(amount + Uint128::one()),
+        None,
+        None,
+    )
+    .unwrap_err();
+    assert!(matches!(
+        err,
+        ContractError::SharesBelowMinimum { shares, min }
+            if shares == amount && min == amount + Uint128::one()
+    ));
+
+    // the share floor holds, but pool fees and price impact leave less than 1:1 deployed
+    let err =
+        deposit_with_floors(&mut suite, amount, Some(amount), Some(amount), None).unwrap_err();
+    assert!(matches!(
+        err,
+        ContractError::DeployedValueBelowMinimum { value, min }
+            if value < amount && min == amount
+    ));
+    assert!(total_supply(&suite.router, &vault).is_zero());
+
+    // a floor that leaves room for the pools' fees and price impact goes through
+    deposit_with_floors(
+        &mut suite,
+        amount,
+        Some(amount),
+        Some(Uint128::new(980)),
+        None,
+    )
+    .unwrap();
+    assert_eq!(share_balance(&suite.router, &vault, &user), amount);
+}
+
+#[test]
+fn seeded_multi_holder_withdraw() {
+    let mut suite = setup_index();
+    let other = Addr::unchecked("other");
+    let user = suite.user.clone();
+    let vault = suite.vault.clone();
+
+    seed_shares(
+        &mut suite,
+        Uint128::new(1_000),
+        &[(&user, Uint128::new(600)), (&other, Uint128::new(400))],
+    );
+    let (rec_token_1, rec_token_2) = (suite.rec_token_1.clone(), suite.rec_token_2.clone());
+    seed_rec_balance(&mut suite, &rec_token_1, Uint128::new(500));
+    seed_rec_balance(&mut suite, &rec_token_2, Uint128::new(500));
+
+    assert_eq!(total_supply(&suite.router, &vault), Uint128::new(1_000));
+    assert_eq!(
+        share_balance(&suite.router, &vault, &user),
+        Uint128::new(600)
+    );
+    assert_eq!(
+        share_balance(&suite.router, &vault, &other),
+        Uint128::new(400)
+    );
+
+    let swapvar = crate::state::SWAPVAR
+        .query(&suite.router.wrap(), vault.clone())
+        .unwrap();
+    let value = vault_value(
+        &suite,
+        &[
+            (&swapvar.pools[0].lp_pool, &rec_token_1),
+            (&swapvar.pools[1].lp_pool, &rec_token_2),
+        ],
+    );
+    let user_before = cw20_balance(&suite.router, &suite.base, &user);
+    suite
+        .router
+        .execute_contract(
+            user.clone(),
+            vault.clone(),
+            &ExecuteMsg::Withdraw {
+                share: Uint128::new(600),
+                deadline: None,
+                min_outputs: None,
+                slippage_bps: None,
+            },
+            &[],
+        )
+        .unwrap();
+
+    // only the user's 60% of each position is sold; the rest stays for the other holder
+    assert_eq!(
+        cw20_balance(&suite.router, &rec_token_1, &vault),
+        Uint128::new(200)
+    );
+    assert_eq!(
+        cw20_balance(&suite.router, &rec_token_2, &vault),
+        Uint128::new(200)
+    );
+    let paid = cw20_balance(&suite.router, &suite.base, &user) - user_before;
+    assert_eq!(paid, value.multiply_ratio(600u128, 1_000u128));
+
+    assert_eq!(total_supply(&suite.router, &vault), Uint128::new(400));
+    assert!(share_balance(&suite.router, &vault, &user).is_zero());
+    assert_eq!(
+        share_balance(&suite.router, &vault, &other),
+        Uint128::new(400)
+    );
+}
+
+#[test]
+fn dead_pool_reverts_withdraw() {
+    let mut suite = setup_index_with(DeadPoolPolicy::Revert, Uint128::zero(), false);
+    let user = suite.user.clone();
+    seed_shares(
+        &mut suite,
+        Uint128::new(1_000),
+        &[(&user, Uint128::new(1_000))],
+    );
+    let rec_token_1 = suite.rec_token_1.clone();
+    seed_rec_balance(&mut suite, &rec_token_1, Uint128::new(500));
+
+    let err: ContractError = suite
+        .router
+        .execute_contract(
+            user.clone(),
+            suite.vault.clone(),
+            &ExecuteMsg::Withdraw {
+                share: Uint128::new(600),
+                deadline: None,
+                min_outputs: None,
+                slippage_bps: None,
+            },
+            &[],
+        )
+        .unwrap_err()
+        .downcast()
+        .unwrap();
+    assert!(matches!(err, ContractError::DeadPool { .. }));
+    assert_eq!(
+        share_balance(&suite.router, &suite.vault, &user),
+        Uint128::new(1_000)
+    );
+}
+
+#[test]
+fn dead_pool_is_skipped_and_paid_in_kind() {
+    let mut suite = setup_index_with(DeadPoolPolicy::Skip, Uint128::zero(), false);
+    let user = suite.user.clone();
+    let vault = suite.vault.clone();
+    seed_shares(
+        &mut suite,
+        Uint128::new(1_000),
+        &[(&user, Uint128::new(1_000))],
+    );
+    let (rec_token_1, rec_token_2) = (suite.rec_token_1.clone(), suite.rec_token_2.clone());
+    seed_rec_balance(&mut suite, &rec_token_1, Uint128::new(500));
+    seed_rec_balance(&mut suite, &rec_token_2, Uint128::new(500));
+
+    let swapvar = crate::state::SWAPVAR
+        .query(&suite.router.wrap(), vault.clone())
+        .unwrap();
+    let healthy_value = vault_value(&suite, &[(&swapvar.pools[0].lp_pool, &rec_token_1)]);
+    let user_before = cw20_balance(&suite.router, &suite.base, &user);
+    suite
+        .router
+        .execute_contract(
+            user.clone(),
+            vault.clone(),
+            &ExecuteMsg::Withdraw {
+                share: Uint128::new(600),
+                deadline: None,
+                min_outputs: None,
+                slippage_bps: None,
+            },
+            &[],
+        )
+        .unwrap();
+
+    // the user's 60% of the healthy pool is sold and paid out in base
+    assert_eq!(
+        cw20_balance(&suite.router, &rec_token_1, &vault),
+        Uint128::new(200)
+    );
+    let paid = cw20_balance(&suite.router, &suite.base, &user) - user_before;
+    assert!(!paid.is_zero());
+    assert_eq!(paid, healthy_value.multiply_ratio(600u128, 1_000u128));
+
+    // the dead pool's token is never swapped; the user takes 60% of it as is
+    assert_eq!(
+        cw20_balance(&suite.router, &rec_token_2, &user),
+        Uint128::new(300)
+    );
+    assert_eq!(
+        cw20_balance(&suite.router, &rec_token_2, &vault),
+        Uint128::new(200)
+    );
+    assert_eq!(total_supply(&suite.router, &vault), Uint128::new(400));
+}
+
+#[test]
+fn failed_deposit_swap_refunds_depositor() {
+    let mut suite = setup_index_with(DeadPoolPolicy::Revert, Uint128::zero(), false);
+    let user = suite.user.clone();
+    let vault = suite.vault.clone();
+    let user_before = cw20_balance(&suite.router, &suite.base, &user);
+
+    // an empty pool cannot quote a minimum output, so accept any and let the swap itself fail
+    deposit_with_floors(&mut suite, Uint128::new(1_000), None, None, Some(10_000)).unwrap();
+
+    // the half routed into the empty pool comes straight back and mints nothing
+    assert_eq!(
+        user_before - cw20_balance(&suite.router, &suite.base, &user),
+        Uint128::new(500)
+    );
+    assert_eq!(
+        share_balance(&suite.router, &vault, &user),
+        Uint128::new(500)
+    );
+    assert_eq!(total_supply(&suite.router, &vault), Uint128::new(500));
+    assert!(cw20_balance(&suite.router, &suite.base, &vault).is_zero());
+    assert!(!cw20_balance(&suite.router, &suite.rec_token_1, &vault).is_zero());
+}
+
+/// Has the owner pull every unit of liquidity out of `pool`.
+fn drain_pool(suite: &mut IndexSuite, pool: &Addr) {
+    let info: wasmswap::msg::InfoResponse = suite
+        .router
+        .wrap()
+        .query_wasm_smart(pool, &wasmswap::msg::QueryMsg::Info {})
+        .unwrap();
+    let lp_token = Addr::unchecked(info.lp_token_address);
+    let owner = suite.owner.clone();
+    let amount = cw20_balance(&suite.router, &lp_token, &owner);
+    increase_allowance(&mut suite.router, &owner, &lp_token, pool, amount);
+    suite
+        .router
+        .execute_contract(
+            owner,
+            pool.clone(),
+            &wasmswap::msg::ExecuteMsg::RemoveLiquidity {
+                amount,
+                min_token1: Uint128::zero(),
+                min_token2: Uint128::zero(),
+                expiration: None,
+            },
+            &[],
+        )
+        .unwrap();
+}
+
+#[test]
+fn dead_pool_is_paused_automatically() {
+    let mut suite = setup_index_with(DeadPoolPolicy::Skip, Uint128::new(100_000), true);
+    let user = suite.user.clone();
+    let vault = suite.vault.clone();
+    let (rec_token_1, rec_token_2) = (suite.rec_token_1.clone(), suite.rec_token_2.clone());
+    deposit(&mut suite, Uint128::new(1_000));
+    let held_2 = cw20_balance(&suite.router, &rec_token_2, &vault);
+    assert!(!held_2.is_zero());
+
+    let pool_2 = crate::state::SWAPVAR
+        .query(&suite.router.wrap(), vault.clone())
+        .unwrap()
+        .pools[1]
+        .lp_pool
+        .clone();
+    drain_pool(&mut suite, &pool_2);
+
+    // the next deposit finds the pool empty, pauses it and buys only into the healthy one
+    let held_1 = cw20_balance(&suite.router, &rec_token_1, &vault);
+    let amount = Uint128::new(1_000);
+    increase_allowance(&mut suite.router, &user, &suite.base, &vault, amount);
+    let res = suite
+        .router
+        .execute_contract(
+            user.clone(),
+            vault.clone(),
+            &ExecuteMsg::Deposit {
+                amount,
+                min_shares_out: None,
+                min_deployed_value: None,
+                slippage_bps: None,
+                deadline: None,
+            },
+            &[],
+        )
+        .unwrap();
+    assert!(res
+        .events
+        .iter()
+        .any(|event| event.ty == "wasm-pool_paused"));
+    let swapvar = crate::state::SWAPVAR
+        .query(&suite.router.wrap(), vault.clone())
+        .unwrap();
+    assert!(!swapvar.pools[0].paused);
+    assert!(swapvar.pools[1].paused);
+    assert!(cw20_balance(&suite.router, &rec_token_1, &vault) > held_1);
+    assert_eq!(cw20_balance(&suite.router, &rec_token_2, &vault), held_2);
+    assert!(cw20_balance(&suite.router, &suite.base, &vault).is_zero());
+
+    // withdrawing still works: the healthy pool unwinds and the stuck token is paid in kind
+    let share = share_balance(&suite.router, &vault, &user);
+    suite
+        .router
+        .execute_contract(
+            user.clone(),
+            vault.clone(),
+            &ExecuteMsg::Withdraw {
+                share,
+                deadline: None,
+                min_outputs: None,
+                slippage_bps: None,
+            },
+            &[],
+        )
+        .unwrap();
+    assert!(cw20_balance(&suite.router, &rec_token_1, &vault).is_zero());
+    assert!(cw20_balance(&suite.router, &rec_token_2, &vault).is_zero());
+    assert_eq!(cw20_balance(&suite.router, &rec_token_2, &user), held_2);
+
+    // only the owner lifts the pause
+    let err: ContractError = suite
+        .router
+        .execute_contract(
+            user,
+            vault.clone(),
+            &ExecuteMsg::ClearPoolPause { index: 1 },
+            &[],
+        )
+        .unwrap_err()
+        .downcast()
+        .unwrap();
+    assert!(matches!(err, ContractError::Unauthorized {}));
+    suite
+        .router
+        .execute_contract(
+            suite.owner.clone(),
+            vault.clone(),
+            &ExecuteMsg::ClearPoolPause { index: 1 },
+            &[],
+        )
+        .unwrap();
+    let swapvar = crate::state::SWAPVAR
+        .query(&suite.router.wrap(), vault)
+        .unwrap();
+    assert!(!swapvar.pools[1].paused);
+}
+
+#[test]
+fn seed_state_is_owner_only() {
+    let mut suite = setup_index();
+    let err: ContractError = suite
+        .router
+        .execute_contract(
+            suite.user.clone(),
+            suite.vault.clone(),
+            &ExecuteMsg::SeedState {
+                total_supply: Uint128::new(1),
+                balances: vec![],
+            },
+            &[],
+        )
+        .unwrap_err()
+        .downcast()
+        .unwrap();
+    assert!(matches!(err, ContractError::Unauthorized {}));
+}
+
+#[test]
+fn instantiate_initializes_every_item() {
+    let suite = setup_index();
+    let wrap = suite.router.wrap();
+
+    let version = cw2::CONTRACT.query(&wrap, suite.vault.clone()).unwrap();
+    assert_eq!(version.version, env!("CARGO_PKG_VERSION"));
+    assert_eq!(vault_config(&suite.router, &suite.vault).owner, suite.owner);
+    let swapvar = crate::state::SWAPVAR
+        .query(&wrap, suite.vault.clone())
+        .unwrap();
+    assert_eq!(swapvar.pools.len(), 2);
+    assert!(crate::state::TOTAL_SUPPLY
+        .query(&wrap, suite.vault.clone())
+        .unwrap()
+        .is_zero());
+    assert!(total_supply(&suite.router, &suite.vault).is_zero());
+}
+
+#[test]
+fn retokenize_full_sequence() {
+    let mut suite = setup_index();
+    deposit(&mut suite, Uint128::new(1_000));
+
+    let vault = suite.vault.clone();
+    assert!(!cw20_balance(&suite.router, &suite.rec_token_1, &vault).is_zero());
+    assert!(!cw20_balance(&suite.router, &suite.rec_token_2, &vault).is_zero());
+    assert!(cw20_balance(&suite.router, &suite.base, &vault).is_zero());
+
+    let owner = suite.owner.clone();
+    let supply = Uint128::new(1_000_000);
+    let new_token = create_cw20(&mut suite.router, &owner, "NEWT", supply);
+    let base = suite.base.clone();
+    let pool = create_amm(
+        &mut suite.router,
+        &owner,
+        &base,
+        &new_token,
+        Uint128::new(100_000),
+        Uint128::new(100_000),
+    );
+
+    // completing before beginning is rejected
+    let err: ContractError = suite
+        .router
+        .execute_contract(
+            owner.clone(),
+            vault.clone(),
+            &ExecuteMsg::CompleteRetokenize {},
+            &[],
+        )
+        .unwrap_err()
+        .downcast()
+        .unwrap();
+    assert!(matches!(err, ContractError::NoRetokenizeInProgress {}));
+
+    suite
+        .router
+        .execute_contract(
+            owner.clone(),
+            vault.clone(),
+            &ExecuteMsg::BeginRetokenize {
+                new_token: new_token.to_string(),
+                pool: pool.to_string(),
+            },
+            &[],
+        )
+        .unwrap();
+
+    // every position is unwound back to the base token
+    assert!(cw20_balance(&suite.router, &suite.rec_token_1, &vault).is_zero());
+    assert!(cw20_balance(&suite.router, &suite.rec_token_2, &vault).is_zero());
+    let unwound = cw20_balance(&suite.router, &base, &vault);
+    assert!(!unwound.is_zero());
+
+    // deposits are halted until the retokenize completes
+    let user = suite.user.clone();
+    increase_allowance(&mut suite.router, &user, &base, &vault, Uint128::new(100));
+    let err: ContractError = suite
+        .router
+        .execute_contract(
+            user,
+            vault.clone(),
+            &ExecuteMsg::Deposit {
+                amount: Uint128::new(100),
+                min_shares_out: None,
+                min_deployed_value: None,
+                slippage_bps: None,
+                deadline: None,
+            },
+            &[],
+        )
+        .unwrap_err()
+        .downcast()
+        .unwrap();
+    assert!(matches!(err, ContractError::RetokenizeInProgress {}));
+
+    // the index pools still trade the old base, so they must be moved first
+    let complete = |router: &mut App| -> Result<(), ContractError> {
+        router
+            .execute_contract(
+                owner.clone(),
+                vault.clone(),
+                &ExecuteMsg::CompleteRetokenize {},
+                &[],
+            )
+            .map(|_| ())
+            .map_err(|err| err.downcast().unwrap())
+    };
+    let err = complete(&mut suite.router).unwrap_err();
+    assert!(matches!(err, ContractError::PoolDenomMismatch { .. }));
+    for (index, rec_token) in [
+        (0, suite.rec_token_1.clone()),
+        (1, suite.rec_token_2.clone()),
+    ] {
+        let lp_pool = create_amm(
+            &mut suite.router,
+            &owner,
+            &new_token,
+            &rec_token,
+            Uint128::new(100_000),
+            Uint128::new(100_000),
+        );
+        suite
+            .router
+            .execute_contract(
+                owner.clone(),
+                vault.clone(),
+                &ExecuteMsg::UpdateSwapConfig {
+                    index,
+                    lp_pool: Some(lp_pool.to_string()),
+                    rec_token: None,
+                },
+                &[],
+            )
+            .unwrap();
+    }
+    complete(&mut suite.router).unwrap();
+
+    assert!(cw20_balance(&suite.router, &base, &vault).is_zero());
+    let swapped = cw20_balance(&suite.router, &new_token, &vault);
+    assert!(!swapped.is_zero());
+    assert_eq!(vault_config(&suite.router, &vault).token, new_token);
+
+    // the vault takes and pays out the new token through the repointed pools
+    let user = suite.user.clone();
+    let amount = Uint128::new(1_000);
+    transfer(&mut suite.router, &owner, &new_token, &user, amount);
+    increase_allowance(&mut suite.router, &user, &new_token, &vault, amount);
+    let shares_before = share_balance(&suite.router, &vault, &user);
+    suite
+        .router
+        .execute_contract(
+            user.clone(),
+            vault.clone(),
+            &ExecuteMsg::Deposit {
+                amount,
+                min_shares_out: None,
+                min_deployed_value: None,
+                slippage_bps: None,
+                deadline: None,
+            },
+            &[],
+        )
+        .unwrap();
+    assert!(cw20_balance(&suite.router, &new_token, &user).is_zero());
+    assert!(!cw20_balance(&suite.router, &suite.rec_token_1, &vault).is_zero());
+    assert!(!cw20_balance(&suite.router, &suite.rec_token_2, &vault).is_zero());
+    let shares = share_balance(&suite.router, &vault, &user);
+    assert!(shares > shares_before);
+
+    suite
+        .router
+        .execute_contract(
+            user.clone(),
+            vault.clone(),
+            &ExecuteMsg::Withdraw {
+                share: shares,
+                deadline: None,
+                min_outputs: None,
+                slippage_bps: None,
+            },
+            &[],
+        )
+        .unwrap();
+    assert!(total_supply(&suite.router, &vault).is_zero());
+    assert!(cw20_balance(&suite.router, &new_token, &user) > swapped);
+    assert!(cw20_balance(&suite.router, &base, &vault).is_zero());
+}
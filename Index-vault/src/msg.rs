@@ -1,5 +1,10 @@
 use cosmwasm_schema::{cw_serde, QueryResponses};
-use cosmwasm_std::{Addr, Uint128};
+use cosmwasm_std::{Addr, Decimal, Uint128};
+use cw2::ContractVersion;
+use cw20::{AllowanceResponse, Denom, Expiration};
+use wasmswap::msg::TokenSelect;
+
+use crate::state::{Config, DeadPoolPolicy, Swapvar};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
@@ -8,27 +13,286 @@ use serde::{Deserialize, Serialize};
 pub struct InstantiateMsg {
     pub owner_addr: String,
     pub token_addr: String,
-    pub lp_pool_1: String,
-    pub lp_pool_2: String,
-    pub rec_token1: String,
-    pub rec_token2: String,
+    /// The index constituents, in pool order. Weights must sum to 10000.
+    pub pools: Vec<PoolInit>,
+    /// Withdraw behavior when a pool has no liquidity. Defaults to `revert`.
+    #[serde(default)]
+    pub on_dead_pool: DeadPoolPolicy,
+    /// Cap on the messages one operation may emit. Defaults to 31; an index that needs more
+    /// should be split across vaults.
+    pub max_submessages: Option<u32>,
+    /// Pause pools found without liquidity on deposit or withdraw instead of failing on them.
+    #[serde(default)]
+    pub auto_pause_dead_pools: bool,
+    /// Slippage allowed on deposit swaps when a deposit gives none, in basis points (max
+    /// 10000). Defaults to 100.
+    pub default_slippage_bps: Option<u16>,
+    /// An address allowed to harvest alongside the owner.
+    #[serde(default)]
+    pub keeper: Option<String>,
+    /// Contract answering `OracleQueryMsg::Price`. Held tokens are valued at its prices
+    /// instead of the pools' spot quotes; swaps are still quoted by the pools.
+    #[serde(default)]
+    pub oracle_addr: Option<String>,
+    /// Without an oracle, value held tokens at the average of the pools' prices over this
+    /// many of the latest deposits and withdrawals, one observation per block. Spot quotes
+    /// are used when unset.
+    #[serde(default)]
+    pub twap_window: Option<u32>,
+    /// How far, in basis points, a pool's price may move from the last one a withdraw
+    /// accepted before withdrawals stop until the owner runs `ResetPrices`. Unchecked when
+    /// unset.
+    #[serde(default)]
+    pub max_price_deviation_bps: Option<u16>,
+    /// Part of each deposit, in basis points, kept as the base token instead of swapped.
+    /// Withdrawals are paid from it first and only sell the pools for the rest.
+    #[serde(default)]
+    pub reserve_bps: u16,
+    /// Part of each harvest, in basis points, transferred to `treasury` before the rest is
+    /// compounded. A nonzero fee needs a treasury.
+    #[serde(default)]
+    pub protocol_fee_bps: u16,
+    #[serde(default)]
+    pub treasury: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct PoolInit {
+    pub lp_pool: String,
+    /// The other token of `lp_pool`, either `{"cw20": "<addr>"}` or `{"native": "<denom>"}`.
+    pub rec_token: Denom,
+    /// Share of each deposit swapped into this pool, out of 10000.
+    pub weight: u64,
+    /// The side of `lp_pool` the base token sits on; `Token1` when unset.
+    #[serde(default)]
+    pub input_token: Option<TokenSelect>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum ExecuteMsg {
-    Deposit { amount: Uint128 },
-    Withdraw { share: Uint128 },
+    /// `min_shares_out` bounds dilution from the share price; `min_deployed_value` bounds
+    /// swap slippage, as the base-token value of what the swaps bought. All floors are
+    /// optional.
+    Deposit {
+        amount: Uint128,
+        #[serde(default)]
+        min_shares_out: Option<Uint128>,
+        #[serde(default)]
+        min_deployed_value: Option<Uint128>,
+        /// Bounds each swap's output below the pool's quote, in basis points. Falls back to
+        /// the configured default.
+        #[serde(default)]
+        slippage_bps: Option<u16>,
+        /// Passed to every swap as its expiration; a deadline already past is rejected.
+        #[serde(default)]
+        deadline: Option<Expiration>,
+    },
+    Withdraw {
+        share: Uint128,
+        /// Passed to every swap as its expiration; a deadline already past is rejected.
+        #[serde(default)]
+        deadline: Option<Expiration>,
+        /// Least base token each pool's swap may return, one per pool in pool order. Paused
+        /// pools pay out in kind, so their entry is ignored.
+        #[serde(default)]
+        min_outputs: Option<Vec<Uint128>>,
+        /// Bounds each swap's output below the pool's quote, in basis points, when
+        /// `min_outputs` is not given. Falls back to the configured default.
+        #[serde(default)]
+        slippage_bps: Option<u16>,
+    },
+    /// Owner only. Unwinds every pool position back into the base token and halts deposits
+    /// and withdrawals until `CompleteRetokenize` runs. `new_token` must be a cw20 contract
+    /// and `pool` must pair it with the current base token, on either side.
+    BeginRetokenize { new_token: String, pool: String },
+    /// Owner only. Swaps the unwound base balance into the new token through the pool given
+    /// to `BeginRetokenize`, within the default slippage, and makes it the vault's token.
+    /// Every pool must hold none of its received token and must already be repointed with
+    /// `UpdateSwapConfig` to one trading the new token on its `input_token` side.
+    CompleteRetokenize {},
+    /// Owner only. Unwinds the pool at `index` back into the base token, drops it from the
+    /// index and spreads its weight over the remaining pools.
+    RemovePool { index: u32 },
+    /// Owner only. Points the pool at `index` at a new pool contract and/or received token;
+    /// fields left out keep their value. The received token can only change while the vault
+    /// holds none of the old one.
+    UpdateSwapConfig {
+        index: u32,
+        #[serde(default)]
+        lp_pool: Option<String>,
+        #[serde(default)]
+        rec_token: Option<Denom>,
+    },
+    /// Owner or keeper only. Moves value from pools above their weight into pools below it. Targets
+    /// are split with `allocate`, so a rounding tie always favors the lower-indexed pool and
+    /// a vault that is balanced up to that remainder emits no swaps.
+    Rebalance {},
+    /// Owner only. Lifts an automatic pause from the pool at `index`. A pool that is still
+    /// dead is paused again by the next deposit or withdraw.
+    ClearPoolPause { index: u32 },
+    /// Owner or keeper only. Swaps whatever each live pool's received token has grown past
+    /// its principal back into the base token and keeps it in the vault, so the gain goes to
+    /// every holder through the share price. The protocol fee's part of each gain is
+    /// transferred to the treasury first. No shares are minted.
+    Harvest {},
+    /// Owner only. Sets or, with no address, clears the keeper allowed to run `Harvest` and
+    /// `Rebalance`.
+    SetKeeper {
+        #[serde(default)]
+        address: Option<String>,
+    },
+    /// Owner only. Sets the slippage, in basis points, allowed on swaps whose caller gives
+    /// neither a slippage nor explicit minimum outputs.
+    SetSlippage { bps: u16 },
+    /// Owner only. Sets the part of each harvest, in basis points, sent to `treasury`. A
+    /// nonzero fee needs a treasury; with no address the current one is kept.
+    SetProtocolFee {
+        bps: u16,
+        #[serde(default)]
+        treasury: Option<String>,
+    },
+    /// Owner only. Accepts every live pool's current price as the reference withdrawals are
+    /// checked against, reopening them after a price deviation tripped.
+    ResetPrices {},
+    /// Owner only. Emergency path for when the pools can no longer swap: burns all of
+    /// `recipient`'s shares and transfers them their pro-rata cut of the idle base balance
+    /// and of every pool's received token in kind, attempting no swap.
+    ForceWithdrawRaw { recipient: String },
+    /// Test harness only, compiled out of production builds. Owner only. Overwrites
+    /// `TOTAL_SUPPLY` and the share balance of every listed address.
+    #[cfg(any(test, feature = "test-utils"))]
+    SeedState {
+        total_supply: Uint128,
+        balances: Vec<(String, Uint128)>,
+    },
 }
 
 #[cw_serde]
 #[derive(QueryResponses)]
 pub enum QueryMsg {
-    #[returns(Uint128)]
+    #[returns(TotalSupplyResponse)]
     GetTotalSupply {},
 
-    #[returns(Uint128)]
+    #[returns(BalanceResponse)]
     GetBalanceOf { address: Addr },
+
+    /// `address`'s shares and their cut of `TotalAssets`. Both are zero for an address
+    /// without a position.
+    #[returns(UserValueResponse)]
+    GetUserValue { address: Addr },
+
+    /// Everything the vault holds valued in the base token: the idle base balance plus
+    /// each pool's received tokens at that pool's current price.
+    #[returns(Uint128)]
+    TotalAssets {},
+
+    /// The stored configuration: owner, base token and pool handling settings.
+    #[returns(Config)]
+    GetConfig {},
+
+    /// Every pool with its received token, weight and pause flag, in pool order.
+    #[returns(Swapvar)]
+    GetSwapInfo {},
+
+    /// Each pool's received token as the vault holds it, next to what that pool would pay
+    /// for it right now, in pool order.
+    #[returns(HoldingsResponse)]
+    GetHoldings {},
+
+    /// Every pool with its target weight and what its holdings are worth now, in pool order.
+    #[returns(CompositionResponse)]
+    GetComposition {},
+
+    /// The base tokens `Withdraw` would pay for `share` right now, each live pool's holdings
+    /// valued at its current price. Dead pools are paid in kind and left out. Zero while no
+    /// shares exist.
+    #[returns(Uint128)]
+    SimulateWithdraw { share: Uint128 },
+
+    /// What each pool may still pull from the vault through the allowances its swaps were
+    /// granted, read from the tokens themselves, in pool order.
+    #[returns(PoolAllowancesResponse)]
+    PoolAllowances {},
+
+    /// The cw2 name and version of the build that instantiated the contract.
+    #[returns(ContractVersion)]
+    ContractInfo {},
+}
+
+#[cw_serde]
+pub struct TotalSupplyResponse {
+    pub total_supply: Uint128,
+}
+
+#[cw_serde]
+pub struct BalanceResponse {
+    pub balance: Uint128,
+}
+
+#[cw_serde]
+pub struct UserValueResponse {
+    pub shares: Uint128,
+    /// Base tokens the shares are worth, every pool's position valued as in `TotalAssets`.
+    pub value: Uint128,
+}
+
+#[cw_serde]
+pub struct HoldingsResponse {
+    pub holdings: Vec<Holding>,
+}
+
+#[cw_serde]
+pub struct Holding {
+    pub lp_pool: Addr,
+    pub rec_token: Denom,
+    /// Raw balance of `rec_token`.
+    pub balance: Uint128,
+    /// Base tokens the pool quotes for `balance`. Zero while the pool is paused, since a
+    /// paused pool may not be able to quote.
+    pub value: Uint128,
+}
+
+#[cw_serde]
+pub struct PoolAllowancesResponse {
+    pub allowances: Vec<PoolAllowance>,
+}
+
+#[cw_serde]
+pub struct PoolAllowance {
+    pub lp_pool: Addr,
+    /// The vault's base token allowance to `lp_pool`, granted for deposit swaps.
+    pub base_token: AllowanceResponse,
+    /// The received token allowance to `lp_pool`, granted for unwinds. `None` for a native
+    /// received token, which is sent along with the swap instead.
+    pub rec_token: Option<AllowanceResponse>,
+}
+
+#[cw_serde]
+pub struct CompositionResponse {
+    pub pools: Vec<PoolComposition>,
+}
+
+#[cw_serde]
+pub struct PoolComposition {
+    pub lp_pool: Addr,
+    pub rec_token: Denom,
+    /// Target share of each deposit, out of 10000.
+    pub weight: u64,
+    /// Base tokens the pool quotes for the vault's balance of `rec_token`, as in
+    /// `GetHoldings`.
+    pub current_value: Uint128,
+}
+
+/// Query interface the vault expects of its price oracle.
+#[cw_serde]
+#[derive(QueryResponses)]
+pub enum OracleQueryMsg {
+    /// Base tokens one unit of `denom` is worth. `denom` is a native denom or a cw20
+    /// address.
+    #[returns(Decimal)]
+    Price { denom: String },
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
@@ -1,24 +1,171 @@
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use cosmwasm_std::{Addr, Uint128};
+use cosmwasm_std::{Addr, Decimal, Uint128};
+use cw20::Denom;
 use cw_storage_plus::{Item, Map};
+use wasmswap::msg::TokenSelect;
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct Config {
     pub token: Addr,
     pub owner: Addr,
+    #[serde(default)]
+    pub on_dead_pool: DeadPoolPolicy,
+    /// Most messages a single execute may emit before it is rejected.
+    #[serde(default = "default_max_submessages")]
+    pub max_submessages: u32,
+    /// Probe every pool on deposit and pause the ones found without liquidity.
+    #[serde(default)]
+    pub auto_pause_dead_pools: bool,
+    /// Slippage allowed on deposit swaps when the depositor gives none, in basis points.
+    #[serde(default = "default_slippage_bps")]
+    pub default_slippage_bps: u16,
+    /// May run `Harvest` and `Rebalance` alongside the owner.
+    #[serde(default)]
+    pub keeper: Option<Addr>,
+    /// Prices received tokens for valuation instead of the pools' spot quotes, which a
+    /// single transaction can move.
+    #[serde(default)]
+    pub oracle: Option<Addr>,
+    /// Without an oracle, value received tokens at the average of this many of the latest
+    /// `PRICE_OBSERVATIONS` instead of the current spot quote.
+    #[serde(default)]
+    pub twap_window: Option<u32>,
+    /// Withdrawals are refused once a pool's price is this many basis points away from its
+    /// entry in `LAST_PRICES`.
+    #[serde(default)]
+    pub max_price_deviation_bps: Option<u16>,
+    /// Fraction of each deposit left unswapped in the base token, in basis points.
+    /// Withdrawals the idle base balance covers are paid from it without any swaps.
+    #[serde(default)]
+    pub reserve_bps: u16,
+    /// Cut of every harvest sent to `treasury` in the received token, in basis points.
+    #[serde(default)]
+    pub protocol_fee_bps: u16,
+    #[serde(default)]
+    pub treasury: Option<Addr>,
+}
+
+pub const MAX_SLIPPAGE_BPS: u16 = 10_000;
+
+pub const DEFAULT_SLIPPAGE_BPS: u16 = 100;
+
+fn default_slippage_bps() -> u16 {
+    DEFAULT_SLIPPAGE_BPS
+}
+
+/// Leaves room for a deposit or withdraw across 15 cw20 pools (an allowance and a swap
+/// each, plus the transfer) within a typical block gas limit.
+pub const DEFAULT_MAX_SUBMESSAGES: u32 = 31;
+
+fn default_max_submessages() -> u32 {
+    DEFAULT_MAX_SUBMESSAGES
+}
+
+/// What a withdraw does when one of the index pools has no liquidity to swap against.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DeadPoolPolicy {
+    /// Fail the withdraw.
+    #[default]
+    Revert,
+    /// Hand the withdrawer their pro-rata share of the dead pool's received token as is and
+    /// only swap the healthy pools.
+    Skip,
+}
+
+/// Pool weights are expressed out of `TOTAL_WEIGHT` and always sum to it.
+pub const TOTAL_WEIGHT: u64 = 10_000;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PoolEntry {
+    pub lp_pool: Addr,
+    /// The pool's other token from the base token, either a cw20 or a native denom.
+    pub rec_token: Denom,
+    pub weight: u64,
+    /// Set when the pool was found without liquidity. A paused pool takes no deposits and
+    /// is left out of valuation until the owner clears it.
+    #[serde(default)]
+    pub paused: bool,
+    /// Received token the vault bought into this pool itself. Anything held above it is
+    /// yield that `Harvest` folds back into the base token.
+    #[serde(default)]
+    pub principal: Uint128,
+    /// The side of the pool the base token sits on, which deposits sell.
+    #[serde(default = "base_token_side")]
+    pub input_token: TokenSelect,
+}
+
+fn base_token_side() -> TokenSelect {
+    TokenSelect::Token1
+}
+
+impl PoolEntry {
+    /// The side of the pool the received token sits on, which withdrawals sell.
+    pub fn output_token(&self) -> TokenSelect {
+        match self.input_token {
+            TokenSelect::Token1 => TokenSelect::Token2,
+            TokenSelect::Token2 => TokenSelect::Token1,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct Swapvar {
-    pub lp_pool_1: Addr,
-    pub rec_token_1: Addr,
-    pub lp_pool_2: Addr,
-    pub rec_token_2: Addr,
+    pub pools: Vec<PoolEntry>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Retokenize {
+    pub new_token: Addr,
+    pub pool: Addr,
+    /// The side of `pool` the old base token sits on, which `CompleteRetokenize` sells.
+    #[serde(default = "base_token_side")]
+    pub input_token: TokenSelect,
+}
+
+/// The latest deposit, kept so a swap that fails in its reply can be refunded.
+/// `amount` and `shares` shrink as failed swaps are unwound; the next deposit overwrites it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct PendingDeposit {
+    pub depositor: Addr,
+    pub amount: Uint128,
+    pub shares: Uint128,
+    /// Base token sent into each pool's swap, in pool order.
+    pub inputs: Vec<Uint128>,
+    /// Received-token balances before the swaps, in pool order.
+    pub rec_balances: Vec<Uint128>,
+    /// Floor on the base-token value the swaps must buy, checked once the last one settles.
+    pub min_deployed_value: Option<Uint128>,
+    /// Part of `amount` kept in the base token rather than sent into any swap.
+    #[serde(default)]
+    pub reserved: Uint128,
+}
+
+/// What the latest deposit and withdraw swaps actually bought, in pool order, as reported by
+/// each pool's `token_bought` attribute. A pool left out of the swap stays at zero.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, JsonSchema)]
+pub struct RealizedOutputs {
+    /// Received token bought by each deposit swap.
+    pub deposit: Vec<Uint128>,
+    /// Base token bought back by each withdraw swap.
+    pub withdraw: Vec<Uint128>,
 }
 
 pub const CONFIG: Item<Config> = Item::new("Config");
 pub const SWAPVAR: Item<Swapvar> = Item::new("swapvar");
 pub const TOTAL_SUPPLY: Item<Uint128> = Item::new("total_supply");
 pub const BALANCE_OF: Map<Addr, Uint128> = Map::new("balance_of");
+pub const RETOKENIZE: Item<Retokenize> = Item::new("retokenize");
+pub const PENDING_DEPOSIT: Item<PendingDeposit> = Item::new("pending_deposit");
+pub const REALIZED_OUTPUTS: Item<RealizedOutputs> = Item::new("realized_outputs");
+/// Held while a deposit or withdraw has swaps in flight, as the reply id of its last swap;
+/// that reply releases it. A nested deposit or withdraw in between is refused.
+pub const LOCK: Item<u64> = Item::new("lock");
+/// Each pool's reserve price, in base tokens per received token, as seen by deposits and
+/// withdrawals at each block height. Only the latest `twap_window` are kept per pool.
+pub const PRICE_OBSERVATIONS: Map<(&Addr, u64), Decimal> = Map::new("price_observations");
+/// Each pool's reserve price as last accepted by a withdraw or `ResetPrices`, the reference
+/// `max_price_deviation_bps` is measured from.
+pub const LAST_PRICES: Map<&Addr, Decimal> = Map::new("last_prices");
@@ -10,7 +10,10 @@ use cw20::{Cw20Coin, Cw20Contract, Cw20ExecuteMsg, Denom};
 use cw_multi_test::{App, Contract, ContractWrapper, Executor};
 use std::str::FromStr;
 
-use crate::msg::{ExecuteMsg, FeeResponse, InfoResponse, InstantiateMsg, QueryMsg, TokenSelect};
+use crate::msg::{
+    ExecuteMsg, FeeResponse, InfoResponse, InstantiateMsg, QueryMsg, Token1ForToken2PriceResponse,
+    TokenSelect,
+};
 
 fn mock_app() -> App {
     App::default()
@@ -507,6 +510,133 @@ fn amm_add_and_remove_liquidity() {
     assert_eq!(owner_balance, Uint128::new(5000));
 }
 
+#[test]
+fn cw20_pair_add_swap_and_remove() {
+    let mut router = mock_app();
+    let owner = Addr::unchecked("owner");
+    let buyer = Addr::unchecked("buyer");
+
+    let token1 = create_cw20(
+        &mut router,
+        &owner,
+        "token1".to_string(),
+        "TOKENA".to_string(),
+        Uint128::new(5000),
+    );
+    let token2 = create_cw20(
+        &mut router,
+        &owner,
+        "token2".to_string(),
+        "TOKENB".to_string(),
+        Uint128::new(5000),
+    );
+    let amm_addr = create_amm(
+        &mut router,
+        &owner,
+        Denom::Cw20(token1.addr()),
+        Denom::Cw20(token2.addr()),
+        Decimal::from_str("0.3").unwrap(),
+        Decimal::zero(),
+        owner.to_string(),
+    );
+    let allow = |router: &mut App, sender: &Addr, token: &Addr, amount: u128| {
+        let allowance_msg = Cw20ExecuteMsg::IncreaseAllowance {
+            spender: amm_addr.to_string(),
+            amount: Uint128::new(amount),
+            expires: None,
+        };
+        router
+            .execute_contract(sender.clone(), token.clone(), &allowance_msg, &[])
+            .unwrap();
+    };
+
+    // deposit both sides
+    allow(&mut router, &owner, &token1.addr(), 1000);
+    allow(&mut router, &owner, &token2.addr(), 1000);
+    let add_liquidity_msg = ExecuteMsg::AddLiquidity {
+        token1_amount: Uint128::new(1000),
+        min_liquidity: Uint128::new(1000),
+        max_token2: Uint128::new(1000),
+        expiration: None,
+    };
+    router
+        .execute_contract(owner.clone(), amm_addr.clone(), &add_liquidity_msg, &[])
+        .unwrap();
+    let info = get_info(&router, &amm_addr);
+    assert_eq!(info.token1_reserve, Uint128::new(1000));
+    assert_eq!(info.token2_reserve, Uint128::new(1000));
+    let lp_token = Cw20Contract(Addr::unchecked(info.lp_token_address));
+    assert_eq!(
+        lp_token.balance(&router, owner.clone()).unwrap(),
+        Uint128::new(1000)
+    );
+
+    // swap at the quoted price
+    token1
+        .call(Cw20ExecuteMsg::Transfer {
+            recipient: buyer.to_string(),
+            amount: Uint128::new(100),
+        })
+        .map(|msg| router.execute(owner.clone(), msg).unwrap())
+        .unwrap();
+    let quote: Token1ForToken2PriceResponse = router
+        .wrap()
+        .query_wasm_smart(
+            &amm_addr,
+            &QueryMsg::Token1ForToken2Price {
+                token1_amount: Uint128::new(100),
+            },
+        )
+        .unwrap();
+    // 100 in against 1000/1000 reserves, less the 0.3% fee
+    assert_eq!(quote.token2_amount, Uint128::new(90));
+    allow(&mut router, &buyer, &token1.addr(), 100);
+    let swap_msg = ExecuteMsg::Swap {
+        input_token: TokenSelect::Token1,
+        input_amount: Uint128::new(100),
+        min_output: quote.token2_amount,
+        expiration: None,
+    };
+    router
+        .execute_contract(buyer.clone(), amm_addr.clone(), &swap_msg, &[])
+        .unwrap();
+    assert_eq!(
+        token1.balance(&router, buyer.clone()).unwrap(),
+        Uint128::zero()
+    );
+    assert_eq!(
+        token2.balance(&router, buyer.clone()).unwrap(),
+        Uint128::new(90)
+    );
+    let info = get_info(&router, &amm_addr);
+    assert_eq!(info.token1_reserve, Uint128::new(1100));
+    assert_eq!(info.token2_reserve, Uint128::new(910));
+
+    // withdraw everything, swap proceeds included
+    allow(&mut router, &owner, &lp_token.addr(), 1000);
+    let remove_liquidity_msg = ExecuteMsg::RemoveLiquidity {
+        amount: Uint128::new(1000),
+        min_token1: Uint128::new(1100),
+        min_token2: Uint128::new(910),
+        expiration: None,
+    };
+    router
+        .execute_contract(owner.clone(), amm_addr.clone(), &remove_liquidity_msg, &[])
+        .unwrap();
+    let info = get_info(&router, &amm_addr);
+    assert_eq!(info.token1_reserve, Uint128::zero());
+    assert_eq!(info.token2_reserve, Uint128::zero());
+    assert_eq!(info.lp_token_supply, Uint128::zero());
+    assert_eq!(
+        token1.balance(&router, owner.clone()).unwrap(),
+        Uint128::new(5000)
+    );
+    assert_eq!(
+        token2.balance(&router, owner.clone()).unwrap(),
+        Uint128::new(4910)
+    );
+}
+
 #[test]
 fn migrate() {
     let mut router = mock_app();
@@ -0,0 +1,122 @@
+use cosmwasm_std::{
+    coins, to_json_binary, Addr, BankMsg, CosmosMsg, Deps, StdResult, Uint128, WasmMsg,
+};
+use cw20::{BalanceResponse, Cw20ExecuteMsg, Cw20QueryMsg, Denom};
+
+/// Balance queries and transfers for the vault's `AssetKind`, a cw20 token or a native denom,
+/// so callers never match on the two themselves.
+pub trait AssetInfo {
+    /// `address`'s balance of the asset, read from the bank module for a native denom.
+    fn query_balance(&self, deps: Deps, address: &Addr) -> StdResult<Uint128>;
+
+    /// Pays `amount` of the asset to `recipient`: a bank send or a cw20 `Transfer`.
+    fn transfer_msg(&self, recipient: &Addr, amount: Uint128) -> StdResult<CosmosMsg>;
+}
+
+impl AssetInfo for Denom {
+    fn query_balance(&self, deps: Deps, address: &Addr) -> StdResult<Uint128> {
+        match self {
+            Denom::Native(denom) => Ok(deps.querier.query_balance(address, denom)?.amount),
+            Denom::Cw20(token) => {
+                let resp: BalanceResponse = deps.querier.query_wasm_smart(
+                    token,
+                    &Cw20QueryMsg::Balance {
+                        address: address.to_string(),
+                    },
+                )?;
+                Ok(resp.balance)
+            }
+        }
+    }
+
+    fn transfer_msg(&self, recipient: &Addr, amount: Uint128) -> StdResult<CosmosMsg> {
+        match self {
+            Denom::Native(denom) => Ok(BankMsg::Send {
+                to_address: recipient.into(),
+                amount: coins(amount.u128(), denom),
+            }
+            .into()),
+            Denom::Cw20(token) => Ok(WasmMsg::Execute {
+                contract_addr: token.into(),
+                msg: to_json_binary(&Cw20ExecuteMsg::Transfer {
+                    recipient: recipient.into(),
+                    amount,
+                })?,
+                funds: vec![],
+            }
+            .into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AssetInfo;
+    use cosmwasm_std::testing::{mock_dependencies_with_balance, MOCK_CONTRACT_ADDR};
+    use cosmwasm_std::{
+        coin, coins, from_json, to_json_binary, Addr, BankMsg, ContractResult, CosmosMsg,
+        SystemResult, Uint128, WasmMsg, WasmQuery,
+    };
+    use cw20::{BalanceResponse, Cw20ExecuteMsg, Cw20QueryMsg, Denom};
+
+    #[test]
+    fn test_transfer_msg() {
+        let recipient = Addr::unchecked("recipient");
+        assert_eq!(
+            Denom::Native("uatom".to_string())
+                .transfer_msg(&recipient, Uint128::new(7))
+                .unwrap(),
+            CosmosMsg::Bank(BankMsg::Send {
+                to_address: "recipient".to_string(),
+                amount: coins(7, "uatom"),
+            })
+        );
+        assert_eq!(
+            Denom::Cw20(Addr::unchecked("token"))
+                .transfer_msg(&recipient, Uint128::new(7))
+                .unwrap(),
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: "token".to_string(),
+                msg: to_json_binary(&Cw20ExecuteMsg::Transfer {
+                    recipient: "recipient".to_string(),
+                    amount: Uint128::new(7),
+                })
+                .unwrap(),
+                funds: vec![],
+            })
+        );
+    }
+
+    #[test]
+    fn test_query_balance() {
+        let mut deps = mock_dependencies_with_balance(&[coin(5, "uatom")]);
+        deps.querier.update_wasm(|query| match query {
+            WasmQuery::Smart { contract_addr, msg } if contract_addr == "token" => {
+                let address = match from_json(msg).unwrap() {
+                    Cw20QueryMsg::Balance { address } => address,
+                    other => panic!("unexpected query {:?}", other),
+                };
+                assert_eq!(address, MOCK_CONTRACT_ADDR);
+                SystemResult::Ok(ContractResult::Ok(
+                    to_json_binary(&BalanceResponse {
+                        balance: Uint128::new(9),
+                    })
+                    .unwrap(),
+                ))
+            }
+            other => panic!("unexpected query {:?}", other),
+        });
+        let contract = Addr::unchecked(MOCK_CONTRACT_ADDR);
+
+        let native = Denom::Native("uatom".to_string());
+        assert_eq!(
+            native.query_balance(deps.as_ref(), &contract).unwrap(),
+            Uint128::new(5)
+        );
+        let cw20 = Denom::Cw20(Addr::unchecked("token"));
+        assert_eq!(
+            cw20.query_balance(deps.as_ref(), &contract).unwrap(),
+            Uint128::new(9)
+        );
+    }
+}
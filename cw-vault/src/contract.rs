@@ -1,44 +1,256 @@
 #[cfg(not(feature = "library"))]
 use cosmwasm_std::entry_point;
 use cosmwasm_std::{
-    to_json_binary, Addr, Binary, CosmosMsg, Deps, DepsMut, Env, MessageInfo, Response, StdError,
-    StdResult, Uint128, WasmMsg, WasmQuery,
+    from_json, to_json_binary, Addr, Api, Binary, CosmosMsg, Decimal, Deps, DepsMut, Env, Event,
+    MessageInfo, Order, Response, StdError, StdResult, Storage, Uint128, WasmMsg,
 };
-use cw2::set_contract_version;
+use cw2::{get_contract_version, set_contract_version};
+use cw_storage_plus::Bound;
+use semver::Version;
 
-use cw20::{Cw20ExecuteMsg, Denom, Expiration, MinterResponse};
-use cw20_base::contract::query_balance;
-use cw20_base::msg;
-use serde::de;
+use cw20::{AllowanceResponse, Cw20ExecuteMsg, Cw20QueryMsg, Cw20ReceiveMsg, TokenInfoResponse};
 
+use crate::asset::AssetInfo;
 use crate::error::ContractError;
-use crate::msg::{ExecuteMsg, InstantiateMsg, QueryMsg};
-use crate::state::{Config, BALANCE_OF, CONFIG, TOTAL_SUPPLY};
+use crate::msg::{
+    BalanceResponse, BreakEvenResponse, CanWithdrawResponse, CirculatingSupplyResponse,
+    DescribeResponse, ExecuteMsg, FeeDragResponse, InstantiateMsg, InvariantResponse, MigrateMsg,
+    PendingWithdrawal, PendingWithdrawalsResponse, QueryMsg, ReceiveMsg, StakedValueResponse,
+    StakingExecuteMsg, StakingQueryMsg, StakingReceiveMsg, StatsResponse, TotalSupplyResponse,
+    TwapSharePriceResponse, UserValueResponse, VaultInfoResponse,
+};
+use crate::state::{
+    AssetKind, Config, QueuedWithdrawal, ShareModel, BALANCE_OF, CONFIG, DEAD_SHARES,
+    DEAD_SHARE_AMOUNT, DEFAULT_MIGRATE_BATCH, DEPOSITED, DEPOSIT_ALLOWLIST, FEE_EXEMPT, FEE_SHARES,
+    HIGH_WATER_MARK, HOLDER_COUNT, MANAGED_ASSETS, MAX_BATCH_DEPOSITS, MAX_FEE_BPS,
+    MAX_METADATA_URI_LEN, NEXT_WITHDRAW_ID, PAUSED, PAUSE_ALLOWS_WITHDRAWALS, PENDING_OWNER,
+    QUEUED_ASSETS, REFERRALS, SHARE_PRICE_SNAPSHOTS, SNAPSHOTS, SNAPSHOT_BACKFILL_CURSOR,
+    TOTAL_PRINCIPAL, TOTAL_SUPPLY, WITHDRAW_QUEUE,
+};
 
 const CONTRACT_NAME: &str = "crates.io:cw-vault";
 const CONTRACT_VERSION: &str = env!("CARGO_PKG_VERSION");
 
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn instantiate(
-    deps: DepsMut,
-    _env: Env,
+    mut deps: DepsMut,
+    env: Env,
     info: MessageInfo,
     msg: InstantiateMsg,
 ) -> Result<Response, ContractError> {
     set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
     let owner = msg.owner_addr;
     let validate_owner = deps.api.addr_validate(&owner)?;
-    let token = msg.token_addr;
-    let validate_token = deps.api.addr_validate(&token)?;
+    let validate_token = validate_asset(deps.api, msg.token)?;
+    if let AssetKind::Cw20(token) = &validate_token {
+        check_token_contract(deps.as_ref(), &validate_owner, token)?;
+    }
+    let validate_staking = msg
+        .staking_addr
+        .map(|staking| deps.api.addr_validate(&staking))
+        .transpose()?;
+    if validate_staking.is_some() && matches!(validate_token, AssetKind::Native(_)) {
+        return Err(ContractError::Cw20Only {
+            feature: "LP staking".to_string(),
+        });
+    }
+    let validate_fee_recipient = msg
+        .fee_recipient
+        .map(|recipient| deps.api.addr_validate(&recipient))
+        .transpose()?;
+    for bps in [
+        msg.share_fee_bps,
+        msg.deposit_fee_bps,
+        msg.withdraw_fee_bps,
+        msg.performance_fee_bps,
+    ] {
+        if bps > MAX_FEE_BPS {
+            return Err(ContractError::InvalidFeeBps { bps });
+        }
+    }
+    let metadata_uri = validate_metadata_uri(msg.metadata_uri)?;
 
     let config = Config {
         token: validate_token,
         owner: validate_owner,
+        staking: validate_staking,
+        fee_recipient: validate_fee_recipient,
+        share_fee_bps: msg.share_fee_bps,
+        deposit_fee_bps: msg.deposit_fee_bps,
+        withdraw_fee_bps: msg.withdraw_fee_bps,
+        performance_fee_bps: msg.performance_fee_bps,
+        deposit_cap: msg.deposit_cap,
+        max_per_address: msg.max_per_address,
+        min_deposit: msg.min_deposit,
+        share_model: msg.share_model,
+        private_mode: msg.private_mode,
+        metadata_uri,
+        withdraw_delay: msg.withdraw_delay,
     };
 
     TOTAL_SUPPLY.save(deps.storage, &Uint128::zero())?;
+    HOLDER_COUNT.save(deps.storage, &0)?;
     CONFIG.save(deps.storage, &config)?;
-    Ok(Response::new().add_attribute("action", "Instantitate"))
+
+    let response = Response::new().add_attribute("action", "instantiate");
+    match msg.initial_deposit {
+        Some(amount) => {
+            let recipient = msg
+                .initial_shares_recipient
+                .map(|recipient| deps.api.addr_validate(&recipient))
+                .transpose()?
+                .unwrap_or_else(|| info.sender.clone());
+            seed_deposit(
+                &mut deps, &env, &info, &config, &recipient, amount, response,
+            )
+        }
+        None if msg.initial_shares_recipient.is_some() => {
+            Err(ContractError::SeedRecipientWithoutDeposit {})
+        }
+        None => Ok(response),
+    }
+}
+
+/// Credits the deployer's `amount` to `recipient` in the empty vault being instantiated.
+/// The seed passes `check_deposit` for `recipient` like any deposit, so it must meet
+/// `min_deposit` and, in private mode, cannot be made at all while the allow-list is still
+/// empty. A cw20 seed is pulled in like any deposit, so the allowance is checked up front
+/// rather than left to fail the transfer.
+fn seed_deposit(
+    deps: &mut DepsMut,
+    env: &Env,
+    info: &MessageInfo,
+    config: &Config,
+    recipient: &Addr,
+    amount: Uint128,
+    response: Response,
+) -> Result<Response, ContractError> {
+    check_deposit(deps.storage, config, recipient, amount)?;
+    if let AssetKind::Cw20(token) = &config.token {
+        let approved: AllowanceResponse = deps.querier.query_wasm_smart(
+            token,
+            &Cw20QueryMsg::Allowance {
+                owner: info.sender.to_string(),
+                spender: env.contract.address.to_string(),
+            },
+        )?;
+        let allowance = if approved.expires.is_expired(&env.block) {
+            Uint128::zero()
+        } else {
+            approved.allowance
+        };
+        if allowance < amount {
+            return Err(ContractError::InsufficientAllowance { allowance, amount });
+        }
+    }
+    let (_, pull_msgs) = pull_deposit(env, info, config, amount)?;
+    let credited = credit_deposit(deps, env, config, recipient, amount, Uint128::zero())?;
+
+    let mut response = response
+        .add_attribute("initial_deposit", amount)
+        .add_attribute("initial_shares", credited.shares)
+        .add_attribute("initial_shares_recipient", recipient)
+        .add_attribute("total_supply", credited.total_supply)
+        .add_messages(pull_msgs)
+        .add_messages(fee_transfer_msg(config, credited.fee)?);
+    if let (Some(staking), AssetKind::Cw20(token)) = (&config.staking, &config.token) {
+        response = response.add_message(get_stake_msg(token, staking, amount - credited.fee)?);
+    }
+    Ok(response)
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn migrate(deps: DepsMut, env: Env, msg: MigrateMsg) -> Result<Response, ContractError> {
+    let stored = get_contract_version(deps.storage)?;
+    if stored.contract != CONTRACT_NAME {
+        return Err(ContractError::MigrationContractMismatch {
+            contract: stored.contract,
+        });
+    }
+    let parse = |version: &str| {
+        version
+            .parse::<Version>()
+            .map_err(|err| StdError::generic_err(format!("Invalid version {}: {}", version, err)))
+    };
+    let upgrading = parse(&stored.version)? < parse(CONTRACT_VERSION)?;
+    if parse(&stored.version)? > parse(CONTRACT_VERSION)? {
+        return Err(ContractError::MigrationDowngrade {
+            from: stored.version,
+            to: CONTRACT_VERSION.to_string(),
+        });
+    }
+
+    if let Some(uri) = msg.metadata_uri {
+        let mut config = CONFIG.load(deps.storage)?;
+        config.metadata_uri = validate_metadata_uri(Some(uri))?;
+        CONFIG.save(deps.storage, &config)?;
+    }
+    if HOLDER_COUNT.may_load(deps.storage)?.is_none() {
+        let count = count_holders(deps.storage)?;
+        HOLDER_COUNT.save(deps.storage, &count)?;
+    }
+    let (backfilled, complete) = if upgrading {
+        backfill_snapshots(
+            deps.storage,
+            env.block.height,
+            msg.balance_batch_limit.unwrap_or(DEFAULT_MIGRATE_BATCH),
+        )?
+    } else {
+        (0, true)
+    };
+
+    let response = Response::new()
+        .add_attribute("action", "migrate")
+        .add_attribute("from_version", stored.version);
+    // the old version stays stored until the backfill is done, so the next migrate resumes it
+    if !complete {
+        return Ok(response
+            .add_attribute("snapshots_backfilled", backfilled.to_string())
+            .add_attribute("migration_complete", "false"));
+    }
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+    Ok(response
+        .add_attribute("to_version", CONTRACT_VERSION)
+        .add_attribute("snapshots_backfilled", backfilled.to_string()))
+}
+
+/// Gives every holder without a snapshot one at `height` holding their current balance, so
+/// `BalanceAt` sees balances that have not moved since snapshots were introduced. Walks at
+/// most `limit` balances after the cursor the previous call left and returns how many it
+/// copied and whether it reached the last one.
+fn backfill_snapshots(
+    storage: &mut dyn Storage,
+    height: u64,
+    limit: u32,
+) -> StdResult<(u32, bool)> {
+    let limit = limit.max(1) as usize;
+    let start = SNAPSHOT_BACKFILL_CURSOR
+        .may_load(storage)?
+        .map(Bound::exclusive);
+    let mut batch = BALANCE_OF
+        .range(storage, start, None, Order::Ascending)
+        .take(limit + 1)
+        .collect::<StdResult<Vec<_>>>()?;
+    let complete = batch.len() <= limit;
+    batch.truncate(limit);
+
+    let mut backfilled = 0;
+    for (address, balance) in batch.iter() {
+        let has_snapshot = SNAPSHOTS
+            .prefix(address.clone())
+            .keys(storage, None, None, Order::Ascending)
+            .next()
+            .is_some();
+        if !has_snapshot {
+            SNAPSHOTS.save(storage, (address.clone(), height), balance)?;
+            backfilled += 1;
+        }
+    }
+    match batch.last() {
+        Some((address, _)) if !complete => SNAPSHOT_BACKFILL_CURSOR.save(storage, address)?,
+        _ => SNAPSHOT_BACKFILL_CURSOR.remove(storage),
+    }
+    Ok((backfilled, complete))
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
@@ -49,161 +261,6963 @@ pub fn execute(
     msg: ExecuteMsg,
 ) -> Result<Response, ContractError> {
     match msg {
-        ExecuteMsg::Deposit { amount } => execute_deposit(deps, env, info, amount),
-        ExecuteMsg::Withdraw { share } => execute_withdraw(deps, env, info, share),
+        ExecuteMsg::Deposit {
+            amount,
+            min_shares,
+            referrer,
+        } => execute_deposit(deps, env, info, None, amount, min_shares, referrer),
+        ExecuteMsg::Withdraw {
+            share,
+            recipient,
+            send_to,
+        } => {
+            let payout = match (recipient, send_to) {
+                (Some(_), Some(_)) => return Err(ContractError::ConflictingPayout {}),
+                (Some(recipient), None) => Payout::Transfer(deps.api.addr_validate(&recipient)?),
+                (None, Some(target)) => {
+                    let contract = deps.api.addr_validate(&target.contract)?;
+                    // a plain account would take the tokens but never see the hook
+                    if deps.querier.query_wasm_contract_info(&contract).is_err() {
+                        return Err(ContractError::NotAContract {
+                            address: contract.into(),
+                        });
+                    }
+                    Payout::Send(contract, target.msg)
+                }
+                (None, None) => Payout::Transfer(info.sender.clone()),
+            };
+            execute_withdraw(deps, env, info, Redeem::Shares(share), payout)
+        }
+        ExecuteMsg::WithdrawAssets { amount } => {
+            let payout = Payout::Transfer(info.sender.clone());
+            execute_withdraw(deps, env, info, Redeem::Assets(amount), payout)
+        }
+        ExecuteMsg::RedeemAll {} => {
+            let payout = Payout::Transfer(info.sender.clone());
+            execute_withdraw(deps, env, info, Redeem::All, payout)
+        }
+        ExecuteMsg::Receive(wrapper) => execute_receive(deps, env, info, wrapper),
+        ExecuteMsg::MigratePosition {
+            target_vault,
+            share,
+        } => execute_migrate_position(deps, env, info, target_vault, share),
+        ExecuteMsg::CaptureDirectDeposits {} => execute_capture_direct_deposits(deps, env),
+        ExecuteMsg::SetPrivateMode { enabled } => execute_set_private_mode(deps, info, enabled),
+        ExecuteMsg::UpdateAllowlist { add, remove } => {
+            execute_update_allowlist(deps, info, add, remove)
+        }
+        ExecuteMsg::SetFeeExempt { address, exempt } => {
+            execute_set_fee_exempt(deps, info, address, exempt)
+        }
+        ExecuteMsg::SetMetadataUri { metadata_uri } => {
+            execute_set_metadata_uri(deps, info, metadata_uri)
+        }
+        ExecuteMsg::UpdateConfig {
+            new_owner,
+            new_token,
+        } => execute_update_config(deps, info, new_owner, new_token),
+        ExecuteMsg::ProposeNewOwner { address } => execute_propose_new_owner(deps, info, address),
+        ExecuteMsg::AcceptOwnership {} => execute_accept_ownership(deps, info),
+        ExecuteMsg::SetPaused {
+            paused,
+            allow_withdrawals,
+        } => execute_set_paused(deps, info, paused, allow_withdrawals),
+        ExecuteMsg::SetCap { cap } => execute_set_cap(deps, info, cap),
+        ExecuteMsg::SetMinDeposit { min_deposit } => {
+            execute_set_min_deposit(deps, info, min_deposit)
+        }
+        ExecuteMsg::SweepToken { token, recipient } => {
+            execute_sweep_token(deps, env, info, token, recipient)
+        }
+        ExecuteMsg::RequestWithdraw { share } => execute_request_withdraw(deps, env, info, share),
+        ExecuteMsg::ClaimWithdraw {} => execute_claim_withdraw(deps, env, info),
+        ExecuteMsg::CancelWithdraw { request_id } => {
+            execute_cancel_withdraw(deps, env, info, request_id)
+        }
+        ExecuteMsg::CollectFees {} => execute_collect_fees(deps, env),
+        ExecuteMsg::Accrue {} => execute_accrue(deps, env),
+        ExecuteMsg::TransferShares { recipient, amount } => {
+            execute_transfer_shares(deps, env, info, recipient, amount)
+        }
+        ExecuteMsg::BatchDeposit { deposits } => execute_batch_deposit(deps, env, info, deposits),
+        ExecuteMsg::DepositFor { recipient, amount } => {
+            execute_deposit(deps, env, info, Some(recipient), amount, None, None)
+        }
     }
 }
 
-fn execute_deposit(
+fn validate_asset(api: &dyn Api, asset: AssetKind) -> Result<AssetKind, ContractError> {
+    match asset {
+        AssetKind::Cw20(token) => Ok(AssetKind::Cw20(api.addr_validate(token.as_str())?)),
+        AssetKind::Native(denom) if denom.trim().is_empty() => Err(ContractError::EmptyDenom {}),
+        AssetKind::Native(denom) => Ok(AssetKind::Native(denom)),
+    }
+}
+
+/// A cw20 vault token must be a cw20 contract, not the owner's own account mistyped into the
+/// wrong field.
+fn check_token_contract(deps: Deps, owner: &Addr, token: &Addr) -> Result<(), ContractError> {
+    if token == owner {
+        return Err(ContractError::TokenIsOwner {
+            token: token.to_string(),
+        });
+    }
+    let info: StdResult<TokenInfoResponse> = deps
+        .querier
+        .query_wasm_smart(token, &Cw20QueryMsg::TokenInfo {});
+    if info.is_err() {
+        return Err(ContractError::NotCw20Token {
+            token: token.to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// A metadata URI must look like `scheme://rest`, without whitespace and within
+/// `MAX_METADATA_URI_LEN` bytes.
+fn validate_metadata_uri(uri: Option<String>) -> Result<Option<String>, ContractError> {
+    let uri = match uri {
+        Some(uri) => uri,
+        None => return Ok(None),
+    };
+    let invalid = |reason: &str| ContractError::InvalidMetadataUri {
+        reason: reason.to_string(),
+    };
+    if uri.is_empty() {
+        return Err(invalid("empty"));
+    }
+    if uri.len() > MAX_METADATA_URI_LEN {
+        return Err(invalid("too long"));
+    }
+    if uri.chars().any(char::is_whitespace) {
+        return Err(invalid("contains whitespace"));
+    }
+    match uri.split_once("://") {
+        Some((scheme, rest)) if !scheme.is_empty() && !rest.is_empty() => Ok(Some(uri)),
+        _ => Err(invalid("missing scheme")),
+    }
+}
+
+/// Every owner-only handler emits one `admin` event named by its `admin_action` attribute,
+/// with the values it changed as `old_<field>`/`new_<field>` pairs.
+fn admin_event(action: &str) -> Event {
+    Event::new("admin").add_attribute("admin_action", action)
+}
+
+fn execute_set_metadata_uri(
     deps: DepsMut,
-    env: Env,
     info: MessageInfo,
-    amount: Uint128,
+    metadata_uri: Option<String>,
 ) -> Result<Response, ContractError> {
-    let config = CONFIG.load(deps.storage)?;
-    let mut shares = Uint128::zero();
-    let mut total_supply = TOTAL_SUPPLY.load(deps.storage)?;
-    let mut balance = BALANCE_OF
-        .load(deps.storage, info.sender.clone())
-        .unwrap_or(Uint128::zero());
+    let mut config = CONFIG.load(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    let old_uri = config.metadata_uri.take().unwrap_or_default();
+    config.metadata_uri = validate_metadata_uri(metadata_uri)?;
+    CONFIG.save(deps.storage, &config)?;
+    let new_uri = config.metadata_uri.unwrap_or_default();
+    Ok(Response::new()
+        .add_attribute("action", "set_metadata_uri")
+        .add_attribute("metadata_uri", new_uri.clone())
+        .add_event(
+            admin_event("set_metadata_uri")
+                .add_attribute("old_metadata_uri", old_uri)
+                .add_attribute("new_metadata_uri", new_uri),
+        ))
+}
 
-    let balance_contract =
-        get_token_balance_of(&deps, env.contract.address.clone(), config.token.clone())?;
+fn execute_set_private_mode(
+    deps: DepsMut,
+    info: MessageInfo,
+    enabled: bool,
+) -> Result<Response, ContractError> {
+    let mut config = CONFIG.load(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    let old_mode = config.private_mode;
+    config.private_mode = enabled;
+    CONFIG.save(deps.storage, &config)?;
+    Ok(Response::new()
+        .add_attribute("action", "set_private_mode")
+        .add_attribute("enabled", enabled.to_string())
+        .add_event(
+            admin_event("set_private_mode")
+                .add_attribute("old_private_mode", old_mode.to_string())
+                .add_attribute("new_private_mode", enabled.to_string()),
+        ))
+}
 
-    if total_supply.is_zero() {
-        shares = amount;
+fn execute_update_allowlist(
+    deps: DepsMut,
+    info: MessageInfo,
+    add: Vec<String>,
+    remove: Vec<String>,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    for address in add.iter() {
+        let address = deps.api.addr_validate(address)?;
+        DEPOSIT_ALLOWLIST.save(deps.storage, address, &true)?;
+    }
+    for address in remove.iter() {
+        let address = deps.api.addr_validate(address)?;
+        DEPOSIT_ALLOWLIST.remove(deps.storage, address);
+    }
+    Ok(Response::new()
+        .add_attribute("action", "update_allowlist")
+        .add_attribute("added", add.len().to_string())
+        .add_attribute("removed", remove.len().to_string())
+        .add_event(
+            admin_event("update_allowlist")
+                .add_attribute("added", add.join(","))
+                .add_attribute("removed", remove.join(",")),
+        ))
+}
+
+fn execute_set_fee_exempt(
+    deps: DepsMut,
+    info: MessageInfo,
+    address: String,
+    exempt: bool,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    let address = deps.api.addr_validate(&address)?;
+    if exempt {
+        FEE_EXEMPT.save(deps.storage, address.clone(), &true)?;
     } else {
-        shares += amount
-            .checked_mul(total_supply)
-            .map_err(StdError::overflow)?
-            .checked_div(balance_contract)
-            .map_err(StdError::divide_by_zero)?;
+        FEE_EXEMPT.remove(deps.storage, address.clone());
     }
+    Ok(Response::new()
+        .add_attribute("action", "set_fee_exempt")
+        .add_attribute("address", address.clone())
+        .add_attribute("exempt", exempt.to_string())
+        .add_event(
+            admin_event("set_fee_exempt")
+                .add_attribute("address", address)
+                .add_attribute("exempt", exempt.to_string()),
+        ))
+}
 
-    total_supply += shares;
-    TOTAL_SUPPLY.save(deps.storage, &total_supply)?;
-    balance += shares;
+fn execute_update_config(
+    deps: DepsMut,
+    info: MessageInfo,
+    new_owner: Option<String>,
+    new_token: Option<String>,
+) -> Result<Response, ContractError> {
+    let mut config = CONFIG.load(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    let mut event = admin_event("update_config");
 
-    BALANCE_OF.save(deps.storage, info.sender.clone(), &balance)?;
+    if let Some(new_owner) = new_owner {
+        let new_owner = deps.api.addr_validate(&new_owner)?;
+        event = event
+            .add_attribute("old_owner", config.owner)
+            .add_attribute("new_owner", new_owner.clone());
+        config.owner = new_owner;
+        // an offer made by the previous owner no longer stands
+        PENDING_OWNER.save(deps.storage, &None)?;
+    }
+    if let Some(new_token) = new_token {
+        let new_token = deps.api.addr_validate(&new_token)?;
+        // outstanding shares are backed by the old token and would be stranded
+        let total_supply = TOTAL_SUPPLY.load(deps.storage)?;
+        if !total_supply.is_zero() {
+            return Err(ContractError::TokenChangeWithSupply { total_supply });
+        }
+        event = event
+            .add_attribute("old_token", asset_attr(&config.token))
+            .add_attribute("new_token", new_token.clone());
+        config.token = AssetKind::Cw20(new_token);
+        MANAGED_ASSETS.save(deps.storage, &Uint128::zero())?;
+    }
+    CONFIG.save(deps.storage, &config)?;
 
-    let transfer_cw20 = Cw20ExecuteMsg::TransferFrom {
-        owner: info.sender.into(),
-        recipient: env.contract.address.into(),
-        amount: amount,
-    };
+    Ok(Response::new()
+        .add_attribute("action", "update_config")
+        .add_event(event))
+}
 
-    let msg = WasmMsg::Execute {
-        contract_addr: config.token.into(),
-        msg: to_json_binary(&transfer_cw20)?,
-        funds: vec![],
-    };
+fn execute_propose_new_owner(
+    deps: DepsMut,
+    info: MessageInfo,
+    address: String,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    let pending = deps.api.addr_validate(&address)?;
+    PENDING_OWNER.save(deps.storage, &Some(pending.clone()))?;
 
-    let c_msg: CosmosMsg = msg.into();
+    Ok(Response::new()
+        .add_attribute("action", "propose_new_owner")
+        .add_attribute("pending_owner", pending.clone())
+        .add_event(
+            admin_event("propose_new_owner")
+                .add_attribute("owner", config.owner)
+                .add_attribute("pending_owner", pending),
+        ))
+}
+
+fn execute_accept_ownership(deps: DepsMut, info: MessageInfo) -> Result<Response, ContractError> {
+    let pending = PENDING_OWNER
+        .may_load(deps.storage)?
+        .flatten()
+        .ok_or(ContractError::NoPendingOwner {})?;
+    if info.sender != pending {
+        return Err(ContractError::Unauthorized {});
+    }
+    let mut config = CONFIG.load(deps.storage)?;
+    let old_owner = std::mem::replace(&mut config.owner, pending);
+    CONFIG.save(deps.storage, &config)?;
+    PENDING_OWNER.save(deps.storage, &None)?;
 
-    Ok(Response::new().add_message(c_msg))
+    Ok(Response::new()
+        .add_attribute("action", "accept_ownership")
+        .add_attribute("owner", config.owner.clone())
+        .add_event(
+            admin_event("accept_ownership")
+                .add_attribute("old_owner", old_owner)
+                .add_attribute("new_owner", config.owner),
+        ))
 }
 
-fn execute_withdraw(
+fn execute_set_paused(
     deps: DepsMut,
-    env: Env,
     info: MessageInfo,
-    share: Uint128,
+    paused: bool,
+    allow_withdrawals: bool,
 ) -> Result<Response, ContractError> {
     let config = CONFIG.load(deps.storage)?;
-    let token = config.token.clone();
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    let was_paused = PAUSED.may_load(deps.storage)?.unwrap_or_default();
+    // nothing is held back once the vault is unpaused
+    let allow_withdrawals = allow_withdrawals || !paused;
+    PAUSED.save(deps.storage, &paused)?;
+    PAUSE_ALLOWS_WITHDRAWALS.save(deps.storage, &allow_withdrawals)?;
 
-    let mut total_supply = TOTAL_SUPPLY.load(deps.storage)?;
+    Ok(Response::new()
+        .add_attribute("action", "set_paused")
+        .add_attribute("paused", paused.to_string())
+        .add_event(
+            admin_event("set_paused")
+                .add_attribute("old_paused", was_paused.to_string())
+                .add_attribute("new_paused", paused.to_string())
+                .add_attribute("allow_withdrawals", allow_withdrawals.to_string()),
+        ))
+}
 
-    let mut balance = BALANCE_OF
-        .load(deps.storage, info.sender.clone())
-        .unwrap_or(Uint128::zero());
+fn execute_set_cap(
+    deps: DepsMut,
+    info: MessageInfo,
+    cap: Option<Uint128>,
+) -> Result<Response, ContractError> {
+    let mut config = CONFIG.load(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    let old_cap = std::mem::replace(&mut config.deposit_cap, cap);
+    CONFIG.save(deps.storage, &config)?;
 
-    let token_bal = get_token_balance_of(&deps, env.contract.address, token)?;
+    let cap_attr = |cap: Option<Uint128>| cap.map(|cap| cap.to_string()).unwrap_or_default();
+    Ok(Response::new()
+        .add_attribute("action", "set_cap")
+        .add_attribute("cap", cap_attr(cap))
+        .add_event(
+            admin_event("set_cap")
+                .add_attribute("old_cap", cap_attr(old_cap))
+                .add_attribute("new_cap", cap_attr(cap)),
+        ))
+}
 
-    let amount = share
-        .checked_mul(token_bal)
-        .map_err(StdError::overflow)?
-        .checked_div(total_supply)
-        .map_err(StdError::divide_by_zero)?;
+fn execute_set_min_deposit(
+    deps: DepsMut,
+    info: MessageInfo,
+    min_deposit: Uint128,
+) -> Result<Response, ContractError> {
+    let mut config = CONFIG.load(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    let old_min_deposit = std::mem::replace(&mut config.min_deposit, min_deposit);
+    CONFIG.save(deps.storage, &config)?;
 
-    total_supply -= share;
-    TOTAL_SUPPLY.save(deps.storage, &total_supply)?;
-    balance -= share;
-    BALANCE_OF.save(deps.storage, info.sender.clone(), &balance)?;
+    Ok(Response::new()
+        .add_attribute("action", "set_min_deposit")
+        .add_attribute("min_deposit", min_deposit)
+        .add_event(
+            admin_event("set_min_deposit")
+                .add_attribute("old_min_deposit", old_min_deposit)
+                .add_attribute("new_min_deposit", min_deposit),
+        ))
+}
 
-    let transfer_cw20 = Cw20ExecuteMsg::Transfer {
-        recipient: info.sender.into(),
-        amount: amount,
-    };
-    let msg = WasmMsg::Execute {
-        contract_addr: config.token.into(),
-        msg: to_json_binary(&transfer_cw20)?,
-        funds: vec![],
+fn execute_sweep_token(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    token: String,
+    recipient: String,
+) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    if info.sender != config.owner {
+        return Err(ContractError::Unauthorized {});
+    }
+    let token = AssetKind::Cw20(deps.api.addr_validate(&token)?);
+    // the underlying is what backs every share, so it never leaves through here
+    if token == config.token {
+        return Err(ContractError::CannotSweepUnderlying {
+            token: asset_attr(&token),
+        });
+    }
+    let recipient = deps.api.addr_validate(&recipient)?;
+
+    let amount = token.query_balance(deps.as_ref(), &env.contract.address)?;
+    let msgs = if amount.is_zero() {
+        vec![]
+    } else {
+        vec![token.transfer_msg(&recipient, amount)?]
     };
 
-    let c_msg: CosmosMsg = msg.into();
+    Ok(Response::new()
+        .add_messages(msgs)
+        .add_attribute("action", "sweep_token")
+        .add_attribute("token", asset_attr(&token))
+        .add_attribute("recipient", recipient.clone())
+        .add_attribute("amount", amount)
+        .add_event(
+            admin_event("sweep_token")
+                .add_attribute("token", asset_attr(&token))
+                .add_attribute("recipient", recipient)
+                .add_attribute("amount", amount),
+        ))
+}
 
-    Ok(Response::new().add_message(c_msg))
+/// In private mode, only addresses on the allow-list may come to hold shares.
+fn assert_allowlisted(
+    storage: &dyn Storage,
+    config: &Config,
+    address: &Addr,
+) -> Result<(), ContractError> {
+    if config.private_mode
+        && !DEPOSIT_ALLOWLIST
+            .may_load(storage, address.clone())?
+            .unwrap_or_default()
+    {
+        return Err(ContractError::NotAllowlisted {
+            address: address.into(),
+        });
+    }
+    Ok(())
 }
 
-pub fn get_token_balance_of(
-    deps: &DepsMut,
-    user_address: Addr,
-    cw20_contract_addr: Addr,
-) -> Result<Uint128, ContractError> {
-    let resp: cw20::BalanceResponse = deps.querier.query_wasm_smart(
-        cw20_contract_addr,
-        &cw20_base::msg::QueryMsg::Balance {
-            address: user_address.to_string(),
-        },
+fn deposits_paused(storage: &dyn Storage) -> StdResult<bool> {
+    Ok(PAUSED.may_load(storage)?.unwrap_or_default())
+}
+
+fn queued_assets(storage: &dyn Storage) -> StdResult<Uint128> {
+    Ok(QUEUED_ASSETS.may_load(storage)?.unwrap_or_default())
+}
+
+fn withdrawals_paused(storage: &dyn Storage) -> StdResult<bool> {
+    Ok(deposits_paused(storage)?
+        && !PAUSE_ALLOWS_WITHDRAWALS
+            .may_load(storage)?
+            .unwrap_or_default())
+}
+
+/// Pulls `amount` from the sender and credits the shares to `recipient`, or to the sender
+/// when there is none.
+fn execute_deposit(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    recipient: Option<String>,
+    amount: Uint128,
+    min_shares: Option<Uint128>,
+    referrer: Option<String>,
+) -> Result<Response, ContractError> {
+    accrue(&mut deps, &env)?;
+    let config = CONFIG.load(deps.storage)?;
+    let recipient = recipient
+        .map(|recipient| deps.api.addr_validate(&recipient))
+        .transpose()?;
+    let referrer = referrer
+        .map(|referrer| deps.api.addr_validate(&referrer))
+        .transpose()?;
+    let depositor = recipient.as_ref().unwrap_or(&info.sender);
+    let (received, pull_msgs) = pull_deposit(&env, &info, &config, amount)?;
+    check_deposit(deps.storage, &config, depositor, amount)?;
+    let balance_contract = get_total_assets(deps.as_ref(), &env, &config)?.saturating_sub(received);
+    let credited = credit_deposit(
+        &mut deps,
+        &env,
+        &config,
+        depositor,
+        amount,
+        balance_contract,
     )?;
-    Ok(resp.balance)
+    // the share price can move between quoting a deposit and it landing, e.g. on a harvest
+    if let Some(min) = min_shares {
+        if credited.shares < min {
+            return Err(ContractError::SlippageExceeded {
+                min,
+                actual: credited.shares,
+            });
+        }
+    }
+    let fee = credited.fee;
+
+    let mut response = Response::new()
+        .add_attribute("action", "deposit")
+        .add_attribute("sender", info.sender)
+        .add_attribute("amount", amount)
+        .add_attribute("shares_minted", credited.shares)
+        .add_attribute("total_supply", credited.total_supply)
+        .add_messages(pull_msgs)
+        .add_messages(fee_transfer_msg(&config, fee)?);
+    if let Some(recipient) = recipient {
+        response = response.add_attribute("recipient", recipient);
+    }
+    if let Some(referrer) = referrer {
+        REFERRALS.update(deps.storage, referrer.clone(), |volume| {
+            volume
+                .unwrap_or_default()
+                .checked_add(amount)
+                .map_err(StdError::overflow)
+        })?;
+        response = response.add_attribute("referrer", referrer);
+    }
+
+    if let (Some(staking), AssetKind::Cw20(token)) = (&config.staking, &config.token) {
+        response = response.add_message(get_stake_msg(token, staking, amount - fee)?);
+    }
+
+    Ok(response)
 }
 
-#[cfg_attr(not(feature = "library"), entry_point)]
-pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
-    match msg {
-        QueryMsg::GetTotalSupply {} => get_total_supply(deps),
-        QueryMsg::GetBalanceOf { address } => get_balance_of(deps, address),
+/// Checks a deposit of `amount` by the sender is paid for. Returns how much of it is already
+/// in the vault's balance (native funds arrive with the message) and the messages that pull
+/// in the rest (cw20s are transferred in after).
+fn pull_deposit(
+    env: &Env,
+    info: &MessageInfo,
+    config: &Config,
+    amount: Uint128,
+) -> Result<(Uint128, Vec<CosmosMsg>), ContractError> {
+    Ok(match &config.token {
+        AssetKind::Native(denom) => {
+            match info.funds.as_slice() {
+                [coin] if coin.denom == *denom && coin.amount == amount => {}
+                _ => {
+                    return Err(ContractError::InvalidFunds {
+                        denom: denom.clone(),
+                        amount,
+                    })
+                }
+            }
+            (amount, vec![])
+        }
+        AssetKind::Cw20(token) => {
+            // spends exactly `amount` of the sender's allowance; whatever they approved beyond
+            // it stays theirs to revoke, and the vault never grants or asks for one itself
+            let transfer_cw20 = Cw20ExecuteMsg::TransferFrom {
+                owner: info.sender.to_string(),
+                recipient: env.contract.address.to_string(),
+                amount,
+            };
+            let msg = WasmMsg::Execute {
+                contract_addr: token.into(),
+                msg: to_json_binary(&transfer_cw20)?,
+                funds: vec![],
+            };
+            (Uint128::zero(), vec![CosmosMsg::from(msg)])
+        }
+    })
+}
+
+fn execute_receive(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    wrapper: Cw20ReceiveMsg,
+) -> Result<Response, ContractError> {
+    accrue(&mut deps, &env)?;
+    let config = CONFIG.load(deps.storage)?;
+    if !matches!(&config.token, AssetKind::Cw20(token) if *token == info.sender) {
+        return Err(ContractError::Unauthorized {});
+    }
+
+    let ReceiveMsg::Deposit { recipient } = from_json(&wrapper.msg)?;
+    let depositor = deps
+        .api
+        .addr_validate(&recipient.unwrap_or(wrapper.sender))?;
+    check_deposit(deps.storage, &config, &depositor, wrapper.amount)?;
+    // the cw20 has already credited the vault, so the amount is left out of pricing
+    let balance_contract =
+        get_total_assets(deps.as_ref(), &env, &config)?.saturating_sub(wrapper.amount);
+    let credited = credit_deposit(
+        &mut deps,
+        &env,
+        &config,
+        &depositor,
+        wrapper.amount,
+        balance_contract,
+    )?;
+    let fee = credited.fee;
+
+    let mut response = Response::new()
+        .add_attribute("action", "receive_deposit")
+        .add_attribute("depositor", depositor)
+        .add_attribute("amount", wrapper.amount)
+        .add_attribute("shares_minted", credited.shares)
+        .add_attribute("total_supply", credited.total_supply)
+        .add_messages(fee_transfer_msg(&config, fee)?);
+    if let (Some(staking), AssetKind::Cw20(token)) = (&config.staking, &config.token) {
+        response = response.add_message(get_stake_msg(token, staking, wrapper.amount - fee)?);
     }
+    Ok(response)
 }
 
-fn get_total_supply(deps: Deps) -> StdResult<Binary> {
-    let total = TOTAL_SUPPLY.load(deps.storage)?;
+fn execute_batch_deposit(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    deposits: Vec<(String, Uint128)>,
+) -> Result<Response, ContractError> {
+    if deposits.len() > MAX_BATCH_DEPOSITS {
+        return Err(ContractError::BatchTooLarge {
+            len: deposits.len(),
+            max: MAX_BATCH_DEPOSITS,
+        });
+    }
+    accrue(&mut deps, &env)?;
+    let config = CONFIG.load(deps.storage)?;
+    let deposits = deposits
+        .into_iter()
+        .map(|(recipient, amount)| Ok((deps.api.addr_validate(&recipient)?, amount)))
+        .collect::<StdResult<Vec<_>>>()?;
+    let amount = deposits
+        .iter()
+        .try_fold(Uint128::zero(), |total, (_, amount)| {
+            total.checked_add(*amount)
+        })
+        .map_err(StdError::overflow)?;
+    // an empty batch has nothing to credit
+    if amount.is_zero() {
+        return Err(ContractError::ZeroAmount {});
+    }
+    let (received, pull_msgs) = pull_deposit(&env, &info, &config, amount)?;
+    for (recipient, amount) in &deposits {
+        check_deposit(deps.storage, &config, recipient, *amount)?;
+    }
+
+    // one snapshot for the whole batch, grown by each entry's tokens as if it had landed
+    let mut balance_contract =
+        get_total_assets(deps.as_ref(), &env, &config)?.saturating_sub(received);
+    let mut fee = Uint128::zero();
+    let mut total_supply = Uint128::zero();
+    let mut response = Response::new()
+        .add_attribute("action", "batch_deposit")
+        .add_attribute("sender", info.sender)
+        .add_attribute("amount", amount);
+    for (recipient, amount) in deposits {
+        let credited = credit_deposit(
+            &mut deps,
+            &env,
+            &config,
+            &recipient,
+            amount,
+            balance_contract,
+        )?;
+        balance_contract = balance_contract
+            .checked_add(amount - credited.fee)
+            .map_err(StdError::overflow)?;
+        fee = fee.checked_add(credited.fee).map_err(StdError::overflow)?;
+        total_supply = credited.total_supply;
+        response = response
+            .add_attribute("recipient", recipient)
+            .add_attribute("shares_minted", credited.shares);
+    }
 
-    return to_json_binary(&total);
+    response = response
+        .add_attribute("total_supply", total_supply)
+        .add_messages(pull_msgs)
+        .add_messages(fee_transfer_msg(&config, fee)?);
+    if let (Some(staking), AssetKind::Cw20(token)) = (&config.staking, &config.token) {
+        response = response.add_message(get_stake_msg(token, staking, amount - fee)?);
+    }
+    Ok(response)
 }
 
-fn get_balance_of(deps: Deps, address: Addr) -> StdResult<Binary> {
-    let balance = BALANCE_OF.load(deps.storage, address)?;
+/// What a deposit credited, for the caller to report and settle.
+struct Credited {
+    /// Shares added to the depositor's balance.
+    shares: Uint128,
+    /// Deposit fee held back from the amount, which the caller forwards to the fee recipient.
+    fee: Uint128,
+    /// Total supply after the deposit.
+    total_supply: Uint128,
+}
 
-    return to_json_binary(&balance);
+/// Refuses a deposit of `amount` by `depositor` before anything is queried or priced.
+fn check_deposit(
+    storage: &dyn Storage,
+    config: &Config,
+    depositor: &Addr,
+    amount: Uint128,
+) -> Result<(), ContractError> {
+    if deposits_paused(storage)? {
+        return Err(ContractError::Paused {});
+    }
+    if amount.is_zero() {
+        return Err(ContractError::ZeroAmount {});
+    }
+    if amount < config.min_deposit {
+        return Err(ContractError::BelowMinDeposit {
+            min: config.min_deposit,
+        });
+    }
+    assert_allowlisted(storage, config, depositor)
 }
 
-#[cfg(test)]
-mod tests {
+/// Mints the shares for a deposit of `amount` to `depositor`, which `check_deposit` has
+/// already let through. `balance_contract` is the vault's total assets without the deposit,
+/// so the part of `amount` already sitting in its balance never prices its own shares.
+fn credit_deposit(
+    deps: &mut DepsMut,
+    env: &Env,
+    config: &Config,
+    depositor: &Addr,
+    amount: Uint128,
+    balance_contract: Uint128,
+) -> Result<Credited, ContractError> {
+    let config = &waive_exempt_fees(deps.storage, config.clone(), depositor);
+    let mut total_supply = TOTAL_SUPPLY.load(deps.storage)?;
 
-    use crate::contract::{execute, instantiate};
-    use crate::msg::{ExecuteMsg, InstantiateMsg};
-    use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info};
+    if let Some(cap) = config.deposit_cap {
+        let attempted = balance_contract
+            .checked_add(amount)
+            .map_err(StdError::overflow)?;
+        if attempted > cap {
+            return Err(ContractError::CapExceeded { cap, attempted });
+        }
+    }
+    let deposited = DEPOSITED
+        .may_load(deps.storage, depositor.clone())?
+        .unwrap_or_default()
+        .checked_add(amount)
+        .map_err(StdError::overflow)?;
+    if let Some(limit) = config.max_per_address {
+        if deposited > limit {
+            return Err(ContractError::AddressLimitExceeded {
+                address: depositor.into(),
+                limit,
+                attempted: deposited,
+            });
+        }
+    }
+    DEPOSITED.save(deps.storage, depositor.clone(), &deposited)?;
+    record_share_price(deps.storage, env, balance_contract, total_supply)?;
+    // the depositor buys in after the fee on growth so far, not before it
+    total_supply = total_supply
+        .checked_add(accrue_performance_fee(
+            deps.storage,
+            env.block.height,
+            config,
+            balance_contract,
+            total_supply,
+        )?)
+        .map_err(StdError::overflow)?;
 
-    pub const ADDR1: &str = "addr1";
-    pub const ADDR2: &str = "addr2";
+    let fee = rounded_up_fee(config, amount, config.deposit_fee_bps)?;
+    if fee >= amount {
+        return Err(ContractError::DepositBelowFee { amount, fee });
+    }
+    let net = amount - fee;
+    let shares = shares_for_deposit(net, balance_contract, total_supply)?;
+    let dead_shares = dead_shares_for(total_supply);
+    // a deposit rounded down to nothing would hand its tokens to the existing holders
+    if shares <= dead_shares {
+        return Err(ContractError::DepositTooSmall { min: dead_shares });
+    }
+    if !dead_shares.is_zero() {
+        let locked = DEAD_SHARES.may_load(deps.storage)?.unwrap_or_default();
+        DEAD_SHARES.save(
+            deps.storage,
+            &locked
+                .checked_add(dead_shares)
+                .map_err(StdError::overflow)?,
+        )?;
+    }
+    let shares_out = shares - dead_shares;
 
-    #[test]
+    total_supply = total_supply
+        .checked_add(shares)
+        .map_err(StdError::overflow)?;
+    TOTAL_SUPPLY.save(deps.storage, &total_supply)?;
+    let principal = TOTAL_PRINCIPAL.may_load(deps.storage)?.unwrap_or_default();
+    TOTAL_PRINCIPAL.save(
+        deps.storage,
+        &principal.checked_add(amount).map_err(StdError::overflow)?,
+    )?;
 
-    fn test_instantiate() {
-        let mut deps = mock_dependencies();
-        let env = mock_env();
-        let info = mock_info(ADDR1, &vec![]);
+    let fee_shares = mint_fee_shares(deps.storage, env.block.height, config, shares_out)?;
+    let credited = shares_out - fee_shares;
+    add_shares(deps.storage, env.block.height, depositor, credited)?;
 
-        let msg = InstantiateMsg {
-            owner_addr: ADDR1.to_string(),
-            token_addr: ADDR2.to_string(),
-        };
+    let managed = MANAGED_ASSETS.may_load(deps.storage)?.unwrap_or_default();
+    MANAGED_ASSETS.save(
+        deps.storage,
+        &managed.checked_add(net).map_err(StdError::overflow)?,
+    )?;
+    Ok(Credited {
+        shares: credited,
+        fee,
+        total_supply,
+    })
+}
 
-        let res = instantiate(deps.as_mut(), env, info, msg).unwrap();
+/// Shares minted for `amount` against the vault's current assets and supply. An empty vault
+/// mints one share per token. Rounds down, like `assets_for_shares`: every rounding goes to
+/// the vault, so the share price can only rise from it.
+fn shares_for_deposit(
+    amount: Uint128,
+    total_assets: Uint128,
+    total_supply: Uint128,
+) -> StdResult<Uint128> {
+    if total_supply.is_zero() {
+        return Ok(amount);
+    }
+    amount
+        .checked_mul(total_supply)
+        .map_err(StdError::overflow)?
+        .checked_div(total_assets)
+        .map_err(StdError::divide_by_zero)
+}
+
+/// Assets `share` redeems for against the vault's current assets and supply, rounded down.
+fn assets_for_shares(
+    share: Uint128,
+    total_assets: Uint128,
+    total_supply: Uint128,
+) -> StdResult<Uint128> {
+    share
+        .checked_mul(total_assets)
+        .map_err(StdError::overflow)?
+        .checked_div(total_supply)
+        .map_err(StdError::divide_by_zero)
+}
+
+/// Shares to redeem so the payout, after the share and withdraw fees, is at least `amount`.
+/// Every step rounds up, so the withdrawer never gets less than asked for.
+fn shares_for_assets(
+    config: &Config,
+    amount: Uint128,
+    total_assets: Uint128,
+    total_supply: Uint128,
+) -> StdResult<Uint128> {
+    let kept_bps = |bps: u16| match config.fee_recipient {
+        Some(_) => MAX_FEE_BPS - bps,
+        None => MAX_FEE_BPS,
+    };
+    let gross = ceil_ratio(
+        amount,
+        MAX_FEE_BPS.into(),
+        kept_bps(config.withdraw_fee_bps).into(),
+    )?;
+    let burned = ceil_ratio(gross, total_supply, total_assets)?;
+    ceil_ratio(
+        burned,
+        MAX_FEE_BPS.into(),
+        kept_bps(config.share_fee_bps).into(),
+    )
+}
+
+/// `value * numerator / denominator`, rounded up.
+fn ceil_ratio(value: Uint128, numerator: Uint128, denominator: Uint128) -> StdResult<Uint128> {
+    value
+        .checked_mul(numerator)
+        .map_err(StdError::overflow)?
+        .checked_add(denominator.saturating_sub(Uint128::one()))
+        .map_err(StdError::overflow)?
+        .checked_div(denominator)
+        .map_err(StdError::divide_by_zero)
+}
+
+/// Shares a deposit at `total_supply` locks away instead of crediting: `DEAD_SHARE_AMOUNT`
+/// for the first deposit into an empty vault, none after that.
+fn dead_shares_for(total_supply: Uint128) -> Uint128 {
+    if total_supply.is_zero() {
+        DEAD_SHARE_AMOUNT
+    } else {
+        Uint128::zero()
+    }
+}
+
+fn execute_capture_direct_deposits(deps: DepsMut, env: Env) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    let live = get_total_assets(deps.as_ref(), &env, &config)?;
+    let managed = MANAGED_ASSETS.may_load(deps.storage)?.unwrap_or_default();
+    let captured = live.saturating_sub(managed);
+
+    let mut response = Response::new();
+    if !captured.is_zero() {
+        MANAGED_ASSETS.save(deps.storage, &live)?;
+        // direct transfers land liquid, so put them to work alongside the rest
+        if let (Some(staking), AssetKind::Cw20(token)) = (&config.staking, &config.token) {
+            response = response.add_message(get_stake_msg(token, staking, captured)?);
+        }
+    }
+
+    Ok(response
+        .add_attribute("action", "capture_direct_deposits")
+        .add_attribute("captured", captured))
+}
+
+fn get_stake_msg(token: &Addr, staking: &Addr, amount: Uint128) -> StdResult<CosmosMsg> {
+    let stake_cw20 = Cw20ExecuteMsg::Send {
+        contract: staking.into(),
+        amount,
+        msg: to_json_binary(&StakingReceiveMsg::Stake {})?,
+    };
+    let stake_msg = WasmMsg::Execute {
+        contract_addr: token.into(),
+        msg: to_json_binary(&stake_cw20)?,
+        funds: vec![],
+    };
+    Ok(stake_msg.into())
+}
 
-        println!("Deployed {:?}", res);
+fn execute_migrate_position(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    target_vault: String,
+    share: Uint128,
+) -> Result<Response, ContractError> {
+    let target_vault = deps.api.addr_validate(&target_vault)?;
+    let config = CONFIG.load(deps.storage)?;
+    let target: VaultInfoResponse = deps
+        .querier
+        .query_wasm_smart(&target_vault, &QueryMsg::VaultInfo {})?;
+    if target.token != config.token {
+        return Err(ContractError::MigrationTokenMismatch {
+            target_vault: target_vault.into(),
+        });
+    }
+
+    let deposit = to_json_binary(&ReceiveMsg::Deposit {
+        recipient: Some(info.sender.to_string()),
+    })?;
+    let response = execute_withdraw(
+        deps,
+        env,
+        info,
+        Redeem::Shares(share),
+        Payout::Send(target_vault.clone(), deposit),
+    )?;
+    Ok(response
+        .add_attribute("action", "migrate_position")
+        .add_attribute("target_vault", target_vault))
+}
+
+/// What a withdrawal redeems.
+enum Redeem {
+    /// A share count, or an amount of the underlying in rebasing mode, as `Withdraw` reads it.
+    Shares(Uint128),
+    /// Enough shares to pay out this much of the underlying after fees.
+    Assets(Uint128),
+    /// Every share the sender holds.
+    All,
+}
+
+/// Where a withdrawal's proceeds go.
+enum Payout {
+    /// A plain cw20 `Transfer`, or a bank send for a native vault.
+    Transfer(Addr),
+    /// A cw20 `Send` to a contract, carrying its hook message.
+    Send(Addr, Binary),
+    /// Held in `WITHDRAW_QUEUE` for the sender until `withdraw_delay` has passed.
+    Queue,
+}
+
+/// Redeems the sender's shares as `redeem` says and pays the proceeds out as `payout` says.
+fn execute_withdraw(
+    mut deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    redeem: Redeem,
+    payout: Payout,
+) -> Result<Response, ContractError> {
+    if withdrawals_paused(deps.storage)? {
+        return Err(ContractError::Paused {});
+    }
+    accrue(&mut deps, &env)?;
+    let requested = match redeem {
+        Redeem::Shares(requested) | Redeem::Assets(requested) => requested,
+        Redeem::All => BALANCE_OF
+            .may_load(deps.storage, info.sender.clone())?
+            .unwrap_or_default(),
+    };
+    if requested.is_zero() {
+        return Err(ContractError::ZeroAmount {});
+    }
+    let config = waive_exempt_fees(deps.storage, CONFIG.load(deps.storage)?, &info.sender);
+    if config.withdraw_delay > 0 && !matches!(payout, Payout::Queue) {
+        return Err(ContractError::WithdrawalsQueued {
+            delay: config.withdraw_delay,
+        });
+    }
+
+    let mut total_supply = TOTAL_SUPPLY.load(deps.storage)?;
+
+    let liquid_bal = config
+        .token
+        .query_balance(deps.as_ref(), &env.contract.address)?
+        .saturating_sub(queued_assets(deps.storage)?);
+    let token_bal = match &config.staking {
+        Some(staking) => {
+            let staked =
+                get_staked_value(deps.as_ref(), env.contract.address.clone(), staking.clone())?;
+            liquid_bal.checked_add(staked).map_err(StdError::overflow)?
+        }
+        None => liquid_bal,
+    };
+    record_share_price(deps.storage, &env, token_bal, total_supply)?;
+    total_supply = total_supply
+        .checked_add(accrue_performance_fee(
+            deps.storage,
+            env.block.height,
+            &config,
+            token_bal,
+            total_supply,
+        )?)
+        .map_err(StdError::overflow)?;
+
+    // a request priced in the underlying cannot be met by a vault that holds none of it
+    let priced_in_assets = matches!(
+        (&redeem, &config.share_model),
+        (Redeem::Assets(_), _) | (Redeem::Shares(_), ShareModel::Rebasing)
+    );
+    if priced_in_assets && token_bal.is_zero() {
+        return Err(ContractError::InsufficientShares {
+            available: Uint128::zero(),
+            requested,
+        });
+    }
+    let share = match (redeem, &config.share_model) {
+        (Redeem::Assets(amount), _) => shares_for_assets(&config, amount, token_bal, total_supply)?,
+        (Redeem::Shares(share), ShareModel::Classic) => share,
+        (Redeem::All, _) => requested,
+        // rounded up so a rebasing withdraw never redeems more than the shares it burns
+        (Redeem::Shares(share), ShareModel::Rebasing) => share
+            .checked_mul(total_supply)
+            .map_err(StdError::overflow)?
+            .checked_add(token_bal.saturating_sub(Uint128::one()))
+            .map_err(StdError::overflow)?
+            .checked_div(token_bal)
+            .map_err(StdError::divide_by_zero)?,
+    };
+    if share > total_supply {
+        return Err(ContractError::InvalidShareAmount {
+            share,
+            total_supply,
+        });
+    }
+    // only shares the sender holds can be redeemed, so nobody reaches another holder's backing
+    let held = BALANCE_OF
+        .may_load(deps.storage, info.sender.clone())?
+        .unwrap_or_default();
+    if share > held {
+        return Err(ContractError::InsufficientShares {
+            available: held,
+            requested: share,
+        });
+    }
+
+    let deposited = DEPOSITED
+        .may_load(deps.storage, info.sender.clone())?
+        .unwrap_or_default();
+    let remaining = deposited - deposited.multiply_ratio(share, held);
+    if remaining.is_zero() {
+        DEPOSITED.remove(deps.storage, info.sender.clone());
+    } else {
+        DEPOSITED.save(deps.storage, info.sender.clone(), &remaining)?;
+    }
+
+    // fee shares the recipient withdraws stop counting as fee shares
+    if config.fee_recipient.as_ref() == Some(&info.sender) {
+        let total_fee_shares = FEE_SHARES.may_load(deps.storage)?.unwrap_or_default();
+        FEE_SHARES.save(deps.storage, &total_fee_shares.saturating_sub(share))?;
+    }
+    // the fee part of the withdrawn shares changes hands instead of being redeemed; for a
+    // queued request the vault holds it until claim, so a cancel can return it
+    let fee_shares = if matches!(payout, Payout::Queue) {
+        let fee_shares = fee_cut(&config, share);
+        add_shares(
+            deps.storage,
+            env.block.height,
+            &env.contract.address,
+            fee_shares,
+        )?;
+        fee_shares
+    } else {
+        mint_fee_shares(deps.storage, env.block.height, &config, share)?
+    };
+    let burned = share - fee_shares;
+
+    let amount = assets_for_shares(burned, token_bal, total_supply)?;
+    let principal = TOTAL_PRINCIPAL.may_load(deps.storage)?.unwrap_or_default();
+    TOTAL_PRINCIPAL.save(
+        deps.storage,
+        &(principal - principal.multiply_ratio(burned, total_supply)),
+    )?;
+
+    total_supply = total_supply
+        .checked_sub(burned)
+        .map_err(StdError::overflow)?;
+    TOTAL_SUPPLY.save(deps.storage, &total_supply)?;
+    let managed = MANAGED_ASSETS.may_load(deps.storage)?.unwrap_or_default();
+    MANAGED_ASSETS.save(deps.storage, &managed.saturating_sub(amount))?;
+    let balance = BALANCE_OF
+        .load(deps.storage, info.sender.clone())
+        .unwrap_or(Uint128::zero())
+        .checked_sub(share)
+        .map_err(|_| ContractError::InsufficientShares {
+            available: held,
+            requested: share,
+        })?;
+    save_balance(deps.storage, env.block.height, &info.sender, balance)?;
+
+    let mut fee = rounded_up_fee(&config, amount, config.withdraw_fee_bps)?;
+    let mut response = Response::new()
+        .add_attribute("action", "withdraw")
+        .add_attribute("sender", info.sender.clone())
+        .add_attribute("share", share)
+        .add_attribute("assets_returned", amount - fee)
+        .add_attribute("total_supply", total_supply);
+    let c_msg = match (payout, &config.token) {
+        (Payout::Transfer(recipient), token) => Some(token.transfer_msg(&recipient, amount - fee)?),
+        (Payout::Send(contract, msg), AssetKind::Cw20(token)) => Some(
+            WasmMsg::Execute {
+                contract_addr: token.into(),
+                msg: to_json_binary(&Cw20ExecuteMsg::Send {
+                    contract: contract.into(),
+                    amount: amount - fee,
+                    msg,
+                })?,
+                funds: vec![],
+            }
+            .into(),
+        ),
+        (Payout::Send(..), AssetKind::Native(_)) => {
+            return Err(ContractError::Cw20Only {
+                feature: "Paying out through a cw20 Send".to_string(),
+            })
+        }
+        (Payout::Queue, _) => {
+            let request_id = NEXT_WITHDRAW_ID.may_load(deps.storage)?.unwrap_or_default();
+            NEXT_WITHDRAW_ID.save(deps.storage, &(request_id + 1))?;
+            let unlock_at = env.block.time.seconds() + config.withdraw_delay;
+            // the fee waits with the request, so a cancel can hand it back to the vault
+            WITHDRAW_QUEUE.save(
+                deps.storage,
+                (info.sender.clone(), request_id),
+                &QueuedWithdrawal {
+                    unlock_at,
+                    amount,
+                    fee,
+                    shares: burned,
+                    fee_shares,
+                },
+            )?;
+            let total_queued = queued_assets(deps.storage)?;
+            QUEUED_ASSETS.save(
+                deps.storage,
+                &total_queued
+                    .checked_add(amount)
+                    .map_err(StdError::overflow)?,
+            )?;
+            response = response
+                .add_attribute("request_id", request_id.to_string())
+                .add_attribute("unlock_at", unlock_at.to_string());
+            fee = Uint128::zero();
+            None
+        }
+    };
+    let fee_msgs = fee_transfer_msg(&config, fee)?;
+
+    // only unstake what the liquid balance can't already cover
+    let unstake_amount = amount.saturating_sub(liquid_bal);
+    if let Some(staking) = config.staking {
+        if !unstake_amount.is_zero() {
+            let unstake_msg = WasmMsg::Execute {
+                contract_addr: staking.into(),
+                msg: to_json_binary(&StakingExecuteMsg::Unstake {
+                    amount: unstake_amount,
+                })?,
+                funds: vec![],
+            };
+            response = response.add_message(unstake_msg);
+        }
+    }
+
+    Ok(response.add_messages(c_msg).add_messages(fee_msgs))
+}
+
+fn execute_request_withdraw(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    share: Uint128,
+) -> Result<Response, ContractError> {
+    let response = execute_withdraw(deps, env, info, Redeem::Shares(share), Payout::Queue)?;
+    Ok(response.add_attribute("action", "request_withdraw"))
+}
+
+/// Pays out every queued withdrawal of the sender whose unlock time has passed, in one
+/// transfer. Requests still locked stay queued.
+fn execute_claim_withdraw(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+) -> Result<Response, ContractError> {
+    if withdrawals_paused(deps.storage)? {
+        return Err(ContractError::Paused {});
+    }
+    let config = CONFIG.load(deps.storage)?;
+
+    let now = env.block.time.seconds();
+    let (unlocked, locked): (Vec<_>, Vec<_>) = WITHDRAW_QUEUE
+        .prefix(info.sender.clone())
+        .range(deps.storage, None, None, Order::Ascending)
+        .collect::<StdResult<Vec<(u64, QueuedWithdrawal)>>>()?
+        .into_iter()
+        .partition(|(_, queued)| queued.unlock_at <= now);
+    if unlocked.is_empty() {
+        return Err(
+            match locked.iter().map(|(_, queued)| queued.unlock_at).min() {
+                Some(unlock_at) => ContractError::WithdrawNotReady { unlock_at },
+                None => ContractError::NothingToClaim {},
+            },
+        );
+    }
+
+    let mut released = Uint128::zero();
+    let mut fee = Uint128::zero();
+    let mut fee_shares = Uint128::zero();
+    for (request_id, queued) in unlocked {
+        WITHDRAW_QUEUE.remove(deps.storage, (info.sender.clone(), request_id));
+        released = released
+            .checked_add(queued.amount)
+            .map_err(StdError::overflow)?;
+        fee = fee.checked_add(queued.fee).map_err(StdError::overflow)?;
+        fee_shares = fee_shares
+            .checked_add(queued.fee_shares)
+            .map_err(StdError::overflow)?;
+    }
+    release_fee_shares(deps.storage, &env, &config, fee_shares)?;
+    let total_queued = queued_assets(deps.storage)?;
+    QUEUED_ASSETS.save(
+        deps.storage,
+        &total_queued
+            .checked_sub(released)
+            .map_err(StdError::overflow)?,
+    )?;
+    let amount = released - fee;
+
+    Ok(Response::new()
+        .add_message(config.token.transfer_msg(&info.sender, amount)?)
+        .add_messages(fee_transfer_msg(&config, fee)?)
+        .add_attribute("action", "claim_withdraw")
+        .add_attribute("sender", info.sender)
+        .add_attribute("amount", amount)
+        .add_attribute("still_queued", locked.len().to_string()))
+}
+
+fn execute_cancel_withdraw(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    request_id: u64,
+) -> Result<Response, ContractError> {
+    let key = (info.sender.clone(), request_id);
+    let queued = WITHDRAW_QUEUE
+        .may_load(deps.storage, key.clone())?
+        .ok_or(ContractError::NoWithdrawRequest { request_id })?;
+    let config = CONFIG.load(deps.storage)?;
+    // the tokens rejoin the vault like a deposit, so they pass the same gates
+    if deposits_paused(deps.storage)? {
+        return Err(ContractError::Paused {});
+    }
+    assert_allowlisted(deps.storage, &config, &info.sender)?;
+
+    let total_assets = get_total_assets(deps.as_ref(), &env, &config)?;
+    if let Some(cap) = config.deposit_cap {
+        let attempted = total_assets
+            .checked_add(queued.amount)
+            .map_err(StdError::overflow)?;
+        if attempted > cap {
+            return Err(ContractError::CapExceeded { cap, attempted });
+        }
+    }
+    let deposited = DEPOSITED
+        .may_load(deps.storage, info.sender.clone())?
+        .unwrap_or_default()
+        .checked_add(queued.amount)
+        .map_err(StdError::overflow)?;
+    if let Some(limit) = config.max_per_address {
+        if deposited > limit {
+            return Err(ContractError::AddressLimitExceeded {
+                address: info.sender.into(),
+                limit,
+                attempted: deposited,
+            });
+        }
+    }
+    DEPOSITED.save(deps.storage, info.sender.clone(), &deposited)?;
+
+    let mut total_supply = TOTAL_SUPPLY.load(deps.storage)?;
+    record_share_price(deps.storage, &env, total_assets, total_supply)?;
+    // growth while the request was queued is charged before its shares come back
+    total_supply = total_supply
+        .checked_add(accrue_performance_fee(
+            deps.storage,
+            env.block.height,
+            &config,
+            total_assets,
+            total_supply,
+        )?)
+        .map_err(StdError::overflow)?;
+
+    WITHDRAW_QUEUE.remove(deps.storage, key);
+    let total_queued = queued_assets(deps.storage)?;
+    QUEUED_ASSETS.save(
+        deps.storage,
+        &total_queued
+            .checked_sub(queued.amount)
+            .map_err(StdError::overflow)?,
+    )?;
+    let managed = MANAGED_ASSETS.may_load(deps.storage)?.unwrap_or_default();
+    MANAGED_ASSETS.save(
+        deps.storage,
+        &managed
+            .checked_add(queued.amount)
+            .map_err(StdError::overflow)?,
+    )?;
+    let principal = TOTAL_PRINCIPAL.may_load(deps.storage)?.unwrap_or_default();
+    TOTAL_PRINCIPAL.save(
+        deps.storage,
+        &principal
+            .checked_add(queued.amount)
+            .map_err(StdError::overflow)?,
+    )?;
+
+    // exactly the shares the request burned, whatever the share price is now, plus the
+    // share fee the vault was holding for it
+    total_supply = total_supply
+        .checked_add(queued.shares)
+        .map_err(StdError::overflow)?;
+    TOTAL_SUPPLY.save(deps.storage, &total_supply)?;
+    sub_shares(
+        deps.storage,
+        env.block.height,
+        &env.contract.address,
+        queued.fee_shares,
+    )?;
+    add_shares(
+        deps.storage,
+        env.block.height,
+        &info.sender,
+        queued.shares + queued.fee_shares,
+    )?;
+
+    Ok(Response::new()
+        .add_attribute("action", "cancel_withdraw")
+        .add_attribute("sender", info.sender)
+        .add_attribute("request_id", request_id.to_string())
+        .add_attribute("amount", queued.amount)
+        .add_attribute("shares_minted", queued.shares)
+        .add_attribute("fee_shares_returned", queued.fee_shares)
+        .add_attribute("total_supply", total_supply))
+}
+
+fn execute_transfer_shares(
+    deps: DepsMut,
+    env: Env,
+    info: MessageInfo,
+    recipient: String,
+    amount: Uint128,
+) -> Result<Response, ContractError> {
+    if amount.is_zero() {
+        return Err(ContractError::ZeroAmount {});
+    }
+    let recipient = deps.api.addr_validate(&recipient)?;
+    let config = CONFIG.load(deps.storage)?;
+    assert_allowlisted(deps.storage, &config, &recipient)?;
+
+    let share = match config.share_model {
+        ShareModel::Classic => amount,
+        // rounded up like a rebasing withdraw, so the sender never moves more than it pays for
+        ShareModel::Rebasing => {
+            let total_supply = TOTAL_SUPPLY.load(deps.storage)?;
+            let total_assets = get_total_assets(deps.as_ref(), &env, &config)?;
+            ceil_ratio(amount, total_supply, total_assets)?
+        }
+    };
+    let held = BALANCE_OF
+        .may_load(deps.storage, info.sender.clone())?
+        .unwrap_or_default();
+    let remaining = held
+        .checked_sub(share)
+        .map_err(|_| ContractError::InsufficientShares {
+            available: held,
+            requested: share,
+        })?;
+    save_balance(deps.storage, env.block.height, &info.sender, remaining)?;
+    add_shares(deps.storage, env.block.height, &recipient, share)?;
+
+    let deposited = DEPOSITED
+        .may_load(deps.storage, info.sender.clone())?
+        .unwrap_or_default();
+    let moved = deposited.multiply_ratio(share, held);
+    if moved == deposited {
+        DEPOSITED.remove(deps.storage, info.sender.clone());
+    } else {
+        DEPOSITED.save(deps.storage, info.sender.clone(), &(deposited - moved))?;
+    }
+    if !moved.is_zero() {
+        DEPOSITED.update(deps.storage, recipient.clone(), |deposited| {
+            deposited
+                .unwrap_or_default()
+                .checked_add(moved)
+                .map_err(StdError::overflow)
+        })?;
+    }
+
+    // fee shares handed on become ordinary shares, as when the recipient withdraws them
+    if config.fee_recipient.as_ref() == Some(&info.sender) && recipient != info.sender {
+        let total_fee_shares = FEE_SHARES.may_load(deps.storage)?.unwrap_or_default();
+        FEE_SHARES.save(deps.storage, &total_fee_shares.saturating_sub(share))?;
+    }
+
+    Ok(Response::new()
+        .add_attribute("action", "transfer_shares")
+        .add_attribute("from", info.sender)
+        .add_attribute("to", recipient)
+        .add_attribute("share", share))
+}
+
+/// Sets `address`'s share balance and records it in `SNAPSHOTS` at `height`. A zero balance
+/// drops the holder, so enumeration never sees one.
+fn save_balance(
+    storage: &mut dyn Storage,
+    height: u64,
+    address: &Addr,
+    balance: Uint128,
+) -> StdResult<()> {
+    let held = BALANCE_OF.has(storage, address.clone());
+    if balance.is_zero() {
+        BALANCE_OF.remove(storage, address.clone());
+    } else {
+        BALANCE_OF.save(storage, address.clone(), &balance)?;
+    }
+    if held == balance.is_zero() {
+        if let Some(count) = HOLDER_COUNT.may_load(storage)? {
+            let count = if held {
+                count.saturating_sub(1)
+            } else {
+                count + 1
+            };
+            HOLDER_COUNT.save(storage, &count)?;
+        }
+    }
+    SNAPSHOTS.save(storage, (address.clone(), height), &balance)
+}
+
+/// Adds `shares` to `address`'s balance through `save_balance`.
+fn add_shares(
+    storage: &mut dyn Storage,
+    height: u64,
+    address: &Addr,
+    shares: Uint128,
+) -> StdResult<()> {
+    let balance = BALANCE_OF
+        .may_load(storage, address.clone())?
+        .unwrap_or_default()
+        .checked_add(shares)
+        .map_err(StdError::overflow)?;
+    save_balance(storage, height, address, balance)
+}
+
+/// Takes `shares` from `address`'s balance through `save_balance`.
+fn sub_shares(
+    storage: &mut dyn Storage,
+    height: u64,
+    address: &Addr,
+    shares: Uint128,
+) -> StdResult<()> {
+    let balance = BALANCE_OF
+        .may_load(storage, address.clone())?
+        .unwrap_or_default()
+        .checked_sub(shares)
+        .map_err(StdError::overflow)?;
+    save_balance(storage, height, address, balance)
+}
+
+/// Hands share fees the vault held for claimed requests to the fee recipient. With the
+/// recipient since removed, they are burned instead and their value stays with the holders.
+fn release_fee_shares(
+    storage: &mut dyn Storage,
+    env: &Env,
+    config: &Config,
+    shares: Uint128,
+) -> StdResult<()> {
+    if shares.is_zero() {
+        return Ok(());
+    }
+    sub_shares(storage, env.block.height, &env.contract.address, shares)?;
+    match &config.fee_recipient {
+        Some(recipient) => {
+            add_shares(storage, env.block.height, recipient, shares)?;
+            add_fee_shares(storage, shares)
+        }
+        None => {
+            let total_supply = TOTAL_SUPPLY
+                .load(storage)?
+                .checked_sub(shares)
+                .map_err(StdError::overflow)?;
+            TOTAL_SUPPLY.save(storage, &total_supply)
+        }
+    }
+}
+
+/// Credits `fee_recipient` with the configured fraction of `shares` and returns how many
+/// shares that was. Fees stay in the vault as shares, so no tokens move when they accrue.
+fn mint_fee_shares(
+    storage: &mut dyn Storage,
+    height: u64,
+    config: &Config,
+    shares: Uint128,
+) -> Result<Uint128, ContractError> {
+    let recipient = match &config.fee_recipient {
+        Some(recipient) => recipient,
+        None => return Ok(Uint128::zero()),
+    };
+    let fee_shares = fee_cut(config, shares);
+    if !fee_shares.is_zero() {
+        add_shares(storage, height, recipient, fee_shares)?;
+        add_fee_shares(storage, fee_shares)?;
+    }
+    Ok(fee_shares)
+}
+
+fn add_fee_shares(storage: &mut dyn Storage, shares: Uint128) -> StdResult<()> {
+    let total_fee_shares = FEE_SHARES
+        .may_load(storage)?
+        .unwrap_or_default()
+        .checked_add(shares)
+        .map_err(StdError::overflow)?;
+    FEE_SHARES.save(storage, &total_fee_shares)
+}
+
+/// Brings yield the vault is owed into its balance before anything is priced against it.
+/// The vault holds nothing that pays out later yet, so there is nothing to settle; a
+/// strategy with pending rewards realizes them here.
+fn accrue(_deps: &mut DepsMut, _env: &Env) -> Result<(), ContractError> {
+    Ok(())
+}
+
+fn execute_accrue(mut deps: DepsMut, env: Env) -> Result<Response, ContractError> {
+    accrue(&mut deps, &env)?;
+    Ok(Response::new().add_attribute("action", "accrue"))
+}
+
+fn execute_collect_fees(deps: DepsMut, env: Env) -> Result<Response, ContractError> {
+    let config = CONFIG.load(deps.storage)?;
+    let total_assets = get_total_assets(deps.as_ref(), &env, &config)?;
+    let total_supply = TOTAL_SUPPLY.load(deps.storage)?;
+    let fee_shares = accrue_performance_fee(
+        deps.storage,
+        env.block.height,
+        &config,
+        total_assets,
+        total_supply,
+    )?;
+
+    Ok(Response::new()
+        .add_attribute("action", "collect_fees")
+        .add_attribute("fee_shares", fee_shares)
+        .add_attribute(
+            "high_water_mark",
+            high_water_mark(deps.storage)?.to_string(),
+        ))
+}
+
+fn high_water_mark(storage: &dyn Storage) -> StdResult<Decimal> {
+    Ok(HIGH_WATER_MARK
+        .may_load(storage)?
+        .unwrap_or_else(Decimal::one))
+}
+
+/// Shares that pay the performance fee on the share price's growth above `mark`: once
+/// minted, they are worth `performance_fee_bps` of that growth across the whole supply.
+fn performance_fee_shares(
+    config: &Config,
+    mark: Decimal,
+    total_assets: Uint128,
+    total_supply: Uint128,
+) -> StdResult<Uint128> {
+    if total_supply.is_zero() {
+        return Ok(Uint128::zero());
+    }
+    let gain = total_assets.saturating_sub(total_supply.mul_ceil(mark));
+    let fee = token_fee(config, gain, config.performance_fee_bps);
+    if fee.is_zero() {
+        return Ok(Uint128::zero());
+    }
+    Ok(total_supply.multiply_ratio(fee, total_assets - fee))
+}
+
+/// Mints the performance fee owed at `total_assets` and `total_supply` to `fee_recipient`
+/// and raises `HIGH_WATER_MARK` to the share price after it. Returns the shares minted,
+/// which the caller adds to its own copy of the supply.
+fn accrue_performance_fee(
+    storage: &mut dyn Storage,
+    height: u64,
+    config: &Config,
+    total_assets: Uint128,
+    total_supply: Uint128,
+) -> StdResult<Uint128> {
+    let recipient = match &config.fee_recipient {
+        Some(recipient) => recipient,
+        None => return Ok(Uint128::zero()),
+    };
+    let mark = high_water_mark(storage)?;
+    let fee_shares = performance_fee_shares(config, mark, total_assets, total_supply)?;
+    // growth too small to pay for a share keeps accruing against the old mark
+    if fee_shares.is_zero() {
+        return Ok(fee_shares);
+    }
+    add_shares(storage, height, recipient, fee_shares)?;
+    add_fee_shares(storage, fee_shares)?;
+    let total_supply = total_supply
+        .checked_add(fee_shares)
+        .map_err(StdError::overflow)?;
+    TOTAL_SUPPLY.save(storage, &total_supply)?;
+    HIGH_WATER_MARK.save(storage, &Decimal::from_ratio(total_assets, total_supply))?;
+    Ok(fee_shares)
+}
+
+/// `config` with the share, deposit and withdraw fees zeroed when `address` is in
+/// `FEE_EXEMPT`. The performance fee is left as is.
+fn waive_exempt_fees(storage: &dyn Storage, mut config: Config, address: &Addr) -> Config {
+    if FEE_EXEMPT.has(storage, address.clone()) {
+        config.share_fee_bps = 0;
+        config.deposit_fee_bps = 0;
+        config.withdraw_fee_bps = 0;
+    }
+    config
+}
+
+/// The part of `shares` taken as fee shares, zero when no fee recipient is configured.
+fn fee_cut(config: &Config, shares: Uint128) -> Uint128 {
+    token_fee(config, shares, config.share_fee_bps)
+}
+
+/// The part of `amount` taken at `bps`, zero when no fee recipient is configured.
+fn token_fee(config: &Config, amount: Uint128, bps: u16) -> Uint128 {
+    if config.fee_recipient.is_none() {
+        return Uint128::zero();
+    }
+    amount.multiply_ratio(bps, MAX_FEE_BPS)
+}
+
+/// `token_fee` rounded up, for the deposit and withdraw fees, so splitting a transfer into
+/// dust cannot dodge them: any non-zero amount pays at least one unit once `bps` is set.
+fn rounded_up_fee(config: &Config, amount: Uint128, bps: u16) -> StdResult<Uint128> {
+    if config.fee_recipient.is_none() {
+        return Ok(Uint128::zero());
+    }
+    ceil_ratio(amount, bps.into(), MAX_FEE_BPS.into())
+}
+
+/// Forwards a token fee to the fee recipient; nothing when the fee is zero.
+fn fee_transfer_msg(config: &Config, fee: Uint128) -> StdResult<Vec<CosmosMsg>> {
+    let recipient = match &config.fee_recipient {
+        Some(recipient) if !fee.is_zero() => recipient,
+        _ => return Ok(vec![]),
+    };
+    Ok(vec![config.token.transfer_msg(recipient, fee)?])
+}
+
+/// A cw20 address or native denom, as it appears in attributes.
+fn asset_attr(asset: &AssetKind) -> String {
+    match asset {
+        AssetKind::Native(denom) => denom.clone(),
+        AssetKind::Cw20(token) => token.to_string(),
+    }
+}
+
+/// Value backing the shares: the contract's liquid token balance plus, in staking mode,
+/// the value of its staked position. Queued withdrawals are already owed, so their tokens
+/// are left out.
+pub fn get_total_assets(deps: Deps, env: &Env, config: &Config) -> StdResult<Uint128> {
+    let liquid_bal = config
+        .token
+        .query_balance(deps, &env.contract.address)?
+        .saturating_sub(queued_assets(deps.storage)?);
+    match &config.staking {
+        Some(staking) => {
+            let staked = get_staked_value(deps, env.contract.address.clone(), staking.clone())?;
+            liquid_bal.checked_add(staked).map_err(StdError::overflow)
+        }
+        None => Ok(liquid_bal),
+    }
+}
+
+pub fn get_staked_value(
+    deps: Deps,
+    user_address: Addr,
+    staking_contract_addr: Addr,
+) -> StdResult<Uint128> {
+    let resp: StakedValueResponse = deps.querier.query_wasm_smart(
+        staking_contract_addr,
+        &StakingQueryMsg::StakedValue {
+            address: user_address.to_string(),
+        },
+    )?;
+    Ok(resp.value)
+}
+
+fn record_share_price(
+    storage: &mut dyn Storage,
+    env: &Env,
+    total_assets: Uint128,
+    total_supply: Uint128,
+) -> StdResult<()> {
+    if total_supply.is_zero() {
+        return Ok(());
+    }
+    let price = Decimal::from_ratio(total_assets, total_supply);
+    SHARE_PRICE_SNAPSHOTS.save(storage, env.block.time.seconds(), &price)
+}
+
+pub fn get_token_balance_of(
+    deps: Deps,
+    user_address: Addr,
+    cw20_contract_addr: Addr,
+) -> StdResult<Uint128> {
+    AssetKind::Cw20(cw20_contract_addr).query_balance(deps, &user_address)
+}
+
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
+    match msg {
+        QueryMsg::GetTotalSupply {} => get_total_supply(deps, env),
+        QueryMsg::GetBalanceOf { address } => get_balance_of(deps, env, address),
+        QueryMsg::GetUserValue { address } => to_json_binary(&user_value(deps, env, address)?),
+        QueryMsg::BalanceAt { address, height } => {
+            to_json_binary(&balance_at(deps, address, height)?)
+        }
+        QueryMsg::AllBalances { start_after, limit } => {
+            to_json_binary(&all_balances(deps, env, start_after, limit)?)
+        }
+        QueryMsg::CirculatingSupply {} => to_json_binary(&circulating_supply(deps, env)?),
+        QueryMsg::TwapSharePrice { window_seconds } => {
+            to_json_binary(&get_twap_share_price(deps, env, window_seconds)?)
+        }
+        QueryMsg::CanWithdraw { address } => to_json_binary(&can_withdraw(deps, env, address)?),
+        QueryMsg::Describe {} => to_json_binary(&describe(deps)?),
+        QueryMsg::ContractInfo {} => to_json_binary(&get_contract_version(deps.storage)?),
+        QueryMsg::VaultInfo {} => to_json_binary(&vault_info(deps)?),
+        QueryMsg::GetConfig {} => to_json_binary(&CONFIG.load(deps.storage)?),
+        QueryMsg::MaxDeposit { address } => to_json_binary(&max_deposit(deps, env, address)?),
+        QueryMsg::PreviewDeposit { amount } => to_json_binary(&preview_deposit(deps, env, amount)?),
+        QueryMsg::MaxRedeem { address } => to_json_binary(&max_redeem(deps, address)?),
+        QueryMsg::MaxWithdraw { address } => to_json_binary(&max_withdraw(deps, env, address)?),
+        QueryMsg::ConvertToAssets { share } => {
+            to_json_binary(&convert_to_assets(deps, env, share)?)
+        }
+        QueryMsg::SharesForAssets { assets } => {
+            to_json_binary(&convert_to_shares(deps, env, assets)?)
+        }
+        QueryMsg::TotalAssets {} => {
+            let config = CONFIG.load(deps.storage)?;
+            to_json_binary(&get_total_assets(deps, &env, &config)?)
+        }
+        QueryMsg::ReferralVolume { referrer } => to_json_binary(
+            &REFERRALS
+                .may_load(deps.storage, referrer)?
+                .unwrap_or_default(),
+        ),
+        QueryMsg::TotalPrincipal {} => {
+            to_json_binary(&TOTAL_PRINCIPAL.may_load(deps.storage)?.unwrap_or_default())
+        }
+        QueryMsg::PricePerShare {} => to_json_binary(&price_per_share(deps, env)?),
+        QueryMsg::FeeDrag { horizon_seconds } => to_json_binary(&fee_drag(deps, horizon_seconds)?),
+        QueryMsg::BreakEven { address } => to_json_binary(&break_even(deps, env, address)?),
+        QueryMsg::VerifyInvariant {} => to_json_binary(&verify_invariant(deps)?),
+        QueryMsg::Stats {} => to_json_binary(&stats(deps, env)?),
+        QueryMsg::PendingWithdrawals { address } => {
+            to_json_binary(&pending_withdrawals(deps, env, address)?)
+        }
+    }
+}
+
+const SECONDS_PER_YEAR: u64 = 365 * 24 * 60 * 60;
+
+fn fee_drag(deps: Deps, horizon_seconds: u64) -> StdResult<FeeDragResponse> {
+    if horizon_seconds == 0 {
+        return Err(StdError::generic_err("horizon_seconds must be positive"));
+    }
+    let config = CONFIG.load(deps.storage)?;
+    let performance_fee = fee_rate(&config, config.performance_fee_bps);
+
+    let total_drag = Decimal::one() - round_trip_kept(&config);
+    let annualized_drag = total_drag * Decimal::from_ratio(SECONDS_PER_YEAR, horizon_seconds);
+
+    Ok(FeeDragResponse {
+        deposit_fee: Decimal::one() - leg_kept(&config, config.deposit_fee_bps),
+        withdraw_fee: Decimal::one() - leg_kept(&config, config.withdraw_fee_bps),
+        performance_fee,
+        total_drag,
+        annualized_drag,
+    })
+}
+
+/// Mirrors `token_fee`: fees only apply once a recipient is configured.
+fn fee_rate(config: &Config, bps: u16) -> Decimal {
+    match config.fee_recipient {
+        Some(_) => Decimal::from_ratio(bps, MAX_FEE_BPS),
+        None => Decimal::zero(),
+    }
+}
+
+/// Fraction of what goes into a deposit or withdraw that comes out of it: the token fee
+/// (`token_fee_bps`) is taken first and the share fee from what is left.
+fn leg_kept(config: &Config, token_fee_bps: u16) -> Decimal {
+    (Decimal::one() - fee_rate(config, token_fee_bps))
+        * (Decimal::one() - fee_rate(config, config.share_fee_bps))
+}
+
+/// Fraction of a deposit that survives a deposit and withdraw, each fee applying to what
+/// is left after the previous one.
+fn round_trip_kept(config: &Config) -> Decimal {
+    leg_kept(config, config.deposit_fee_bps) * leg_kept(config, config.withdraw_fee_bps)
+}
+
+fn break_even(deps: Deps, env: Env, address: Addr) -> StdResult<BreakEvenResponse> {
+    let config = CONFIG.load(deps.storage)?;
+    let balance = BALANCE_OF
+        .may_load(deps.storage, address)?
+        .unwrap_or_default();
+    let position_value = if balance.is_zero() {
+        Uint128::zero()
+    } else {
+        let total_supply = TOTAL_SUPPLY.load(deps.storage)?;
+        let total_assets = get_total_assets(deps, &env, &config)?;
+        balance.multiply_ratio(total_assets, total_supply)
+    };
+
+    let kept = round_trip_kept(&config);
+    let break_even = Decimal::one()
+        .checked_div(kept)
+        .map_err(|_| StdError::generic_err("a 100% fee can never break even"))?
+        - Decimal::one();
+
+    Ok(BreakEvenResponse {
+        position_value,
+        round_trip_cost: position_value * (Decimal::one() - kept),
+        break_even,
+    })
+}
+
+fn vault_info(deps: Deps) -> StdResult<VaultInfoResponse> {
+    let config = CONFIG.load(deps.storage)?;
+    Ok(VaultInfoResponse {
+        token: config.token,
+        owner: config.owner,
+        metadata_uri: config.metadata_uri,
+    })
+}
+
+fn max_deposit(deps: Deps, env: Env, address: Addr) -> StdResult<Uint128> {
+    let config = CONFIG.load(deps.storage)?;
+    if deposits_paused(deps.storage)? {
+        return Ok(Uint128::zero());
+    }
+    if config.private_mode
+        && !DEPOSIT_ALLOWLIST
+            .may_load(deps.storage, address.clone())?
+            .unwrap_or_default()
+    {
+        return Ok(Uint128::zero());
+    }
+
+    let total_supply = TOTAL_SUPPLY.load(deps.storage)?;
+    // share minting multiplies the amount by the supply before dividing, so that product
+    // has to fit in a Uint128
+    let mint_ceiling = Uint128::MAX
+        .checked_div(total_supply)
+        .unwrap_or(Uint128::MAX);
+    let mut max = mint_ceiling;
+    if let Some(cap) = config.deposit_cap {
+        max = max.min(cap.saturating_sub(get_total_assets(deps, &env, &config)?));
+    }
+    if let Some(limit) = config.max_per_address {
+        let deposited = DEPOSITED
+            .may_load(deps.storage, address)?
+            .unwrap_or_default();
+        max = max.min(limit.saturating_sub(deposited));
+    }
+    Ok(max)
+}
+
+/// Runs the deposit share math against the current state without changing it.
+fn preview_deposit(deps: Deps, env: Env, amount: Uint128) -> StdResult<Uint128> {
+    let config = CONFIG.load(deps.storage)?;
+    let total_supply = TOTAL_SUPPLY.load(deps.storage)?;
+    let total_assets = if total_supply.is_zero() {
+        Uint128::zero()
+    } else {
+        get_total_assets(deps, &env, &config)?
+    };
+    let total_supply = total_supply
+        .checked_add(performance_fee_shares(
+            &config,
+            high_water_mark(deps.storage)?,
+            total_assets,
+            total_supply,
+        )?)
+        .map_err(StdError::overflow)?;
+    let net = amount - rounded_up_fee(&config, amount, config.deposit_fee_bps)?;
+    // a first deposit too small to cover the dead shares would fail, so it previews as zero
+    let shares = shares_for_deposit(net, total_assets, total_supply)?
+        .saturating_sub(dead_shares_for(total_supply));
+    Ok(shares - fee_cut(&config, shares))
+}
+
+fn max_redeem(deps: Deps, address: Addr) -> StdResult<Uint128> {
+    if withdrawals_paused(deps.storage)? {
+        return Ok(Uint128::zero());
+    }
+    Ok(BALANCE_OF
+        .may_load(deps.storage, address)?
+        .unwrap_or_default())
+}
+
+/// Runs the withdraw math for the `max_redeem` shares against the current state.
+fn max_withdraw(deps: Deps, env: Env, address: Addr) -> StdResult<Uint128> {
+    let share = max_redeem(deps, address)?;
+    if share.is_zero() {
+        return Ok(Uint128::zero());
+    }
+    let config = CONFIG.load(deps.storage)?;
+    let total_supply = TOTAL_SUPPLY.load(deps.storage)?;
+    let total_assets = get_total_assets(deps, &env, &config)?;
+    let total_supply = total_supply
+        .checked_add(performance_fee_shares(
+            &config,
+            high_water_mark(deps.storage)?,
+            total_assets,
+            total_supply,
+        )?)
+        .map_err(StdError::overflow)?;
+    let burned = share - fee_cut(&config, share);
+    let amount = assets_for_shares(burned, total_assets, total_supply)?;
+    Ok(amount - rounded_up_fee(&config, amount, config.withdraw_fee_bps)?)
+}
+
+/// What `share` shares redeem for at the current share price, before fees. Nothing is worth
+/// anything while no shares exist.
+fn convert_to_assets(deps: Deps, env: Env, share: Uint128) -> StdResult<Uint128> {
+    let total_supply = TOTAL_SUPPLY.load(deps.storage)?;
+    if total_supply.is_zero() {
+        return Ok(Uint128::zero());
+    }
+    let config = CONFIG.load(deps.storage)?;
+    let total_assets = get_total_assets(deps, &env, &config)?;
+    Ok(share.multiply_ratio(total_assets, total_supply))
+}
+
+/// What `assets` of the underlying is worth in shares at the current share price, before
+/// fees, rounded down. Unlike `preview_deposit` no dead shares or fees are taken off.
+fn convert_to_shares(deps: Deps, env: Env, assets: Uint128) -> StdResult<Uint128> {
+    let total_supply = TOTAL_SUPPLY.load(deps.storage)?;
+    if total_supply.is_zero() {
+        return Ok(assets);
+    }
+    let config = CONFIG.load(deps.storage)?;
+    let total_assets = get_total_assets(deps, &env, &config)?;
+    shares_for_deposit(assets, total_assets, total_supply)
+}
+
+fn user_value(deps: Deps, env: Env, address: Addr) -> StdResult<UserValueResponse> {
+    let shares = BALANCE_OF
+        .may_load(deps.storage, address)?
+        .unwrap_or_default();
+    Ok(UserValueResponse {
+        shares,
+        value: convert_to_assets(deps, env, shares)?,
+    })
+}
+
+fn price_per_share(deps: Deps, env: Env) -> StdResult<Decimal> {
+    let total_supply = TOTAL_SUPPLY.load(deps.storage)?;
+    if total_supply.is_zero() {
+        return Ok(Decimal::one());
+    }
+    let config = CONFIG.load(deps.storage)?;
+    let total_assets = get_total_assets(deps, &env, &config)?;
+    Ok(Decimal::from_ratio(total_assets, total_supply))
+}
+
+fn describe(deps: Deps) -> StdResult<DescribeResponse> {
+    let config = CONFIG.load(deps.storage)?;
+    Ok(DescribeResponse {
+        contract_info: get_contract_version(deps.storage)?,
+        features: active_features(&config),
+    })
+}
+
+fn active_features(config: &Config) -> Vec<String> {
+    let mut features = vec![];
+    if config.staking.is_some() {
+        features.push("lp_staking".to_string());
+    }
+    if config.fee_recipient.is_some() && config.share_fee_bps > 0 {
+        features.push("share_fee".to_string());
+    }
+    if config.fee_recipient.is_some() && config.deposit_fee_bps > 0 {
+        features.push("deposit_fee".to_string());
+    }
+    if config.fee_recipient.is_some() && config.withdraw_fee_bps > 0 {
+        features.push("withdraw_fee".to_string());
+    }
+    if config.fee_recipient.is_some() && config.performance_fee_bps > 0 {
+        features.push("performance_fee".to_string());
+    }
+    if config.share_model == ShareModel::Rebasing {
+        features.push("rebasing".to_string());
+    }
+    if config.private_mode {
+        features.push("private_mode".to_string());
+    }
+    if matches!(config.token, AssetKind::Native(_)) {
+        features.push("native_asset".to_string());
+    }
+    if config.withdraw_delay > 0 {
+        features.push("withdraw_queue".to_string());
+    }
+    features
+}
+
+fn get_total_supply(deps: Deps, env: Env) -> StdResult<Binary> {
+    let total = TOTAL_SUPPLY.load(deps.storage)?;
+
+    to_json_binary(&TotalSupplyResponse {
+        total_supply: present_shares(deps, &env, total)?,
+    })
+}
+
+fn get_balance_of(deps: Deps, env: Env, address: Addr) -> StdResult<Binary> {
+    let balance = BALANCE_OF
+        .may_load(deps.storage, address)?
+        .unwrap_or_default();
+
+    to_json_binary(&BalanceResponse {
+        balance: present_shares(deps, &env, balance)?,
+    })
+}
+
+/// The latest snapshot of `address`'s balance at or before `height`.
+fn balance_at(deps: Deps, address: Addr, height: u64) -> StdResult<Uint128> {
+    SNAPSHOTS
+        .prefix(address)
+        .range(
+            deps.storage,
+            None,
+            Some(Bound::inclusive(height)),
+            Order::Descending,
+        )
+        .next()
+        .transpose()
+        .map(|snapshot| snapshot.map(|(_, balance)| balance).unwrap_or_default())
+}
+
+const DEFAULT_BALANCES_LIMIT: u32 = 10;
+const MAX_BALANCES_LIMIT: u32 = 30;
+
+fn all_balances(
+    deps: Deps,
+    env: Env,
+    start_after: Option<String>,
+    limit: Option<u32>,
+) -> StdResult<Vec<(Addr, Uint128)>> {
+    let limit = limit
+        .unwrap_or(DEFAULT_BALANCES_LIMIT)
+        .min(MAX_BALANCES_LIMIT) as usize;
+    let start = start_after
+        .map(|address| deps.api.addr_validate(&address))
+        .transpose()?
+        .map(Bound::exclusive);
+    BALANCE_OF
+        .range(deps.storage, start, None, Order::Ascending)
+        .take(limit)
+        .map(|entry| {
+            let (address, balance) = entry?;
+            Ok((address, present_shares(deps, &env, balance)?))
+        })
+        .collect()
+}
+
+fn stats(deps: Deps, env: Env) -> StdResult<StatsResponse> {
+    let config = CONFIG.load(deps.storage)?;
+    let total_supply = TOTAL_SUPPLY.load(deps.storage)?;
+    let holder_count = match HOLDER_COUNT.may_load(deps.storage)? {
+        Some(count) => count,
+        None => count_holders(deps.storage)?,
+    };
+    Ok(StatsResponse {
+        total_supply: present_shares(deps, &env, total_supply)?,
+        holder_count,
+        total_assets: get_total_assets(deps, &env, &config)?,
+    })
+}
+
+fn pending_withdrawals(
+    deps: Deps,
+    env: Env,
+    address: Addr,
+) -> StdResult<PendingWithdrawalsResponse> {
+    let now = env.block.time.seconds();
+    let withdrawals = WITHDRAW_QUEUE
+        .prefix(address)
+        .range(deps.storage, None, None, Order::Ascending)
+        .map(|item| {
+            let (request_id, queued) = item?;
+            Ok(PendingWithdrawal {
+                request_id,
+                amount: queued.amount - queued.fee,
+                unlock_time: queued.unlock_at,
+                claimable: queued.unlock_at <= now,
+            })
+        })
+        .collect::<StdResult<_>>()?;
+    Ok(PendingWithdrawalsResponse { withdrawals })
+}
+
+/// Walks every balance; `HOLDER_COUNT` is the cheap answer where it exists.
+fn count_holders(storage: &dyn Storage) -> StdResult<u64> {
+    Ok(BALANCE_OF
+        .keys(storage, None, None, Order::Ascending)
+        .count() as u64)
+}
+
+fn verify_invariant(deps: Deps) -> StdResult<InvariantResponse> {
+    let total_supply = TOTAL_SUPPLY.load(deps.storage)?;
+    let dead_shares = DEAD_SHARES.may_load(deps.storage)?.unwrap_or_default();
+    let balances_total = BALANCE_OF
+        .range(deps.storage, None, None, Order::Ascending)
+        .try_fold(Uint128::zero(), |sum, entry| {
+            let (_, balance) = entry?;
+            Ok::<_, StdError>(sum.checked_add(balance)?)
+        })?;
+    Ok(InvariantResponse {
+        total_supply,
+        balances_total,
+        dead_shares,
+        consistent: balances_total.checked_add(dead_shares).ok() == Some(total_supply),
+    })
+}
+
+fn circulating_supply(deps: Deps, env: Env) -> StdResult<CirculatingSupplyResponse> {
+    let total_supply = TOTAL_SUPPLY.load(deps.storage)?;
+    let fee_shares = FEE_SHARES.may_load(deps.storage)?.unwrap_or_default();
+    let dead_shares = DEAD_SHARES.may_load(deps.storage)?.unwrap_or_default();
+    let circulating = total_supply
+        .checked_sub(fee_shares)
+        .and_then(|supply| supply.checked_sub(dead_shares))
+        .map_err(StdError::overflow)?;
+    Ok(CirculatingSupplyResponse {
+        total_supply: present_shares(deps, &env, total_supply)?,
+        fee_shares: present_shares(deps, &env, fee_shares)?,
+        dead_shares: present_shares(deps, &env, dead_shares)?,
+        circulating_supply: present_shares(deps, &env, circulating)?,
+    })
+}
+
+/// Shares as holders see them: unchanged in classic mode, their underlying value in rebasing
+/// mode.
+fn present_shares(deps: Deps, env: &Env, shares: Uint128) -> StdResult<Uint128> {
+    let config = CONFIG.load(deps.storage)?;
+    if config.share_model == ShareModel::Classic || shares.is_zero() {
+        return Ok(shares);
+    }
+    let total_supply = TOTAL_SUPPLY.load(deps.storage)?;
+    let total_assets = get_total_assets(deps, env, &config)?;
+    Ok(shares.multiply_ratio(total_assets, total_supply))
+}
+
+fn can_withdraw(deps: Deps, env: Env, address: Addr) -> StdResult<CanWithdrawResponse> {
+    let blocked = |reason: &str| CanWithdrawResponse {
+        can: false,
+        reason: Some(reason.to_string()),
+    };
+
+    if withdrawals_paused(deps.storage)? {
+        return Ok(blocked("paused"));
+    }
+    let balance = BALANCE_OF
+        .may_load(deps.storage, address)?
+        .unwrap_or_default();
+    if balance.is_zero() {
+        return Ok(blocked("no_position"));
+    }
+
+    let config = CONFIG.load(deps.storage)?;
+    let total_supply = TOTAL_SUPPLY.load(deps.storage)?;
+    let total_assets = get_total_assets(deps, &env, &config)?;
+    let redeemable = balance.multiply_ratio(total_assets, total_supply);
+    if redeemable.is_zero() {
+        return Ok(blocked("insufficient_liquidity"));
+    }
+
+    Ok(CanWithdrawResponse {
+        can: true,
+        reason: None,
+    })
+}
+
+/// Each snapshot's price holds until the next one (or until now for the latest). The
+/// snapshot preceding the window supplies the price at the window start; when there is
+/// none the average starts at the first snapshot inside the window.
+fn get_twap_share_price(
+    deps: Deps,
+    env: Env,
+    window_seconds: u64,
+) -> StdResult<TwapSharePriceResponse> {
+    let now = env.block.time.seconds();
+    let start = now.saturating_sub(window_seconds);
+
+    let in_window = SHARE_PRICE_SNAPSHOTS
+        .range(
+            deps.storage,
+            Some(Bound::inclusive(start)),
+            Some(Bound::inclusive(now)),
+            Order::Ascending,
+        )
+        .collect::<StdResult<Vec<(u64, Decimal)>>>()?;
+    let before_window = SHARE_PRICE_SNAPSHOTS
+        .range(
+            deps.storage,
+            None,
+            Some(Bound::exclusive(start)),
+            Order::Descending,
+        )
+        .next()
+        .transpose()?;
+
+    let mut points: Vec<(u64, Decimal)> = vec![];
+    if let Some((_, price)) = before_window {
+        points.push((start, price));
+    }
+    points.extend(in_window.iter().cloned());
+
+    let snapshots = in_window.len() as u32;
+    let latest = match points.last() {
+        Some((_, price)) => *price,
+        None => {
+            return Ok(TwapSharePriceResponse {
+                price: None,
+                snapshots,
+            })
+        }
+    };
+
+    let mut weighted_sum = Uint128::zero();
+    let mut elapsed = 0u64;
+    for (i, (time, price)) in points.iter().enumerate() {
+        let until = points.get(i + 1).map(|(next, _)| *next).unwrap_or(now);
+        let duration = until - time;
+        weighted_sum = weighted_sum.checked_add(
+            price
+                .atomics()
+                .checked_mul(Uint128::from(duration))
+                .map_err(StdError::overflow)?,
+        )?;
+        elapsed += duration;
+    }
+
+    let price = if elapsed == 0 {
+        latest
+    } else {
+        Decimal::new(weighted_sum / Uint128::from(elapsed))
+    };
+
+    Ok(TwapSharePriceResponse {
+        price: Some(price),
+        snapshots,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+
+    use crate::contract::{
+        execute, get_token_balance_of, instantiate, migrate, query, CONTRACT_NAME, CONTRACT_VERSION,
+    };
+    use crate::msg::{
+        BreakEvenResponse, CanWithdrawResponse, CirculatingSupplyResponse, DescribeResponse,
+        ExecuteMsg, FeeDragResponse, InstantiateMsg, InvariantResponse, MigrateMsg,
+        PendingWithdrawal, PendingWithdrawalsResponse, QueryMsg, ReceiveMsg, SendTarget,
+        StakedValueResponse, StakingExecuteMsg, StakingReceiveMsg, StatsResponse,
+        TotalSupplyResponse, TwapSharePriceResponse, UserValueResponse, VaultInfoResponse,
+    };
+    use crate::state::{
+        AssetKind, Config, ShareModel, BALANCE_OF, DEAD_SHARE_AMOUNT, DEPOSITED, FEE_SHARES,
+        HOLDER_COUNT, MAX_BATCH_DEPOSITS, MAX_METADATA_URI_LEN, SHARE_PRICE_SNAPSHOTS, SNAPSHOTS,
+        SNAPSHOT_BACKFILL_CURSOR, TOTAL_SUPPLY,
+    };
+    use crate::ContractError;
+    use cosmwasm_std::testing::{
+        self, mock_env, mock_info, MockApi, MockQuerier, MockStorage, MOCK_CONTRACT_ADDR,
+    };
+    use cosmwasm_std::{
+        attr, coin, coins, from_json, to_json_binary, Addr, BankMsg, Binary, ContractInfoResponse,
+        ContractResult, CosmosMsg, Decimal, Order, OwnedDeps, Response, StdError, StdResult,
+        SubMsg, SystemError, SystemResult, Uint128, WasmMsg, WasmQuery,
+    };
+    use cw2::ContractVersion;
+    use cw20::{
+        AllowanceResponse, BalanceResponse, Cw20ExecuteMsg, Cw20QueryMsg, Cw20ReceiveMsg,
+        Expiration, TokenInfoResponse,
+    };
+
+    pub const ADDR1: &str = "addr1";
+    pub const ADDR2: &str = "addr2";
+    pub const STAKING: &str = "staking";
+
+    fn default_instantiate_msg() -> InstantiateMsg {
+        InstantiateMsg {
+            owner_addr: ADDR1.to_string(),
+            token: AssetKind::Cw20(Addr::unchecked(ADDR2)),
+            staking_addr: None,
+            fee_recipient: None,
+            share_fee_bps: 0,
+            deposit_fee_bps: 0,
+            withdraw_fee_bps: 0,
+            deposit_cap: None,
+            max_per_address: None,
+            min_deposit: Uint128::zero(),
+            share_model: ShareModel::Classic,
+            private_mode: false,
+            metadata_uri: None,
+            withdraw_delay: 0,
+            performance_fee_bps: 0,
+            initial_deposit: None,
+            initial_shares_recipient: None,
+        }
+    }
+
+    /// Mock dependencies whose token (`ADDR2`) answers as a cw20 holding nothing.
+    fn mock_dependencies() -> OwnedDeps<MockStorage, MockApi, MockQuerier> {
+        let mut deps = testing::mock_dependencies();
+        mock_balances(&mut deps, 0, 0);
+        deps
+    }
+
+    /// Answers the vault's cw20 balance and token info queries on the token (`ADDR2`) and
+    /// the staked value query on the staking contract.
+    fn mock_balances(
+        deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>,
+        token_balance: u128,
+        staked_value: u128,
+    ) {
+        deps.querier.update_wasm(move |query| match query {
+            WasmQuery::Smart { contract_addr, msg } if contract_addr == ADDR2 => {
+                let res = match from_json(msg) {
+                    Ok(Cw20QueryMsg::TokenInfo {}) => to_json_binary(&TokenInfoResponse {
+                        name: "vault token".to_string(),
+                        symbol: "VAULT".to_string(),
+                        decimals: 6,
+                        total_supply: Uint128::zero(),
+                    }),
+                    _ => to_json_binary(&BalanceResponse {
+                        balance: Uint128::new(token_balance),
+                    }),
+                };
+                SystemResult::Ok(ContractResult::Ok(res.unwrap()))
+            }
+            WasmQuery::Smart { contract_addr, .. } if contract_addr == STAKING => {
+                SystemResult::Ok(ContractResult::Ok(
+                    to_json_binary(&StakedValueResponse {
+                        value: Uint128::new(staked_value),
+                    })
+                    .unwrap(),
+                ))
+            }
+            WasmQuery::ContractInfo { contract_addr } if contract_addr == STAKING => {
+                let mut info = ContractInfoResponse::default();
+                info.code_id = 1;
+                SystemResult::Ok(ContractResult::Ok(to_json_binary(&info).unwrap()))
+            }
+            _ => SystemResult::Err(SystemError::UnsupportedRequest {
+                kind: "wasm".to_string(),
+            }),
+        });
+    }
+
+    #[test]
+
+    fn test_instantiate() {
+        let mut deps = mock_dependencies();
+        let env = mock_env();
+        let info = mock_info(ADDR1, &[]);
+
+        let msg = default_instantiate_msg();
+
+        let res = instantiate(deps.as_mut(), env, info, msg).unwrap();
+
+        assert_eq!(res.attributes, vec![attr("action", "instantiate")]);
+    }
+
+    #[test]
+    fn test_msgs_round_trip_in_snake_case() {
+        let msg = default_instantiate_msg();
+        let json = to_json_binary(&msg).unwrap();
+        assert_eq!(from_json::<InstantiateMsg>(&json).unwrap(), msg);
+        // fields left out take their defaults, the rest are read by their snake_case names
+        let minimal = br#"{"owner_addr":"addr1","token":{"cw20":"addr2"},"staking_addr":null,"fee_recipient":null,"share_fee_bps":0}"#;
+        assert_eq!(from_json::<InstantiateMsg>(minimal).unwrap(), msg);
+
+        let msg = ExecuteMsg::DepositFor {
+            recipient: ADDR2.to_string(),
+            amount: Uint128::new(5),
+        };
+        let json = to_json_binary(&msg).unwrap();
+        assert_eq!(
+            json,
+            Binary::from(br#"{"deposit_for":{"recipient":"addr2","amount":"5"}}"#)
+        );
+        assert_eq!(from_json::<ExecuteMsg>(&json).unwrap(), msg);
+
+        assert_eq!(
+            from_json::<MigrateMsg>(b"{}").unwrap(),
+            MigrateMsg::default()
+        );
+
+        // fields the contract does not know are refused rather than dropped
+        assert!(from_json::<ExecuteMsg>(br#"{"redeem_all":{"share":"1"}}"#).is_err());
+        assert!(from_json::<MigrateMsg>(br#"{"metadata":"ipfs://x"}"#).is_err());
+    }
+
+    #[test]
+    fn test_price_per_share() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            default_instantiate_msg(),
+        )
+        .unwrap();
+        let price = |deps: &OwnedDeps<MockStorage, MockApi, MockQuerier>| -> Decimal {
+            from_json(query(deps.as_ref(), mock_env(), QueryMsg::PricePerShare {}).unwrap())
+                .unwrap()
+        };
+        assert_eq!(price(&deps), Decimal::one());
+
+        seed_vault(&mut deps, 10_000);
+        assert_eq!(price(&deps), Decimal::one());
+
+        // 1_234 tokens of yield on 10_000 shares, kept to the last fraction
+        mock_balances(&mut deps, 11_234, 0);
+        assert_eq!(price(&deps), Decimal::from_ratio(11_234u128, 10_000u128));
+        assert!(price(&deps) > Decimal::one());
+    }
+
+    #[test]
+    fn test_get_config() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            InstantiateMsg {
+                deposit_fee_bps: 25,
+                deposit_cap: Some(Uint128::new(1_000_000)),
+                ..fee_instantiate_msg(50)
+            },
+        )
+        .unwrap();
+        let config: Config =
+            from_json(query(deps.as_ref(), mock_env(), QueryMsg::GetConfig {}).unwrap()).unwrap();
+        assert_eq!(config.owner, Addr::unchecked(ADDR1));
+        assert_eq!(config.token, AssetKind::Cw20(Addr::unchecked(ADDR2)));
+        assert_eq!(config.staking, None);
+        assert_eq!(config.fee_recipient, Some(Addr::unchecked(FEE_RECIPIENT)));
+        assert_eq!(config.share_fee_bps, 50);
+        assert_eq!(config.deposit_fee_bps, 25);
+        assert_eq!(config.withdraw_fee_bps, 0);
+        assert_eq!(config.deposit_cap, Some(Uint128::new(1_000_000)));
+    }
+
+    #[test]
+    fn test_zero_amounts_are_rejected() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            default_instantiate_msg(),
+        )
+        .unwrap();
+        mock_balances(&mut deps, 0, 0);
+
+        let msgs = vec![
+            (
+                ADDR1,
+                ExecuteMsg::Deposit {
+                    amount: Uint128::zero(),
+                    min_shares: None,
+                    referrer: None,
+                },
+            ),
+            (
+                ADDR2,
+                ExecuteMsg::Receive(Cw20ReceiveMsg {
+                    sender: ADDR1.to_string(),
+                    amount: Uint128::zero(),
+                    msg: to_json_binary(&ReceiveMsg::Deposit { recipient: None }).unwrap(),
+                }),
+            ),
+            (
+                ADDR1,
+                ExecuteMsg::Withdraw {
+                    share: Uint128::zero(),
+                    recipient: None,
+                    send_to: None,
+                },
+            ),
+        ];
+        for (sender, msg) in msgs {
+            let err = execute(deps.as_mut(), mock_env(), mock_info(sender, &[]), msg).unwrap_err();
+            assert!(matches!(err, ContractError::ZeroAmount {}));
+        }
+        assert!(TOTAL_SUPPLY.load(deps.as_ref().storage).unwrap().is_zero());
+    }
+
+    #[test]
+    fn test_withdraw_more_than_held() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            default_instantiate_msg(),
+        )
+        .unwrap();
+        // other holders keep the total supply above what this depositor owns
+        mock_balances(&mut deps, 1000, 0);
+        TOTAL_SUPPLY
+            .save(deps.as_mut().storage, &Uint128::new(1000))
+            .unwrap();
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            ExecuteMsg::Deposit {
+                amount: Uint128::new(10),
+                min_shares: None,
+                referrer: None,
+            },
+        )
+        .unwrap();
+        assert_eq!(share_balance(&deps, ADDR1), 10);
+
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            ExecuteMsg::Withdraw {
+                share: Uint128::new(11),
+                recipient: None,
+                send_to: None,
+            },
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::InsufficientShares { available, requested }
+                if available == Uint128::new(10) && requested == Uint128::new(11)
+        ));
+        assert_eq!(share_balance(&deps, ADDR1), 10);
+    }
+
+    #[test]
+    fn test_withdraw_share_exceeds_total_supply() {
+        let mut deps = mock_dependencies();
+        let info = mock_info(ADDR1, &[]);
+
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            info.clone(),
+            default_instantiate_msg(),
+        )
+        .unwrap();
+        TOTAL_SUPPLY
+            .save(deps.as_mut().storage, &Uint128::new(1000))
+            .unwrap();
+        mock_balances(&mut deps, 1000, 0);
+
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            ExecuteMsg::Withdraw {
+                share: Uint128::MAX,
+                recipient: None,
+                send_to: None,
+            },
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::InvalidShareAmount { share, total_supply }
+                if share == Uint128::MAX && total_supply == Uint128::new(1000)
+        ));
+    }
+
+    #[test]
+    fn test_staking_deposit_stakes_tokens() {
+        let mut deps = mock_dependencies();
+        let info = mock_info(ADDR1, &[]);
+        let mut msg = default_instantiate_msg();
+        msg.staking_addr = Some(STAKING.to_string());
+        instantiate(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
+
+        // 100 liquid + 900 staked back 1000 shares
+        mock_balances(&mut deps, 100, 900);
+        TOTAL_SUPPLY
+            .save(deps.as_mut().storage, &Uint128::new(1000))
+            .unwrap();
+
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            ExecuteMsg::Deposit {
+                amount: Uint128::new(500),
+                min_shares: None,
+                referrer: None,
+            },
+        )
+        .unwrap();
+
+        let balance = BALANCE_OF
+            .load(deps.as_ref().storage, Addr::unchecked(ADDR1))
+            .unwrap();
+        assert_eq!(balance, Uint128::new(500));
+
+        assert_eq!(res.messages.len(), 2);
+        match &res.messages[1].msg {
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr, msg, ..
+            }) => {
+                assert_eq!(contract_addr, ADDR2);
+                let send: Cw20ExecuteMsg = from_json(msg).unwrap();
+                assert_eq!(
+                    send,
+                    Cw20ExecuteMsg::Send {
+                        contract: STAKING.to_string(),
+                        amount: Uint128::new(500),
+                        msg: to_json_binary(&StakingReceiveMsg::Stake {}).unwrap(),
+                    }
+                );
+            }
+            other => panic!("unexpected message {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_deposit_never_grants_allowance() {
+        for staking_addr in [None, Some(STAKING.to_string())] {
+            let mut deps = mock_dependencies();
+            let mut msg = default_instantiate_msg();
+            msg.staking_addr = staking_addr;
+            instantiate(deps.as_mut(), mock_env(), mock_info(ADDR1, &[]), msg).unwrap();
+            mock_balances(&mut deps, 1_000, 0);
+            TOTAL_SUPPLY
+                .save(deps.as_mut().storage, &Uint128::new(1000))
+                .unwrap();
+
+            let deposit = ExecuteMsg::Deposit {
+                amount: Uint128::new(500),
+                min_shares: None,
+                referrer: None,
+            };
+            let hook = ExecuteMsg::Receive(Cw20ReceiveMsg {
+                sender: ADDR1.to_string(),
+                amount: Uint128::new(500),
+                msg: to_json_binary(&ReceiveMsg::Deposit { recipient: None }).unwrap(),
+            });
+            for (sender, msg, pulled) in [(ADDR1, deposit, 1), (ADDR2, hook, 0)] {
+                let res = execute(deps.as_mut(), mock_env(), mock_info(sender, &[]), msg).unwrap();
+                let cw20_msgs: Vec<Cw20ExecuteMsg> = res
+                    .messages
+                    .iter()
+                    .filter_map(|sub| match &sub.msg {
+                        CosmosMsg::Wasm(WasmMsg::Execute { msg, .. }) => from_json(msg).ok(),
+                        _ => None,
+                    })
+                    .collect();
+                assert!(!cw20_msgs
+                    .iter()
+                    .any(|msg| matches!(msg, Cw20ExecuteMsg::IncreaseAllowance { .. })));
+                let transfer_froms: Vec<_> = cw20_msgs
+                    .iter()
+                    .filter(|msg| matches!(msg, Cw20ExecuteMsg::TransferFrom { .. }))
+                    .collect();
+                assert_eq!(transfer_froms.len(), pulled);
+                for msg in transfer_froms {
+                    assert!(matches!(
+                        msg,
+                        Cw20ExecuteMsg::TransferFrom { amount, .. } if amount == Uint128::new(500)
+                    ));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_staking_withdraw_unstakes_shortfall() {
+        let mut deps = mock_dependencies();
+        let info = mock_info(ADDR1, &[]);
+        let mut msg = default_instantiate_msg();
+        msg.staking_addr = Some(STAKING.to_string());
+        instantiate(deps.as_mut(), mock_env(), info.clone(), msg).unwrap();
+
+        // 2000 of value backs 1000 shares, so 500 shares redeem 1000 tokens
+        mock_balances(&mut deps, 200, 1800);
+        TOTAL_SUPPLY
+            .save(deps.as_mut().storage, &Uint128::new(1000))
+            .unwrap();
+        BALANCE_OF
+            .save(
+                deps.as_mut().storage,
+                Addr::unchecked(ADDR1),
+                &Uint128::new(500),
+            )
+            .unwrap();
+
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            ExecuteMsg::Withdraw {
+                share: Uint128::new(500),
+                recipient: None,
+                send_to: None,
+            },
+        )
+        .unwrap();
+
+        assert_eq!(res.messages.len(), 2);
+        match &res.messages[0].msg {
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr, msg, ..
+            }) => {
+                assert_eq!(contract_addr, STAKING);
+                let unstake: StakingExecuteMsg = from_json(msg).unwrap();
+                assert_eq!(
+                    unstake,
+                    StakingExecuteMsg::Unstake {
+                        amount: Uint128::new(800)
+                    }
+                );
+            }
+            other => panic!("unexpected message {:?}", other),
+        }
+        match &res.messages[1].msg {
+            CosmosMsg::Wasm(WasmMsg::Execute { msg, .. }) => {
+                let transfer: Cw20ExecuteMsg = from_json(msg).unwrap();
+                assert_eq!(
+                    transfer,
+                    Cw20ExecuteMsg::Transfer {
+                        recipient: ADDR1.to_string(),
+                        amount: Uint128::new(1000),
+                    }
+                );
+            }
+            other => panic!("unexpected message {:?}", other),
+        }
+    }
+
+    fn query_twap(
+        deps: &OwnedDeps<MockStorage, MockApi, MockQuerier>,
+        window: u64,
+    ) -> TwapSharePriceResponse {
+        from_json(
+            query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::TwapSharePrice {
+                    window_seconds: window,
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_twap_share_price() {
+        let mut deps = mock_dependencies();
+        let now = mock_env().block.time.seconds();
+        for (age, price) in [(100, "1"), (60, "1.2"), (20, "1.5")] {
+            SHARE_PRICE_SNAPSHOTS
+                .save(
+                    deps.as_mut().storage,
+                    now - age,
+                    &price.parse::<Decimal>().unwrap(),
+                )
+                .unwrap();
+        }
+
+        // window starts at now-80 with the price from now-100 still in effect:
+        // (20 * 1.0 + 40 * 1.2 + 20 * 1.5) / 80
+        let res = query_twap(&deps, 80);
+        assert_eq!(res.price, Some("1.225".parse().unwrap()));
+        assert_eq!(res.snapshots, 2);
+
+        // window shorter than the last snapshot age uses the latest price
+        let res = query_twap(&deps, 10);
+        assert_eq!(res.price, Some("1.5".parse().unwrap()));
+        assert_eq!(res.snapshots, 0);
+
+        // window larger than history starts at the first snapshot
+        // (40 * 1.0 + 40 * 1.2 + 20 * 1.5) / 100
+        let res = query_twap(&deps, 1000);
+        assert_eq!(res.price, Some("1.18".parse().unwrap()));
+        assert_eq!(res.snapshots, 3);
+    }
+
+    #[test]
+    fn test_twap_share_price_without_snapshots() {
+        let deps = mock_dependencies();
+        let res = query_twap(&deps, 3600);
+        assert_eq!(res.price, None);
+        assert_eq!(res.snapshots, 0);
+    }
+
+    #[test]
+    fn test_deposit_records_share_price() {
+        let mut deps = mock_dependencies();
+        let info = mock_info(ADDR1, &[]);
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            info.clone(),
+            default_instantiate_msg(),
+        )
+        .unwrap();
+
+        mock_balances(&mut deps, 2000, 0);
+        TOTAL_SUPPLY
+            .save(deps.as_mut().storage, &Uint128::new(1000))
+            .unwrap();
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            info,
+            ExecuteMsg::Deposit {
+                amount: Uint128::new(100),
+                min_shares: None,
+                referrer: None,
+            },
+        )
+        .unwrap();
+
+        let price = SHARE_PRICE_SNAPSHOTS
+            .load(deps.as_ref().storage, mock_env().block.time.seconds())
+            .unwrap();
+        assert_eq!(price, Decimal::from_ratio(2u128, 1u128));
+    }
+
+    fn query_can_withdraw(
+        deps: &OwnedDeps<MockStorage, MockApi, MockQuerier>,
+        address: &str,
+    ) -> CanWithdrawResponse {
+        from_json(
+            query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::CanWithdraw {
+                    address: Addr::unchecked(address),
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_can_withdraw() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            default_instantiate_msg(),
+        )
+        .unwrap();
+        TOTAL_SUPPLY
+            .save(deps.as_mut().storage, &Uint128::new(1000))
+            .unwrap();
+        BALANCE_OF
+            .save(
+                deps.as_mut().storage,
+                Addr::unchecked(ADDR1),
+                &Uint128::new(100),
+            )
+            .unwrap();
+
+        let res = query_can_withdraw(&deps, ADDR2);
+        assert!(!res.can);
+        assert_eq!(res.reason, Some("no_position".to_string()));
+
+        mock_balances(&mut deps, 0, 0);
+        let res = query_can_withdraw(&deps, ADDR1);
+        assert!(!res.can);
+        assert_eq!(res.reason, Some("insufficient_liquidity".to_string()));
+
+        mock_balances(&mut deps, 1000, 0);
+        let res = query_can_withdraw(&deps, ADDR1);
+        assert!(res.can);
+        assert_eq!(res.reason, None);
+    }
+
+    #[test]
+    fn test_pause() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            default_instantiate_msg(),
+        )
+        .unwrap();
+        mock_balances(&mut deps, 1000, 0);
+        TOTAL_SUPPLY
+            .save(deps.as_mut().storage, &Uint128::new(1000))
+            .unwrap();
+        BALANCE_OF
+            .save(
+                deps.as_mut().storage,
+                Addr::unchecked(ADDR1),
+                &Uint128::new(1000),
+            )
+            .unwrap();
+        let run = |deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>,
+                   sender: &str,
+                   msg: ExecuteMsg| {
+            execute(deps.as_mut(), mock_env(), mock_info(sender, &[]), msg)
+        };
+        let deposit = ExecuteMsg::Deposit {
+            amount: Uint128::new(10),
+            min_shares: None,
+            referrer: None,
+        };
+        let withdraw = ExecuteMsg::Withdraw {
+            share: Uint128::new(10),
+            recipient: None,
+            send_to: None,
+        };
+        let set_paused = |paused: bool, allow_withdrawals: bool| ExecuteMsg::SetPaused {
+            paused,
+            allow_withdrawals,
+        };
+        let max_deposit = |deps: &OwnedDeps<MockStorage, MockApi, MockQuerier>| {
+            query_u128(
+                deps,
+                QueryMsg::MaxDeposit {
+                    address: Addr::unchecked(ADDR1),
+                },
+            )
+        };
+
+        let err = run(&mut deps, ADDR2, set_paused(true, false)).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+
+        run(&mut deps, ADDR1, set_paused(true, false)).unwrap();
+        for msg in [deposit.clone(), withdraw.clone()] {
+            let err = run(&mut deps, ADDR1, msg).unwrap_err();
+            assert!(matches!(err, ContractError::Paused {}));
+        }
+        assert_eq!(max_deposit(&deps), 0);
+        assert_eq!(
+            query_can_withdraw(&deps, ADDR1).reason,
+            Some("paused".to_string())
+        );
+
+        // deposits only: holders can still get out
+        run(&mut deps, ADDR1, set_paused(true, true)).unwrap();
+        let err = run(&mut deps, ADDR1, deposit.clone()).unwrap_err();
+        assert!(matches!(err, ContractError::Paused {}));
+        assert!(query_can_withdraw(&deps, ADDR1).can);
+        run(&mut deps, ADDR1, withdraw.clone()).unwrap();
+
+        run(&mut deps, ADDR1, set_paused(false, false)).unwrap();
+        assert!(max_deposit(&deps) > 0);
+        run(&mut deps, ADDR1, deposit).unwrap();
+        run(&mut deps, ADDR1, withdraw).unwrap();
+    }
+
+    #[test]
+    fn test_describe() {
+        for staking_addr in [None, Some(STAKING.to_string())] {
+            let mut deps = mock_dependencies();
+            let msg = InstantiateMsg {
+                staking_addr: staking_addr.clone(),
+                ..default_instantiate_msg()
+            };
+            instantiate(deps.as_mut(), mock_env(), mock_info(ADDR1, &[]), msg).unwrap();
+
+            let res: DescribeResponse =
+                from_json(query(deps.as_ref(), mock_env(), QueryMsg::Describe {}).unwrap())
+                    .unwrap();
+            assert_eq!(
+                res.contract_info,
+                cw2::get_contract_version(deps.as_ref().storage).unwrap()
+            );
+            assert_eq!(res.contract_info.contract, CONTRACT_NAME);
+            assert_eq!(res.contract_info.version, CONTRACT_VERSION);
+
+            let expected: Vec<String> = staking_addr
+                .map(|_| "lp_staking".to_string())
+                .into_iter()
+                .collect();
+            assert_eq!(res.features, expected);
+
+            let info: ContractVersion =
+                from_json(query(deps.as_ref(), mock_env(), QueryMsg::ContractInfo {}).unwrap())
+                    .unwrap();
+            assert_eq!(info, res.contract_info);
+        }
+    }
+
+    #[test]
+    fn test_full_withdraw_removes_balance_key() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            default_instantiate_msg(),
+        )
+        .unwrap();
+        mock_balances(&mut deps, 1000, 0);
+        TOTAL_SUPPLY
+            .save(deps.as_mut().storage, &Uint128::new(1000))
+            .unwrap();
+        for (holder, share) in [(ADDR1, 600u128), ("holder", 400)] {
+            BALANCE_OF
+                .save(
+                    deps.as_mut().storage,
+                    Addr::unchecked(holder),
+                    &Uint128::new(share),
+                )
+                .unwrap();
+        }
+
+        for (holder, share) in [(ADDR1, 600u128), ("holder", 100)] {
+            execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info(holder, &[]),
+                ExecuteMsg::Withdraw {
+                    share: Uint128::new(share),
+                    recipient: None,
+                    send_to: None,
+                },
+            )
+            .unwrap();
+        }
+
+        assert_eq!(
+            BALANCE_OF
+                .may_load(deps.as_ref().storage, Addr::unchecked(ADDR1))
+                .unwrap(),
+            None
+        );
+        let holders: Vec<Addr> = BALANCE_OF
+            .keys(deps.as_ref().storage, None, None, Order::Ascending)
+            .collect::<StdResult<_>>()
+            .unwrap();
+        assert_eq!(holders, vec![Addr::unchecked("holder")]);
+
+        assert_eq!(query_balance_of(&deps, ADDR1), 0);
+    }
+
+    const FEE_RECIPIENT: &str = "fee_recipient";
+
+    fn fee_instantiate_msg(share_fee_bps: u16) -> InstantiateMsg {
+        InstantiateMsg {
+            fee_recipient: Some(FEE_RECIPIENT.to_string()),
+            share_fee_bps,
+            ..default_instantiate_msg()
+        }
+    }
+
+    fn share_balance(deps: &OwnedDeps<MockStorage, MockApi, MockQuerier>, address: &str) -> u128 {
+        BALANCE_OF
+            .may_load(deps.as_ref().storage, Addr::unchecked(address))
+            .unwrap()
+            .unwrap_or_default()
+            .u128()
+    }
+
+    #[test]
+    fn test_deposit_mints_fee_shares() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            fee_instantiate_msg(100),
+        )
+        .unwrap();
+        mock_balances(&mut deps, 1000, 0);
+        TOTAL_SUPPLY
+            .save(deps.as_mut().storage, &Uint128::new(1000))
+            .unwrap();
+        BALANCE_OF
+            .save(
+                deps.as_mut().storage,
+                Addr::unchecked("holder"),
+                &Uint128::new(1000),
+            )
+            .unwrap();
+
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            ExecuteMsg::Deposit {
+                amount: Uint128::new(1000),
+                min_shares: None,
+                referrer: None,
+            },
+        )
+        .unwrap();
+
+        // 1000 shares are minted in total, 1% of them to the fee recipient
+        assert_eq!(share_balance(&deps, FEE_RECIPIENT), 10);
+        assert_eq!(share_balance(&deps, ADDR1), 990);
+        assert_eq!(share_balance(&deps, "holder"), 1000);
+        assert_eq!(
+            TOTAL_SUPPLY.load(deps.as_ref().storage).unwrap(),
+            Uint128::new(2000)
+        );
+    }
+
+    #[test]
+    fn test_withdraw_moves_fee_shares_to_recipient() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            fee_instantiate_msg(250),
+        )
+        .unwrap();
+        mock_balances(&mut deps, 2000, 0);
+        TOTAL_SUPPLY
+            .save(deps.as_mut().storage, &Uint128::new(1000))
+            .unwrap();
+        BALANCE_OF
+            .save(
+                deps.as_mut().storage,
+                Addr::unchecked(ADDR1),
+                &Uint128::new(1000),
+            )
+            .unwrap();
+
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            ExecuteMsg::Withdraw {
+                share: Uint128::new(400),
+                recipient: None,
+                send_to: None,
+            },
+        )
+        .unwrap();
+
+        // 10 of the 400 shares go to the fee recipient, the other 390 redeem at 2 tokens each
+        assert_eq!(share_balance(&deps, FEE_RECIPIENT), 10);
+        assert_eq!(share_balance(&deps, ADDR1), 600);
+        assert_eq!(
+            TOTAL_SUPPLY.load(deps.as_ref().storage).unwrap(),
+            Uint128::new(610)
+        );
+        match &res.messages[0].msg {
+            CosmosMsg::Wasm(WasmMsg::Execute { msg, .. }) => {
+                let transfer: Cw20ExecuteMsg = from_json(msg).unwrap();
+                assert_eq!(
+                    transfer,
+                    Cw20ExecuteMsg::Transfer {
+                        recipient: ADDR1.to_string(),
+                        amount: Uint128::new(780),
+                    }
+                );
+            }
+            other => panic!("unexpected message {:?}", other),
+        }
+    }
+
+    /// Seeds 1000 shares backed by 2000 tokens: 900 held by `ADDR1` and 100 by `other`.
+    fn seed_holders(
+        deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>,
+        other: &str,
+        fee_shares: u128,
+    ) {
+        mock_balances(deps, 2000, 0);
+        TOTAL_SUPPLY
+            .save(deps.as_mut().storage, &Uint128::new(1000))
+            .unwrap();
+        FEE_SHARES
+            .save(deps.as_mut().storage, &Uint128::new(fee_shares))
+            .unwrap();
+        for (holder, shares) in [(ADDR1, 900), (other, 100)] {
+            BALANCE_OF
+                .save(
+                    deps.as_mut().storage,
+                    Addr::unchecked(holder),
+                    &Uint128::new(shares),
+                )
+                .unwrap();
+        }
+    }
+
+    fn withdraw(
+        deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>,
+        sender: &str,
+        share: u128,
+    ) -> Result<Response, ContractError> {
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(sender, &[]),
+            ExecuteMsg::Withdraw {
+                share: Uint128::new(share),
+                recipient: None,
+                send_to: None,
+            },
+        )
+    }
+
+    #[test]
+    fn test_fee_shares_redeem_like_any_other() {
+        // the same 100 shares held as fees or by an ordinary holder
+        let mut with_fees = mock_dependencies();
+        instantiate(
+            with_fees.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            fee_instantiate_msg(0),
+        )
+        .unwrap();
+        seed_holders(&mut with_fees, FEE_RECIPIENT, 100);
+        let mut without_fees = mock_dependencies();
+        instantiate(
+            without_fees.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            default_instantiate_msg(),
+        )
+        .unwrap();
+        seed_holders(&mut without_fees, ADDR2, 0);
+
+        // a normal holder redeems at the full share price either way
+        for deps in [&mut with_fees, &mut without_fees] {
+            let res = withdraw(deps, ADDR1, 900).unwrap();
+            assert_eq!(withdrawn_amount(&res), Uint128::new(1800));
+        }
+
+        // what is left backs exactly the fee shares, and only their holder gets it
+        mock_balances(&mut with_fees, 200, 0);
+        let err = withdraw(&mut with_fees, ADDR1, 1).unwrap_err();
+        assert!(matches!(err, ContractError::InsufficientShares { .. }));
+        let err = withdraw(&mut with_fees, ADDR2, 100).unwrap_err();
+        assert!(matches!(err, ContractError::InsufficientShares { .. }));
+        let res = withdraw(&mut with_fees, FEE_RECIPIENT, 100).unwrap();
+        assert_eq!(withdrawn_amount(&res), Uint128::new(200));
+        assert!(TOTAL_SUPPLY
+            .load(with_fees.as_ref().storage)
+            .unwrap()
+            .is_zero());
+        assert!(FEE_SHARES
+            .load(with_fees.as_ref().storage)
+            .unwrap()
+            .is_zero());
+    }
+
+    #[test]
+    fn test_withdraw_is_capped_at_own_shares() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            fee_instantiate_msg(250),
+        )
+        .unwrap();
+        seed_holders(&mut deps, FEE_RECIPIENT, 100);
+
+        let err = withdraw(&mut deps, ADDR1, 901).unwrap_err();
+        assert!(matches!(err, ContractError::InsufficientShares { .. }));
+        assert_eq!(share_balance(&deps, ADDR1), 900);
+        assert_eq!(
+            TOTAL_SUPPLY.load(deps.as_ref().storage).unwrap(),
+            Uint128::new(1000)
+        );
+
+        // all 900 go out, 22 of them to the fee recipient, the other 878 at 2 tokens each
+        let res = withdraw(&mut deps, ADDR1, 900).unwrap();
+        assert_eq!(withdrawn_amount(&res), Uint128::new(1756));
+        assert_eq!(share_balance(&deps, FEE_RECIPIENT), 122);
+    }
+
+    #[test]
+    fn test_preview_deposit_matches_deposit() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            fee_instantiate_msg(100),
+        )
+        .unwrap();
+        let preview = |deps: &OwnedDeps<MockStorage, MockApi, MockQuerier>, amount: u128| {
+            query_u128(
+                deps,
+                QueryMsg::PreviewDeposit {
+                    amount: Uint128::new(amount),
+                },
+            )
+        };
+        let deposit = |deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>, amount: u128| {
+            execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info(ADDR1, &[]),
+                ExecuteMsg::Deposit {
+                    amount: Uint128::new(amount),
+                    min_shares: None,
+                    referrer: None,
+                },
+            )
+            .unwrap();
+        };
+
+        // an empty vault mints one share per token, less the dead shares and the 1% fee
+        mock_balances(&mut deps, 0, 0);
+        assert_eq!(preview(&deps, 1_000), 0);
+        assert_eq!(preview(&deps, 10_000), 8_910);
+        deposit(&mut deps, 10_000);
+        assert_eq!(share_balance(&deps, ADDR1), 8_910);
+
+        // the vault doubled in value: 500 tokens buy 250 shares, 2 of them as fees
+        mock_balances(&mut deps, 20_000, 0);
+        let previewed = preview(&deps, 500);
+        assert_eq!(previewed, 248);
+        assert_eq!(
+            TOTAL_SUPPLY.load(deps.as_ref().storage).unwrap(),
+            Uint128::new(10_000)
+        );
+        deposit(&mut deps, 500);
+        assert_eq!(share_balance(&deps, ADDR1), 8_910 + previewed);
+    }
+
+    #[test]
+    fn test_convert_to_assets() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            default_instantiate_msg(),
+        )
+        .unwrap();
+        let convert = |deps: &OwnedDeps<MockStorage, MockApi, MockQuerier>, share: u128| {
+            query_u128(
+                deps,
+                QueryMsg::ConvertToAssets {
+                    share: Uint128::new(share),
+                },
+            )
+        };
+
+        // no supply yet, and the balance query is never reached
+        assert_eq!(convert(&deps, 100), 0);
+
+        mock_balances(&mut deps, 1500, 500);
+        TOTAL_SUPPLY
+            .save(deps.as_mut().storage, &Uint128::new(1000))
+            .unwrap();
+        assert_eq!(convert(&deps, 100), 150);
+        assert_eq!(convert(&deps, 1000), 1500);
+        assert_eq!(convert(&deps, 0), 0);
+    }
+
+    #[test]
+    fn test_shares_for_assets() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            default_instantiate_msg(),
+        )
+        .unwrap();
+        let to_shares = |deps: &OwnedDeps<MockStorage, MockApi, MockQuerier>, assets: u128| {
+            query_u128(
+                deps,
+                QueryMsg::SharesForAssets {
+                    assets: Uint128::new(assets),
+                },
+            )
+        };
+        let to_assets = |deps: &OwnedDeps<MockStorage, MockApi, MockQuerier>, share: u128| {
+            query_u128(
+                deps,
+                QueryMsg::ConvertToAssets {
+                    share: Uint128::new(share),
+                },
+            )
+        };
+
+        // one for one before the first deposit, dead shares and all
+        assert_eq!(to_shares(&deps, 100), 100);
+
+        mock_balances(&mut deps, 2000, 0);
+        TOTAL_SUPPLY
+            .save(deps.as_mut().storage, &Uint128::new(1000))
+            .unwrap();
+        assert_eq!(to_shares(&deps, 200), 100);
+        assert_eq!(to_shares(&deps, 0), 0);
+        // both directions round down, so a round trip never gains
+        for assets in [1u128, 199, 200, 201, 2_000, 12_345] {
+            let shares = to_shares(&deps, assets);
+            assert_eq!(to_assets(&deps, shares), assets - assets % 2);
+        }
+    }
+
+    #[test]
+    fn test_user_value() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            default_instantiate_msg(),
+        )
+        .unwrap();
+        let user_value = |deps: &OwnedDeps<MockStorage, MockApi, MockQuerier>, address: &str| {
+            from_json::<UserValueResponse>(
+                query(
+                    deps.as_ref(),
+                    mock_env(),
+                    QueryMsg::GetUserValue {
+                        address: Addr::unchecked(address),
+                    },
+                )
+                .unwrap(),
+            )
+            .unwrap()
+        };
+
+        // an empty vault is never asked for its balance
+        let value = user_value(&deps, ADDR1);
+        assert!(value.shares.is_zero() && value.value.is_zero());
+
+        seed_vault(&mut deps, 10_000);
+        mock_balances(&mut deps, 15_000, 0);
+        let value = user_value(&deps, ADDR1);
+        assert_eq!(value.shares, Uint128::new(9_000));
+        assert_eq!(value.value, Uint128::new(13_500));
+        let value = user_value(&deps, "nobody");
+        assert!(value.shares.is_zero() && value.value.is_zero());
+    }
+
+    #[test]
+    fn test_total_assets() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            default_instantiate_msg(),
+        )
+        .unwrap();
+        mock_balances(&mut deps, 1500, 500);
+        // nothing staked without a staking contract
+        assert_eq!(query_u128(&deps, QueryMsg::TotalAssets {}), 1500);
+
+        let mut deps = mock_dependencies();
+        mock_balances(&mut deps, 1500, 500);
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            InstantiateMsg {
+                staking_addr: Some(STAKING.to_string()),
+                ..default_instantiate_msg()
+            },
+        )
+        .unwrap();
+        assert_eq!(query_u128(&deps, QueryMsg::TotalAssets {}), 2000);
+    }
+
+    #[test]
+    fn test_all_balances_pages_through_holders() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            default_instantiate_msg(),
+        )
+        .unwrap();
+        for (i, holder) in ["holder_c", "holder_a", "holder_e", "holder_b", "holder_d"]
+            .iter()
+            .enumerate()
+        {
+            BALANCE_OF
+                .save(
+                    deps.as_mut().storage,
+                    Addr::unchecked(*holder),
+                    &Uint128::new(100 * (i as u128 + 1)),
+                )
+                .unwrap();
+        }
+        let page = |deps: &OwnedDeps<MockStorage, MockApi, MockQuerier>,
+                    start_after: Option<&str>,
+                    limit: Option<u32>| {
+            from_json::<Vec<(Addr, Uint128)>>(
+                query(
+                    deps.as_ref(),
+                    mock_env(),
+                    QueryMsg::AllBalances {
+                        start_after: start_after.map(str::to_string),
+                        limit,
+                    },
+                )
+                .unwrap(),
+            )
+            .unwrap()
+        };
+
+        let first = page(&deps, None, Some(2));
+        assert_eq!(
+            first,
+            vec![
+                (Addr::unchecked("holder_a"), Uint128::new(200)),
+                (Addr::unchecked("holder_b"), Uint128::new(400)),
+            ]
+        );
+        let second = page(&deps, Some("holder_b"), Some(2));
+        assert_eq!(
+            second,
+            vec![
+                (Addr::unchecked("holder_c"), Uint128::new(100)),
+                (Addr::unchecked("holder_d"), Uint128::new(500)),
+            ]
+        );
+        let last = page(&deps, Some("holder_d"), Some(2));
+        assert_eq!(last, vec![(Addr::unchecked("holder_e"), Uint128::new(300))]);
+        assert!(page(&deps, Some("holder_e"), Some(2)).is_empty());
+
+        // the default page holds everyone here, and oversized limits are capped
+        assert_eq!(page(&deps, None, None).len(), 5);
+        for i in 0..40 {
+            BALANCE_OF
+                .save(
+                    deps.as_mut().storage,
+                    Addr::unchecked(format!("extra_{:02}", i)),
+                    &Uint128::one(),
+                )
+                .unwrap();
+        }
+        assert_eq!(page(&deps, None, None).len(), 10);
+        assert_eq!(page(&deps, None, Some(1_000)).len(), 30);
+    }
+
+    #[test]
+    fn test_balance_of_unknown_address_is_zero() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            default_instantiate_msg(),
+        )
+        .unwrap();
+        let balance = query_balance_of(&deps, "never_deposited");
+        assert_eq!(balance, 0);
+    }
+
+    #[test]
+    fn test_token_balance_from_query_context() {
+        let mut deps = mock_dependencies();
+        mock_balances(&mut deps, 1500, 0);
+        // a read-only Deps is all the helper needs
+        let balance = get_token_balance_of(
+            deps.as_ref(),
+            mock_env().contract.address,
+            Addr::unchecked(ADDR2),
+        )
+        .unwrap();
+        assert_eq!(balance, Uint128::new(1500));
+    }
+
+    #[test]
+    fn test_instantiate_rejects_fee_over_max() {
+        let mut deps = mock_dependencies();
+        let over_max = [
+            fee_instantiate_msg(10_001),
+            InstantiateMsg {
+                deposit_fee_bps: 10_001,
+                ..fee_instantiate_msg(0)
+            },
+            InstantiateMsg {
+                withdraw_fee_bps: 10_001,
+                ..fee_instantiate_msg(0)
+            },
+        ];
+        for msg in over_max {
+            let err =
+                instantiate(deps.as_mut(), mock_env(), mock_info(ADDR1, &[]), msg).unwrap_err();
+            assert!(matches!(err, ContractError::InvalidFeeBps { bps: 10_001 }));
+        }
+    }
+
+    #[test]
+    fn test_instantiate_rejects_non_cw20_token() {
+        let mut deps = mock_dependencies();
+        let with_token = |token: &str| InstantiateMsg {
+            token: AssetKind::Cw20(Addr::unchecked(token)),
+            ..default_instantiate_msg()
+        };
+
+        // a wallet answers no cw20 query
+        let err = instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            with_token("wallet"),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::NotCw20Token { token } if token == "wallet"));
+
+        let err = instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            with_token(ADDR1),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::TokenIsOwner { token } if token == ADDR1));
+
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            with_token(ADDR2),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn test_instantiate_with_seed_deposit() {
+        let with_allowance = |allowance: u128| {
+            let mut deps = mock_dependencies();
+            deps.querier.update_wasm(move |query| match query {
+                WasmQuery::Smart { msg, .. } => {
+                    let res = match from_json(msg).unwrap() {
+                        Cw20QueryMsg::Allowance { owner, spender } => {
+                            assert_eq!(
+                                (owner.as_str(), spender.as_str()),
+                                (ADDR1, MOCK_CONTRACT_ADDR)
+                            );
+                            to_json_binary(&AllowanceResponse {
+                                allowance: Uint128::new(allowance),
+                                expires: Expiration::Never {},
+                            })
+                        }
+                        _ => to_json_binary(&TokenInfoResponse {
+                            name: "vault token".to_string(),
+                            symbol: "VAULT".to_string(),
+                            decimals: 6,
+                            total_supply: Uint128::zero(),
+                        }),
+                    };
+                    SystemResult::Ok(ContractResult::Ok(res.unwrap()))
+                }
+                _ => SystemResult::Err(SystemError::UnsupportedRequest {
+                    kind: "wasm".to_string(),
+                }),
+            });
+            deps
+        };
+        let seeded = |recipient: Option<&str>| InstantiateMsg {
+            initial_deposit: Some(Uint128::new(10_000)),
+            initial_shares_recipient: recipient.map(str::to_string),
+            ..default_instantiate_msg()
+        };
+
+        let mut deps = with_allowance(5_000);
+        let err = instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            seeded(None),
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::InsufficientAllowance { allowance, amount }
+                if allowance == Uint128::new(5_000) && amount == Uint128::new(10_000)
+        ));
+        let err = instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            InstantiateMsg {
+                initial_shares_recipient: Some("founder".to_string()),
+                ..default_instantiate_msg()
+            },
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::SeedRecipientWithoutDeposit {}));
+
+        // the seed is held to the same rules as any deposit
+        let err = instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            InstantiateMsg {
+                min_deposit: Uint128::new(20_000),
+                ..seeded(None)
+            },
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::BelowMinDeposit { min } if min == Uint128::new(20_000)
+        ));
+        let err = instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            InstantiateMsg {
+                private_mode: true,
+                ..seeded(Some("founder"))
+            },
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::NotAllowlisted { .. }));
+
+        // the vault launches holding the seed, minus the usual dead shares
+        let mut deps = with_allowance(10_000);
+        let res = instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            seeded(Some("founder")),
+        )
+        .unwrap();
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(WasmMsg::Execute {
+                contract_addr: ADDR2.to_string(),
+                msg: to_json_binary(&Cw20ExecuteMsg::TransferFrom {
+                    owner: ADDR1.to_string(),
+                    recipient: MOCK_CONTRACT_ADDR.to_string(),
+                    amount: Uint128::new(10_000),
+                })
+                .unwrap(),
+                funds: vec![],
+            })]
+        );
+        assert!(res.attributes.contains(&attr("initial_shares", "9000")));
+        assert_eq!(query_total_supply(&deps), 10_000);
+        assert_eq!(share_balance(&deps, "founder"), 9_000);
+        assert_eq!(share_balance(&deps, ADDR1), 0);
+    }
+
+    #[test]
+    fn test_deposit_cap() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            InstantiateMsg {
+                deposit_cap: Some(Uint128::new(20_000)),
+                ..default_instantiate_msg()
+            },
+        )
+        .unwrap();
+        let deposit = |deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>, amount: u128| {
+            execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info(ADDR1, &[]),
+                ExecuteMsg::Deposit {
+                    amount: Uint128::new(amount),
+                    min_shares: None,
+                    referrer: None,
+                },
+            )
+        };
+        let max_deposit = |deps: &OwnedDeps<MockStorage, MockApi, MockQuerier>| {
+            query_u128(
+                deps,
+                QueryMsg::MaxDeposit {
+                    address: Addr::unchecked(ADDR1),
+                },
+            )
+        };
+        seed_vault(&mut deps, 10_000);
+        assert_eq!(max_deposit(&deps), 10_000);
+
+        // filling the vault exactly to the cap is fine
+        deposit(&mut deps, 10_000).unwrap();
+        mock_balances(&mut deps, 20_000, 0);
+        assert_eq!(max_deposit(&deps), 0);
+        let err = deposit(&mut deps, 1).unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::CapExceeded { cap, attempted }
+                if cap == Uint128::new(20_000) && attempted == Uint128::new(20_001)
+        ));
+
+        let set_cap = |cap: Option<u128>| ExecuteMsg::SetCap {
+            cap: cap.map(Uint128::new),
+        };
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR2, &[]),
+            set_cap(None),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            set_cap(Some(25_000)),
+        )
+        .unwrap();
+        assert_eq!(max_deposit(&deps), 5_000);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            set_cap(None),
+        )
+        .unwrap();
+        deposit(&mut deps, 10_000).unwrap();
+    }
+
+    #[test]
+    fn test_min_deposit() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            InstantiateMsg {
+                min_deposit: Uint128::new(5_000),
+                ..default_instantiate_msg()
+            },
+        )
+        .unwrap();
+        let deposit = |deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>, amount: u128| {
+            execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info(ADDR1, &[]),
+                ExecuteMsg::Deposit {
+                    amount: Uint128::new(amount),
+                    min_shares: None,
+                    referrer: None,
+                },
+            )
+        };
+        mock_balances(&mut deps, 0, 0);
+
+        let err = deposit(&mut deps, 4_999).unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::BelowMinDeposit { min } if min == Uint128::new(5_000)
+        ));
+        deposit(&mut deps, 5_000).unwrap();
+        assert_eq!(share_balance(&deps, ADDR1), 4_000);
+
+        let set_min_deposit = |min_deposit: u128| ExecuteMsg::SetMinDeposit {
+            min_deposit: Uint128::new(min_deposit),
+        };
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR2, &[]),
+            set_min_deposit(0),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+
+        mock_balances(&mut deps, 5_000, 0);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            set_min_deposit(100),
+        )
+        .unwrap();
+        assert!(matches!(
+            deposit(&mut deps, 99).unwrap_err(),
+            ContractError::BelowMinDeposit { min } if min == Uint128::new(100)
+        ));
+        deposit(&mut deps, 100).unwrap();
+    }
+
+    #[test]
+    fn test_per_address_limit() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            InstantiateMsg {
+                max_per_address: Some(Uint128::new(20_000)),
+                ..default_instantiate_msg()
+            },
+        )
+        .unwrap();
+        let deposit = |deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>,
+                       sender: &str,
+                       amount: u128| {
+            execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info(sender, &[]),
+                ExecuteMsg::Deposit {
+                    amount: Uint128::new(amount),
+                    min_shares: None,
+                    referrer: None,
+                },
+            )
+        };
+        let max_deposit = |deps: &OwnedDeps<MockStorage, MockApi, MockQuerier>, address: &str| {
+            query_u128(
+                deps,
+                QueryMsg::MaxDeposit {
+                    address: Addr::unchecked(address),
+                },
+            )
+        };
+        seed_vault(&mut deps, 10_000);
+
+        // exactly at the limit is fine, one more is not
+        deposit(&mut deps, ADDR1, 10_000).unwrap();
+        mock_balances(&mut deps, 20_000, 0);
+        assert_eq!(max_deposit(&deps, ADDR1), 0);
+        let err = deposit(&mut deps, ADDR1, 1).unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::AddressLimitExceeded { address, limit, attempted }
+                if address == ADDR1
+                    && limit == Uint128::new(20_000)
+                    && attempted == Uint128::new(20_001)
+        ));
+
+        // the limit is per address
+        assert_eq!(max_deposit(&deps, ADDR2), 20_000);
+        deposit(&mut deps, ADDR2, 20_000).unwrap();
+        mock_balances(&mut deps, 40_000, 0);
+
+        // withdrawing half the shares frees half the allowance
+        assert_eq!(share_balance(&deps, ADDR1), 19_000);
+        withdraw(&mut deps, ADDR1, 9_500).unwrap();
+        assert_eq!(max_deposit(&deps, ADDR1), 10_000);
+        deposit(&mut deps, ADDR1, 10_000).unwrap();
+        assert!(deposit(&mut deps, ADDR1, 1).is_err());
+    }
+
+    #[test]
+    fn test_native_asset() {
+        let mut deps = mock_dependencies();
+        let native = InstantiateMsg {
+            token: AssetKind::Native("uvault".to_string()),
+            ..default_instantiate_msg()
+        };
+        for (msg, err) in [
+            (
+                InstantiateMsg {
+                    token: AssetKind::Native(" ".to_string()),
+                    ..default_instantiate_msg()
+                },
+                "Native denom must not be empty",
+            ),
+            (
+                InstantiateMsg {
+                    staking_addr: Some(STAKING.to_string()),
+                    ..native.clone()
+                },
+                "LP staking is only available for cw20 vaults",
+            ),
+        ] {
+            let res = instantiate(deps.as_mut(), mock_env(), mock_info(ADDR1, &[]), msg);
+            assert_eq!(res.unwrap_err().to_string(), err);
+        }
+        instantiate(deps.as_mut(), mock_env(), mock_info(ADDR1, &[]), native).unwrap();
+
+        let deposit = ExecuteMsg::Deposit {
+            amount: Uint128::new(10_000),
+            min_shares: None,
+            referrer: None,
+        };
+        for funds in [
+            vec![],
+            coins(10_000, "uother"),
+            coins(9_999, "uvault"),
+            vec![coin(10_000, "uvault"), coin(1, "uother")],
+        ] {
+            let err = execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info(ADDR1, &funds),
+                deposit.clone(),
+            )
+            .unwrap_err();
+            assert!(matches!(
+                err,
+                ContractError::InvalidFunds { denom, amount }
+                    if denom == "uvault" && amount == Uint128::new(10_000)
+            ));
+        }
+
+        // the attached funds are already in the vault's balance and price no shares
+        deps.querier
+            .update_balance(MOCK_CONTRACT_ADDR, coins(10_000, "uvault"));
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &coins(10_000, "uvault")),
+            deposit,
+        )
+        .unwrap();
+        assert!(res.messages.is_empty());
+        assert_eq!(share_balance(&deps, ADDR1), 9_000);
+
+        let res = withdraw(&mut deps, ADDR1, 4_500).unwrap();
+        assert_eq!(
+            res.messages
+                .iter()
+                .map(|sub| sub.msg.clone())
+                .collect::<Vec<_>>(),
+            vec![CosmosMsg::Bank(BankMsg::Send {
+                to_address: ADDR1.to_string(),
+                amount: coins(4_500, "uvault"),
+            })]
+        );
+
+        // only the cw20 of a cw20 vault may call the receive hook
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR2, &[]),
+            ExecuteMsg::Receive(Cw20ReceiveMsg {
+                sender: ADDR1.to_string(),
+                amount: Uint128::new(100),
+                msg: to_json_binary(&ReceiveMsg::Deposit { recipient: None }).unwrap(),
+            }),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+    }
+
+    #[test]
+    fn test_deposit_and_withdraw_attributes() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            default_instantiate_msg(),
+        )
+        .unwrap();
+        mock_balances(&mut deps, 0, 0);
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            ExecuteMsg::Deposit {
+                amount: Uint128::new(10_000),
+                min_shares: None,
+                referrer: None,
+            },
+        )
+        .unwrap();
+        // the dead shares count towards the supply but are not minted to the depositor
+        assert_eq!(
+            res.attributes,
+            vec![
+                attr("action", "deposit"),
+                attr("sender", ADDR1),
+                attr("amount", "10000"),
+                attr("shares_minted", "9000"),
+                attr("total_supply", "10000"),
+            ]
+        );
+
+        mock_balances(&mut deps, 10_000, 0);
+        let res = withdraw(&mut deps, ADDR1, 1_000).unwrap();
+        assert_eq!(
+            res.attributes,
+            vec![
+                attr("action", "withdraw"),
+                attr("sender", ADDR1),
+                attr("share", "1000"),
+                attr("assets_returned", "1000"),
+                attr("total_supply", "9000"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_token_fees_go_to_fee_recipient() {
+        let mut deps = mock_dependencies();
+        // 1% of deposits and 2% of withdrawals, no share fee
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            InstantiateMsg {
+                deposit_fee_bps: 100,
+                withdraw_fee_bps: 200,
+                ..fee_instantiate_msg(0)
+            },
+        )
+        .unwrap();
+        let transfers = |res: &Response| -> Vec<Cw20ExecuteMsg> {
+            res.messages
+                .iter()
+                .map(|msg| match &msg.msg {
+                    CosmosMsg::Wasm(WasmMsg::Execute { msg, .. }) => from_json(msg).unwrap(),
+                    other => panic!("unexpected message {:?}", other),
+                })
+                .collect()
+        };
+
+        mock_balances(&mut deps, 0, 0);
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            ExecuteMsg::Deposit {
+                amount: Uint128::new(10_000),
+                min_shares: None,
+                referrer: None,
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            transfers(&res),
+            vec![
+                Cw20ExecuteMsg::TransferFrom {
+                    owner: ADDR1.to_string(),
+                    recipient: MOCK_CONTRACT_ADDR.to_string(),
+                    amount: Uint128::new(10_000),
+                },
+                Cw20ExecuteMsg::Transfer {
+                    recipient: FEE_RECIPIENT.to_string(),
+                    amount: Uint128::new(100),
+                },
+            ]
+        );
+        // only the 9_900 left after the fee is priced, less the dead shares
+        assert_eq!(share_balance(&deps, ADDR1), 8_900);
+        assert_eq!(share_balance(&deps, FEE_RECIPIENT), 0);
+
+        mock_balances(&mut deps, 9_900, 0);
+        let res = withdraw(&mut deps, ADDR1, 1_000).unwrap();
+        assert_eq!(
+            transfers(&res),
+            vec![
+                Cw20ExecuteMsg::Transfer {
+                    recipient: ADDR1.to_string(),
+                    amount: Uint128::new(980),
+                },
+                Cw20ExecuteMsg::Transfer {
+                    recipient: FEE_RECIPIENT.to_string(),
+                    amount: Uint128::new(20),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_token_fees_round_up() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            InstantiateMsg {
+                deposit_fee_bps: 100,
+                withdraw_fee_bps: 200,
+                ..fee_instantiate_msg(0)
+            },
+        )
+        .unwrap();
+        let deposit = |deps: &mut OwnedDeps<_, _, _>, amount: u128| {
+            execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info(ADDR1, &[]),
+                ExecuteMsg::Deposit {
+                    amount: Uint128::new(amount),
+                    min_shares: None,
+                    referrer: None,
+                },
+            )
+        };
+        let fee_paid = |res: &Response| match &res.messages.last().unwrap().msg {
+            CosmosMsg::Wasm(WasmMsg::Execute { msg, .. }) => {
+                match from_json::<Cw20ExecuteMsg>(msg).unwrap() {
+                    Cw20ExecuteMsg::Transfer { recipient, amount }
+                        if recipient == FEE_RECIPIENT =>
+                    {
+                        amount.u128()
+                    }
+                    other => panic!("unexpected message {:?}", other),
+                }
+            }
+            other => panic!("unexpected message {:?}", other),
+        };
+
+        // a single unit would go entirely to its rounded-up fee
+        mock_balances(&mut deps, 0, 0);
+        let err = deposit(&mut deps, 1).unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::DepositBelowFee { amount, fee } if amount.u128() == 1 && fee.u128() == 1
+        ));
+
+        deposit(&mut deps, 10_000).unwrap();
+        mock_balances(&mut deps, 9_900, 0);
+
+        // 1% of 50 is half a unit, charged as a whole one
+        let res = deposit(&mut deps, 50).unwrap();
+        assert_eq!(fee_paid(&res), 1);
+        assert!(res.attributes.contains(&attr("shares_minted", "49")));
+        mock_balances(&mut deps, 9_949, 0);
+
+        // 2% of a 10 unit withdrawal is a fifth of a unit, charged as a whole one
+        let res = withdraw(&mut deps, ADDR1, 10).unwrap();
+        assert_eq!(withdrawn_amount(&res), Uint128::new(9));
+        assert_eq!(fee_paid(&res), 1);
+    }
+
+    #[test]
+    fn test_fee_exempt_address_pays_no_fees() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            InstantiateMsg {
+                deposit_fee_bps: 100,
+                withdraw_fee_bps: 200,
+                ..fee_instantiate_msg(0)
+            },
+        )
+        .unwrap();
+        let set_exempt = |deps: &mut OwnedDeps<_, _, _>, sender: &str, exempt: bool| {
+            execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info(sender, &[]),
+                ExecuteMsg::SetFeeExempt {
+                    address: "exempt".to_string(),
+                    exempt,
+                },
+            )
+        };
+        let deposit = |deps: &mut OwnedDeps<_, _, _>, sender: &str, amount: u128| {
+            execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info(sender, &[]),
+                ExecuteMsg::Deposit {
+                    amount: Uint128::new(amount),
+                    min_shares: None,
+                    referrer: None,
+                },
+            )
+            .unwrap()
+        };
+
+        let err = set_exempt(&mut deps, "exempt", true).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+        let res = set_exempt(&mut deps, ADDR1, true).unwrap();
+        assert_eq!(
+            admin_event(&res),
+            (
+                "set_fee_exempt".to_string(),
+                vec![
+                    ("address".to_string(), "exempt".to_string()),
+                    ("exempt".to_string(), "true".to_string()),
+                ]
+            )
+        );
+
+        // a normal deposit forwards its 1% fee, an exempt one only pulls in the tokens
+        let res = deposit(&mut deps, ADDR1, 10_000);
+        assert_eq!(res.messages.len(), 2);
+        assert!(res.attributes.contains(&attr("shares_minted", "8900")));
+        mock_balances(&mut deps, 9_900, 0);
+        let res = deposit(&mut deps, "exempt", 9_900);
+        assert_eq!(res.messages.len(), 1);
+        assert!(res.attributes.contains(&attr("shares_minted", "9900")));
+        mock_balances(&mut deps, 19_800, 0);
+
+        // and the same on the way out, where the normal holder pays 2%
+        let res = withdraw(&mut deps, "exempt", 1_000).unwrap();
+        assert_eq!(res.messages.len(), 1);
+        assert_eq!(withdrawn_amount(&res), Uint128::new(1_000));
+        mock_balances(&mut deps, 18_800, 0);
+        let res = withdraw(&mut deps, ADDR1, 1_000).unwrap();
+        assert_eq!(res.messages.len(), 2);
+        assert_eq!(withdrawn_amount(&res), Uint128::new(980));
+        mock_balances(&mut deps, 17_800, 0);
+
+        // lifting the exemption brings the fees back
+        set_exempt(&mut deps, ADDR1, false).unwrap();
+        let res = withdraw(&mut deps, "exempt", 1_000).unwrap();
+        assert_eq!(res.messages.len(), 2);
+        assert_eq!(withdrawn_amount(&res), Uint128::new(980));
+    }
+
+    fn query_u128(deps: &OwnedDeps<MockStorage, MockApi, MockQuerier>, msg: QueryMsg) -> u128 {
+        from_json::<Uint128>(query(deps.as_ref(), mock_env(), msg).unwrap())
+            .unwrap()
+            .u128()
+    }
+
+    fn query_total_supply(deps: &OwnedDeps<MockStorage, MockApi, MockQuerier>) -> u128 {
+        from_json::<TotalSupplyResponse>(
+            query(deps.as_ref(), mock_env(), QueryMsg::GetTotalSupply {}).unwrap(),
+        )
+        .unwrap()
+        .total_supply
+        .u128()
+    }
+
+    fn query_balance_of(
+        deps: &OwnedDeps<MockStorage, MockApi, MockQuerier>,
+        address: &str,
+    ) -> u128 {
+        let msg = QueryMsg::GetBalanceOf {
+            address: Addr::unchecked(address),
+        };
+        from_json::<crate::msg::BalanceResponse>(query(deps.as_ref(), mock_env(), msg).unwrap())
+            .unwrap()
+            .balance
+            .u128()
+    }
+
+    fn withdrawn_amount(res: &Response) -> Uint128 {
+        match &res.messages[0].msg {
+            CosmosMsg::Wasm(WasmMsg::Execute { msg, .. }) => {
+                match from_json::<Cw20ExecuteMsg>(msg).unwrap() {
+                    Cw20ExecuteMsg::Transfer { amount, .. } => amount,
+                    other => panic!("unexpected message {:?}", other),
+                }
+            }
+            other => panic!("unexpected message {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_share_models_redeem_consistently() {
+        let mut results = vec![];
+        for share_model in [ShareModel::Classic, ShareModel::Rebasing] {
+            let mut deps = mock_dependencies();
+            let msg = InstantiateMsg {
+                share_model: share_model.clone(),
+                ..default_instantiate_msg()
+            };
+            instantiate(deps.as_mut(), mock_env(), mock_info(ADDR1, &[]), msg).unwrap();
+
+            // 1000 shares backed by 3000 tokens after yield
+            mock_balances(&mut deps, 3000, 0);
+            TOTAL_SUPPLY
+                .save(deps.as_mut().storage, &Uint128::new(1000))
+                .unwrap();
+            for (holder, shares) in [(ADDR1, 400u128), ("holder", 600)] {
+                BALANCE_OF
+                    .save(
+                        deps.as_mut().storage,
+                        Addr::unchecked(holder),
+                        &Uint128::new(shares),
+                    )
+                    .unwrap();
+            }
+
+            let balance = query_balance_of(&deps, ADDR1);
+            let supply = query_total_supply(&deps);
+            match share_model {
+                ShareModel::Classic => assert_eq!((balance, supply), (400, 1000)),
+                ShareModel::Rebasing => assert_eq!((balance, supply), (1200, 3000)),
+            }
+
+            // withdrawing half of what the balance query reports redeems the same value
+            let res = execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info(ADDR1, &[]),
+                ExecuteMsg::Withdraw {
+                    share: Uint128::new(balance / 2),
+                    recipient: None,
+                    send_to: None,
+                },
+            )
+            .unwrap();
+            results.push((
+                withdrawn_amount(&res),
+                share_balance(&deps, ADDR1),
+                TOTAL_SUPPLY.load(deps.as_ref().storage).unwrap(),
+            ));
+        }
+
+        assert_eq!(results[0], results[1]);
+        assert_eq!(results[0], (Uint128::new(600), 200, Uint128::new(800)));
+    }
+
+    #[test]
+    fn test_rebasing_withdraw_rounds_shares_up() {
+        let mut deps = mock_dependencies();
+        let msg = InstantiateMsg {
+            share_model: ShareModel::Rebasing,
+            ..default_instantiate_msg()
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info(ADDR1, &[]), msg).unwrap();
+        mock_balances(&mut deps, 3000, 0);
+        TOTAL_SUPPLY
+            .save(deps.as_mut().storage, &Uint128::new(1000))
+            .unwrap();
+        BALANCE_OF
+            .save(
+                deps.as_mut().storage,
+                Addr::unchecked(ADDR1),
+                &Uint128::new(1000),
+            )
+            .unwrap();
+
+        // 100 underlying is 33.3 shares, so 34 are burned and 102 paid out
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            ExecuteMsg::Withdraw {
+                share: Uint128::new(100),
+                recipient: None,
+                send_to: None,
+            },
+        )
+        .unwrap();
+        assert_eq!(share_balance(&deps, ADDR1), 966);
+        assert_eq!(withdrawn_amount(&res), Uint128::new(102));
+    }
+
+    #[test]
+    fn test_max_deposit() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            default_instantiate_msg(),
+        )
+        .unwrap();
+        let max_deposit = |deps: &OwnedDeps<MockStorage, MockApi, MockQuerier>| {
+            query_u128(
+                deps,
+                QueryMsg::MaxDeposit {
+                    address: Addr::unchecked(ADDR1),
+                },
+            )
+        };
+
+        // nothing limits an empty vault
+        assert_eq!(max_deposit(&deps), u128::MAX);
+
+        // once shares exist, amount * supply must not overflow
+        TOTAL_SUPPLY
+            .save(deps.as_mut().storage, &Uint128::new(1000))
+            .unwrap();
+        assert_eq!(max_deposit(&deps), u128::MAX / 1000);
+
+        mock_balances(&mut deps, 1000, 0);
+        let limit = max_deposit(&deps);
+        let deposit = |deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>, amount| {
+            execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info(ADDR1, &[]),
+                ExecuteMsg::Deposit {
+                    amount: Uint128::new(amount),
+                    min_shares: None,
+                    referrer: None,
+                },
+            )
+        };
+        assert!(deposit(&mut deps, limit + 1).is_err());
+        assert!(deposit(&mut deps, limit).is_ok());
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            default_instantiate_msg(),
+        )
+        .unwrap();
+        let stats = |deps: &OwnedDeps<MockStorage, MockApi, MockQuerier>| {
+            from_json::<StatsResponse>(
+                query(deps.as_ref(), mock_env(), QueryMsg::Stats {}).unwrap(),
+            )
+            .unwrap()
+        };
+        seed_vault(&mut deps, 10_000);
+        for (sender, balance) in [("alice", 15_000), ("bob", 20_000)] {
+            execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info(sender, &[]),
+                ExecuteMsg::Deposit {
+                    amount: Uint128::new(5_000),
+                    min_shares: None,
+                    referrer: None,
+                },
+            )
+            .unwrap();
+            mock_balances(&mut deps, balance, 0);
+        }
+        assert_eq!(
+            stats(&deps),
+            StatsResponse {
+                total_supply: Uint128::new(20_000),
+                holder_count: 3,
+                total_assets: Uint128::new(20_000),
+            }
+        );
+
+        // the cached count follows balances across zero, not every change
+        withdraw(&mut deps, "alice", 5_000).unwrap();
+        withdraw(&mut deps, "bob", 1_000).unwrap();
+        assert_eq!(HOLDER_COUNT.load(deps.as_ref().storage).unwrap(), 2);
+        assert_eq!(stats(&deps).holder_count, 2);
+
+        // a vault from before the count existed walks its balances instead, until the next
+        // migration stores the count
+        HOLDER_COUNT.remove(deps.as_mut().storage);
+        assert_eq!(stats(&deps).holder_count, 2);
+        migrate(deps.as_mut(), mock_env(), MigrateMsg::default()).unwrap();
+        assert_eq!(HOLDER_COUNT.load(deps.as_ref().storage).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_verify_invariant() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            default_instantiate_msg(),
+        )
+        .unwrap();
+        let verify = |deps: &OwnedDeps<MockStorage, MockApi, MockQuerier>| {
+            from_json::<InvariantResponse>(
+                query(deps.as_ref(), mock_env(), QueryMsg::VerifyInvariant {}).unwrap(),
+            )
+            .unwrap()
+        };
+        seed_vault(&mut deps, 10_000);
+        assert_eq!(
+            verify(&deps),
+            InvariantResponse {
+                total_supply: Uint128::new(10_000),
+                balances_total: Uint128::new(9_000),
+                dead_shares: Uint128::new(1_000),
+                consistent: true,
+            }
+        );
+
+        // a balance credited without minting leaves the supply short
+        BALANCE_OF
+            .save(
+                deps.as_mut().storage,
+                Addr::unchecked(ADDR2),
+                &Uint128::new(500),
+            )
+            .unwrap();
+        assert_eq!(
+            verify(&deps),
+            InvariantResponse {
+                total_supply: Uint128::new(10_000),
+                balances_total: Uint128::new(9_500),
+                dead_shares: Uint128::new(1_000),
+                consistent: false,
+            }
+        );
+    }
+
+    #[test]
+    fn test_circulating_supply_excludes_fee_shares() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            fee_instantiate_msg(100),
+        )
+        .unwrap();
+        mock_balances(&mut deps, 1000, 0);
+        TOTAL_SUPPLY
+            .save(deps.as_mut().storage, &Uint128::new(1000))
+            .unwrap();
+        let circulating = |deps: &OwnedDeps<MockStorage, MockApi, MockQuerier>| {
+            from_json::<CirculatingSupplyResponse>(
+                query(deps.as_ref(), mock_env(), QueryMsg::CirculatingSupply {}).unwrap(),
+            )
+            .unwrap()
+        };
+
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            ExecuteMsg::Deposit {
+                amount: Uint128::new(1000),
+                min_shares: None,
+                referrer: None,
+            },
+        )
+        .unwrap();
+        let res = circulating(&deps);
+        assert_eq!(res.total_supply.u128(), query_total_supply(&deps));
+        assert_eq!(res.total_supply, Uint128::new(2000));
+        assert_eq!(res.fee_shares, Uint128::new(10));
+        assert_eq!(res.total_supply - res.circulating_supply, res.fee_shares);
+
+        // once the recipient redeems its fee shares they are no longer set apart
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(FEE_RECIPIENT, &[]),
+            ExecuteMsg::Withdraw {
+                share: Uint128::new(10),
+                recipient: None,
+                send_to: None,
+            },
+        )
+        .unwrap();
+        let res = circulating(&deps);
+        assert!(res.fee_shares.is_zero());
+        assert_eq!(res.circulating_supply, res.total_supply);
+
+        // stale fee share accounting above the supply is an error, not a panic
+        FEE_SHARES
+            .save(deps.as_mut().storage, &Uint128::new(5000))
+            .unwrap();
+        let err = query(deps.as_ref(), mock_env(), QueryMsg::CirculatingSupply {}).unwrap_err();
+        assert!(matches!(err, StdError::Overflow { .. }));
+    }
+
+    #[test]
+    fn test_receive_deposit() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            default_instantiate_msg(),
+        )
+        .unwrap();
+        seed_vault(&mut deps, 10_000);
+        let receive = |msg: &str| {
+            ExecuteMsg::Receive(Cw20ReceiveMsg {
+                sender: "user".to_string(),
+                amount: Uint128::new(1_000),
+                msg: Binary::from(msg.as_bytes()),
+            })
+        };
+
+        // only the vault's own token can deposit through the hook
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("other_token", &[]),
+            receive(r#"{"deposit":{}}"#),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+
+        // the token has already credited the vault, so nothing is pulled with TransferFrom
+        mock_balances(&mut deps, 11_000, 0);
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR2, &[]),
+            receive(r#"{"deposit":{}}"#),
+        )
+        .unwrap();
+        assert!(res.messages.is_empty());
+        assert_eq!(share_balance(&deps, "user"), 1_000);
+
+        mock_balances(&mut deps, 12_000, 0);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR2, &[]),
+            receive(r#"{"deposit":{"recipient":"friend"}}"#),
+        )
+        .unwrap();
+        assert_eq!(share_balance(&deps, "user"), 1_000);
+        assert_eq!(share_balance(&deps, "friend"), 1_000);
+    }
+
+    #[test]
+    fn test_first_deposit_inflation_is_unprofitable() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            default_instantiate_msg(),
+        )
+        .unwrap();
+        let deposit = |deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>,
+                       sender: &str,
+                       amount: u128| {
+            execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info(sender, &[]),
+                ExecuteMsg::Deposit {
+                    amount: Uint128::new(amount),
+                    min_shares: None,
+                    referrer: None,
+                },
+            )
+        };
+
+        // a first deposit cannot be small enough to own the whole supply
+        mock_balances(&mut deps, 0, 0);
+        let err = deposit(&mut deps, ADDR2, 1_000).unwrap_err();
+        assert!(matches!(err, ContractError::DepositTooSmall { min } if min == DEAD_SHARE_AMOUNT));
+
+        // the attacker takes a single share and donates 10_000 to inflate its price
+        deposit(&mut deps, ADDR2, 1_001).unwrap();
+        assert_eq!(share_balance(&deps, ADDR2), 1);
+        let circulating: CirculatingSupplyResponse =
+            from_json(query(deps.as_ref(), mock_env(), QueryMsg::CirculatingSupply {}).unwrap())
+                .unwrap();
+        assert_eq!(circulating.dead_shares, DEAD_SHARE_AMOUNT);
+        assert_eq!(circulating.circulating_supply, Uint128::one());
+
+        // the victim's deposit still prices against the dead shares' backing
+        mock_balances(&mut deps, 11_001, 0);
+        deposit(&mut deps, "victim", 10_000).unwrap();
+        assert_eq!(share_balance(&deps, "victim"), 909);
+        mock_balances(&mut deps, 21_001, 0);
+        let victim_value = query_u128(
+            &deps,
+            QueryMsg::ConvertToAssets {
+                share: Uint128::new(909),
+            },
+        );
+        assert_eq!(victim_value, 9_994);
+
+        // the attacker paid 11_001 for a share now worth 10
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR2, &[]),
+            ExecuteMsg::Withdraw {
+                share: Uint128::one(),
+                recipient: None,
+                send_to: None,
+            },
+        )
+        .unwrap();
+        assert_eq!(withdrawn_amount(&res), Uint128::new(10));
+
+        // a donation big enough to round a deposit down to nothing makes it fail instead
+        mock_balances(&mut deps, 20_000_000, 0);
+        let err = deposit(&mut deps, "victim", 10_000).unwrap_err();
+        assert!(matches!(err, ContractError::DepositTooSmall { min } if min.is_zero()));
+    }
+
+    #[test]
+    fn test_private_mode_allowlist() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            default_instantiate_msg(),
+        )
+        .unwrap();
+        mock_balances(&mut deps, 10_000, 0);
+        let deposit = |deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>, sender| {
+            execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info(sender, &[]),
+                ExecuteMsg::Deposit {
+                    amount: Uint128::new(10_000),
+                    min_shares: None,
+                    referrer: None,
+                },
+            )
+        };
+
+        // everyone may deposit while the vault is public
+        deposit(&mut deps, "anyone").unwrap();
+
+        // only the owner manages private mode and the list
+        for msg in [
+            ExecuteMsg::SetPrivateMode { enabled: true },
+            ExecuteMsg::UpdateAllowlist {
+                add: vec!["anyone".to_string()],
+                remove: vec![],
+            },
+        ] {
+            let err =
+                execute(deps.as_mut(), mock_env(), mock_info("anyone", &[]), msg).unwrap_err();
+            assert!(matches!(err, ContractError::Unauthorized {}));
+        }
+
+        for msg in [
+            ExecuteMsg::SetPrivateMode { enabled: true },
+            ExecuteMsg::UpdateAllowlist {
+                add: vec!["friend".to_string(), "former".to_string()],
+                remove: vec!["former".to_string()],
+            },
+        ] {
+            execute(deps.as_mut(), mock_env(), mock_info(ADDR1, &[]), msg).unwrap();
+        }
+
+        deposit(&mut deps, "friend").unwrap();
+        let max_deposit = |deps: &OwnedDeps<MockStorage, MockApi, MockQuerier>, address| {
+            query_u128(
+                deps,
+                QueryMsg::MaxDeposit {
+                    address: Addr::unchecked(address),
+                },
+            )
+        };
+        assert!(max_deposit(&deps, "friend") > 0);
+        assert_eq!(max_deposit(&deps, "anyone"), 0);
+        for sender in ["anyone", "former"] {
+            let err = deposit(&mut deps, sender).unwrap_err();
+            assert!(matches!(&err, ContractError::NotAllowlisted { address } if address == sender));
+        }
+
+        // the list only gates deposits: nobody dropped from it is trapped in the vault
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            ExecuteMsg::UpdateAllowlist {
+                add: vec![],
+                remove: vec!["friend".to_string()],
+            },
+        )
+        .unwrap();
+        mock_balances(&mut deps, 20_000, 0);
+        for sender in ["anyone", "friend"] {
+            let held = share_balance(&deps, sender);
+            withdraw(&mut deps, sender, held).unwrap();
+            assert_eq!(share_balance(&deps, sender), 0);
+        }
+    }
+
+    #[test]
+    fn test_capture_direct_deposits() {
+        for staking_addr in [None, Some(STAKING.to_string())] {
+            let mut deps = mock_dependencies();
+            let msg = InstantiateMsg {
+                staking_addr: staking_addr.clone(),
+                ..default_instantiate_msg()
+            };
+            instantiate(deps.as_mut(), mock_env(), mock_info(ADDR1, &[]), msg).unwrap();
+            mock_balances(&mut deps, 0, 0);
+            execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info(ADDR1, &[]),
+                ExecuteMsg::Deposit {
+                    amount: Uint128::new(10_000),
+                    min_shares: None,
+                    referrer: None,
+                },
+            )
+            .unwrap();
+
+            // 250 tokens arrive outside a deposit, left liquid on top of the deposited 10000
+            match staking_addr {
+                Some(_) => mock_balances(&mut deps, 250, 10_000),
+                None => mock_balances(&mut deps, 10_250, 0),
+            }
+            let res = execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info("keeper", &[]),
+                ExecuteMsg::CaptureDirectDeposits {},
+            )
+            .unwrap();
+
+            assert!(res
+                .attributes
+                .iter()
+                .any(|attr| attr.key == "captured" && attr.value == "250"));
+            assert_eq!(
+                TOTAL_SUPPLY.load(deps.as_ref().storage).unwrap(),
+                Uint128::new(10_000)
+            );
+            assert_eq!(share_balance(&deps, ADDR1), 9_000);
+            match staking_addr {
+                Some(_) => {
+                    assert_eq!(res.messages.len(), 1);
+                    match &res.messages[0].msg {
+                        CosmosMsg::Wasm(WasmMsg::Execute { msg, .. }) => {
+                            let send: Cw20ExecuteMsg = from_json(msg).unwrap();
+                            assert!(matches!(
+                                send,
+                                Cw20ExecuteMsg::Send { amount, .. } if amount == Uint128::new(250)
+                            ));
+                        }
+                        other => panic!("unexpected message {:?}", other),
+                    }
+                }
+                None => assert!(res.messages.is_empty()),
+            }
+
+            // a second pass finds nothing new
+            let res = execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info("keeper", &[]),
+                ExecuteMsg::CaptureDirectDeposits {},
+            )
+            .unwrap();
+            assert!(res
+                .attributes
+                .iter()
+                .any(|attr| attr.key == "captured" && attr.value == "0"));
+        }
+    }
+
+    /// Makes the vault's first deposit, of `amount` from `ADDR1`, and leaves the token balance
+    /// mocked at `amount`.
+    fn seed_vault(deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>, amount: u128) {
+        mock_balances(deps, 0, 0);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            ExecuteMsg::Deposit {
+                amount: Uint128::new(amount),
+                min_shares: None,
+                referrer: None,
+            },
+        )
+        .unwrap();
+        mock_balances(deps, amount, 0);
+    }
+
+    #[test]
+    fn test_deposit_overflowing_supply_fails_cleanly() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            default_instantiate_msg(),
+        )
+        .unwrap();
+        let near_max = Uint128::MAX - Uint128::one();
+        TOTAL_SUPPLY.save(deps.as_mut().storage, &near_max).unwrap();
+        BALANCE_OF
+            .save(deps.as_mut().storage, Addr::unchecked(ADDR1), &near_max)
+            .unwrap();
+        // one token backs the whole supply, so a second one would double it
+        mock_balances(&mut deps, 1, 0);
+
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR2, &[]),
+            ExecuteMsg::Deposit {
+                amount: Uint128::one(),
+                min_shares: None,
+                referrer: None,
+            },
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::Std(StdError::Overflow { .. })));
+        assert_eq!(query_total_supply(&deps), near_max.u128());
+    }
+
+    #[test]
+    fn test_staked_value_overflowing_assets_fails_cleanly() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            InstantiateMsg {
+                staking_addr: Some(STAKING.to_string()),
+                ..default_instantiate_msg()
+            },
+        )
+        .unwrap();
+        seed_vault(&mut deps, 10_000);
+        // the liquid balance and the staked position cannot be summed
+        mock_balances(&mut deps, 1, u128::MAX);
+
+        let err = query(deps.as_ref(), mock_env(), QueryMsg::TotalAssets {}).unwrap_err();
+        assert!(matches!(err, StdError::Overflow { .. }));
+        let err = withdraw(&mut deps, ADDR1, 1_000).unwrap_err();
+        assert!(matches!(err, ContractError::Std(StdError::Overflow { .. })));
+    }
+
+    #[test]
+    fn test_total_principal_tracks_deposits_net_of_withdrawals() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            default_instantiate_msg(),
+        )
+        .unwrap();
+        let total_principal =
+            |deps: &OwnedDeps<_, _, _>| query_u128(deps, QueryMsg::TotalPrincipal {});
+        assert_eq!(total_principal(&deps), 0);
+
+        seed_vault(&mut deps, 10_000);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("bob", &[]),
+            ExecuteMsg::Deposit {
+                amount: Uint128::new(5_000),
+                min_shares: None,
+                referrer: None,
+            },
+        )
+        .unwrap();
+        assert_eq!(total_principal(&deps), 15_000);
+
+        // 3000 of yield on top of the principal
+        mock_balances(&mut deps, 18_000, 0);
+        assert_eq!(query_u128(&deps, QueryMsg::TotalAssets {}), 18_000);
+
+        // a fifth of the supply takes a fifth of the principal with it, yield included in
+        // the payout but not in what is written off
+        let res = withdraw(&mut deps, ADDR1, 3_000).unwrap();
+        assert_eq!(withdrawn_amount(&res), Uint128::new(3_600));
+        assert_eq!(total_principal(&deps), 12_000);
+    }
+
+    #[test]
+    fn test_withdraw_errors_name_the_failure() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            default_instantiate_msg(),
+        )
+        .unwrap();
+        seed_vault(&mut deps, 10_000);
+
+        let err = withdraw(&mut deps, ADDR1, 0).unwrap_err();
+        assert!(matches!(err, ContractError::ZeroAmount {}));
+        let err = withdraw(&mut deps, ADDR1, 9_001).unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::InsufficientShares { available, requested }
+                if available == Uint128::new(9_000) && requested == Uint128::new(9_001)
+        ));
+
+        // a drained vault used to fail dividing by its zero balance
+        mock_balances(&mut deps, 0, 0);
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            ExecuteMsg::WithdrawAssets {
+                amount: Uint128::new(100),
+            },
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::InsufficientShares { available, requested }
+                if available.is_zero() && requested == Uint128::new(100)
+        ));
+
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            ExecuteMsg::SetPaused {
+                paused: true,
+                allow_withdrawals: false,
+            },
+        )
+        .unwrap();
+        let err = withdraw(&mut deps, ADDR1, 100).unwrap_err();
+        assert!(matches!(err, ContractError::Paused {}));
+    }
+
+    #[test]
+    fn test_withdraw_assets() {
+        let withdraw_assets = |deps: &mut OwnedDeps<_, _, _>, amount: u128| {
+            execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info(ADDR1, &[]),
+                ExecuteMsg::WithdrawAssets {
+                    amount: Uint128::new(amount),
+                },
+            )
+        };
+
+        // at 1.2 tokens a share, 834 shares are the fewest that pay out 1000
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            default_instantiate_msg(),
+        )
+        .unwrap();
+        seed_vault(&mut deps, 10_000);
+        mock_balances(&mut deps, 12_000, 0);
+        let held = share_balance(&deps, ADDR1);
+        let res = withdraw_assets(&mut deps, 1_000).unwrap();
+        assert_eq!(withdrawn_amount(&res), Uint128::new(1_000));
+        assert!(res.attributes.contains(&attr("share", "834")));
+        assert_eq!(share_balance(&deps, ADDR1), held - 834);
+
+        let err = withdraw_assets(&mut deps, 11_000).unwrap_err();
+        assert!(matches!(err, ContractError::InsufficientShares { .. }));
+        let err = withdraw_assets(&mut deps, 0).unwrap_err();
+        assert!(matches!(err, ContractError::ZeroAmount {}));
+
+        // with share and withdraw fees on top the payout still never falls short, and each of
+        // the three round-ups overshoots by less than a share's worth
+        let mut deps = mock_dependencies();
+        let msg = InstantiateMsg {
+            withdraw_fee_bps: 50,
+            ..fee_instantiate_msg(100)
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info(ADDR1, &[]), msg).unwrap();
+        seed_vault(&mut deps, 10_000);
+        mock_balances(&mut deps, 12_000, 0);
+        for amount in [1u128, 7, 999, 1_000, 2_345] {
+            let paid = withdrawn_amount(&withdraw_assets(&mut deps, amount).unwrap()).u128();
+            assert!(paid >= amount, "asked for {}, paid {}", amount, paid);
+            assert!(paid < amount + 5, "asked for {}, paid {}", amount, paid);
+        }
+    }
+
+    #[test]
+    fn test_redeem_all() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            default_instantiate_msg(),
+        )
+        .unwrap();
+        seed_vault(&mut deps, 10_000);
+        mock_balances(&mut deps, 12_000, 0);
+        assert_eq!(share_balance(&deps, ADDR1), 9_000);
+
+        // 9000 of 10000 shares at 1.2 tokens a share
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            ExecuteMsg::RedeemAll {},
+        )
+        .unwrap();
+        assert_eq!(withdrawn_amount(&res), Uint128::new(10_800));
+        assert!(res.attributes.contains(&attr("share", "9000")));
+        assert!(!BALANCE_OF.has(&deps.storage, Addr::unchecked(ADDR1)));
+        assert_eq!(query_total_supply(&deps), 1_000);
+
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            ExecuteMsg::RedeemAll {},
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::ZeroAmount {}));
+    }
+
+    #[test]
+    fn test_performance_fee_high_water_mark() {
+        let collect = |deps: &mut OwnedDeps<_, _, _>| {
+            let res = execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info("anyone", &[]),
+                ExecuteMsg::CollectFees {},
+            )
+            .unwrap();
+            res.attributes
+                .iter()
+                .find(|attr| attr.key == "fee_shares")
+                .unwrap()
+                .value
+                .parse::<u128>()
+                .unwrap()
+        };
+        let mut deps = mock_dependencies();
+        let msg = InstantiateMsg {
+            performance_fee_bps: 2_000,
+            ..fee_instantiate_msg(0)
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info(ADDR1, &[]), msg).unwrap();
+        seed_vault(&mut deps, 10_000);
+        assert_eq!(collect(&mut deps), 0);
+
+        // 2000 of growth owes 400: 344 new shares are worth that out of 12000 over 10344
+        mock_balances(&mut deps, 12_000, 0);
+        assert_eq!(collect(&mut deps), 344);
+        assert_eq!(share_balance(&deps, FEE_RECIPIENT), 344);
+        assert_eq!(collect(&mut deps), 0);
+
+        // a drawdown charges nothing, and neither does recovering back up to the mark
+        for balance in [11_000, 12_000] {
+            mock_balances(&mut deps, balance, 0);
+            assert_eq!(collect(&mut deps), 0);
+        }
+        assert_eq!(share_balance(&deps, FEE_RECIPIENT), 344);
+
+        // only the 1000 above the previous high is charged, 200 of it as 161 shares
+        mock_balances(&mut deps, 13_000, 0);
+        assert_eq!(collect(&mut deps), 161);
+
+        // a withdraw settles the fee on 1000 more growth first and prices against it
+        mock_balances(&mut deps, 14_000, 0);
+        let res = withdraw(&mut deps, ADDR1, 1_000).unwrap();
+        assert_eq!(share_balance(&deps, FEE_RECIPIENT), 344 + 161 + 152);
+        assert_eq!(
+            withdrawn_amount(&res),
+            Uint128::new(1_000 * 14_000 / 10_657)
+        );
+    }
+
+    #[test]
+    fn test_transfer_shares() {
+        let transfer = |deps: &mut OwnedDeps<_, _, _>, sender: &str, recipient: &str, amount| {
+            execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info(sender, &[]),
+                ExecuteMsg::TransferShares {
+                    recipient: recipient.to_string(),
+                    amount: Uint128::new(amount),
+                },
+            )
+        };
+        let deposited = |deps: &OwnedDeps<_, _, _>, address: &str| {
+            DEPOSITED
+                .may_load(deps.as_ref().storage, Addr::unchecked(address))
+                .unwrap()
+                .unwrap_or_default()
+                .u128()
+        };
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            default_instantiate_msg(),
+        )
+        .unwrap();
+        seed_vault(&mut deps, 10_000);
+
+        let res = transfer(&mut deps, ADDR1, ADDR2, 3_000).unwrap();
+        assert_eq!(
+            res.attributes,
+            vec![
+                attr("action", "transfer_shares"),
+                attr("from", ADDR1),
+                attr("to", ADDR2),
+                attr("share", "3000"),
+            ]
+        );
+        assert_eq!(share_balance(&deps, ADDR1), 6_000);
+        assert_eq!(share_balance(&deps, ADDR2), 3_000);
+        // a third of the shares carries a third of what was deposited
+        assert_eq!(deposited(&deps, ADDR1), 6_667);
+        assert_eq!(deposited(&deps, ADDR2), 3_333);
+
+        let err = transfer(&mut deps, ADDR2, ADDR1, 3_001).unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::InsufficientShares { available, requested }
+                if available == Uint128::new(3_000) && requested == Uint128::new(3_001)
+        ));
+        let err = transfer(&mut deps, ADDR2, ADDR1, 0).unwrap_err();
+        assert!(matches!(err, ContractError::ZeroAmount {}));
+
+        // the shares redeem for the recipient like any others
+        let res = withdraw(&mut deps, ADDR2, 3_000).unwrap();
+        assert_eq!(withdrawn_amount(&res), Uint128::new(3_000));
+        assert_eq!(share_balance(&deps, ADDR2), 0);
+
+        // a private vault only lets shares reach the allow-list
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            ExecuteMsg::SetPrivateMode { enabled: true },
+        )
+        .unwrap();
+        let err = transfer(&mut deps, ADDR1, "outsider", 1_000).unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::NotAllowlisted { address } if address == "outsider"
+        ));
+    }
+
+    #[test]
+    fn test_balance_at_height() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            default_instantiate_msg(),
+        )
+        .unwrap();
+        let at = |height: u64| {
+            let mut env = mock_env();
+            env.block.height = height;
+            env
+        };
+        let balance_at = |deps: &OwnedDeps<_, _, _>, address: &str, height: u64| {
+            query_u128(
+                deps,
+                QueryMsg::BalanceAt {
+                    address: Addr::unchecked(address),
+                    height,
+                },
+            )
+        };
+
+        mock_balances(&mut deps, 0, 0);
+        execute(
+            deps.as_mut(),
+            at(100),
+            mock_info(ADDR1, &[]),
+            ExecuteMsg::Deposit {
+                amount: Uint128::new(10_000),
+                min_shares: None,
+                referrer: None,
+            },
+        )
+        .unwrap();
+        mock_balances(&mut deps, 10_000, 0);
+        execute(
+            deps.as_mut(),
+            at(110),
+            mock_info(ADDR1, &[]),
+            ExecuteMsg::TransferShares {
+                recipient: "bob".to_string(),
+                amount: Uint128::new(3_000),
+            },
+        )
+        .unwrap();
+        execute(
+            deps.as_mut(),
+            at(120),
+            mock_info("bob", &[]),
+            ExecuteMsg::Withdraw {
+                share: Uint128::new(3_000),
+                recipient: None,
+                send_to: None,
+            },
+        )
+        .unwrap();
+
+        for (height, addr1, bob) in [
+            (99, 0, 0),
+            (100, 9_000, 0),
+            (109, 9_000, 0),
+            (110, 6_000, 3_000),
+            (119, 6_000, 3_000),
+            (120, 6_000, 0),
+            (1_000, 6_000, 0),
+        ] {
+            assert_eq!(
+                balance_at(&deps, ADDR1, height),
+                addr1,
+                "addr1 at {}",
+                height
+            );
+            assert_eq!(balance_at(&deps, "bob", height), bob, "bob at {}", height);
+        }
+    }
+
+    #[test]
+    fn test_accrue_leaves_share_math_alone() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            default_instantiate_msg(),
+        )
+        .unwrap();
+        seed_vault(&mut deps, 10_000);
+
+        // anyone may settle, as often as they like
+        for _ in 0..2 {
+            let res = execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info(ADDR2, &[]),
+                ExecuteMsg::Accrue {},
+            )
+            .unwrap();
+            assert!(res.messages.is_empty());
+            assert_eq!(query_total_supply(&deps), 10_000);
+        }
+
+        // two tokens a share, with the deposit settling first
+        mock_balances(&mut deps, 20_000, 0);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR2, &[]),
+            ExecuteMsg::Deposit {
+                amount: Uint128::new(5_000),
+                min_shares: None,
+                referrer: None,
+            },
+        )
+        .unwrap();
+        assert_eq!(share_balance(&deps, ADDR2), 2_500);
+        assert_eq!(query_total_supply(&deps), 12_500);
+    }
+
+    #[test]
+    fn test_deposit_for() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            default_instantiate_msg(),
+        )
+        .unwrap();
+        seed_vault(&mut deps, 10_000);
+        let deposit_for = |recipient: &str| ExecuteMsg::DepositFor {
+            recipient: recipient.to_string(),
+            amount: Uint128::new(5_000),
+        };
+
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            deposit_for(ADDR2),
+        )
+        .unwrap();
+        assert_eq!(share_balance(&deps, ADDR1), 9_000);
+        assert_eq!(share_balance(&deps, ADDR2), 5_000);
+        // the payer funds it
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(WasmMsg::Execute {
+                contract_addr: ADDR2.to_string(),
+                msg: to_json_binary(&Cw20ExecuteMsg::TransferFrom {
+                    owner: ADDR1.to_string(),
+                    recipient: MOCK_CONTRACT_ADDR.to_string(),
+                    amount: Uint128::new(5_000),
+                })
+                .unwrap(),
+                funds: vec![],
+            })]
+        );
+        assert!(res.attributes.contains(&attr("recipient", ADDR2)));
+
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            deposit_for(""),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::Std(_)));
+    }
+
+    #[test]
+    fn test_batch_deposit() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            default_instantiate_msg(),
+        )
+        .unwrap();
+        seed_vault(&mut deps, 10_000);
+        // two tokens a share
+        mock_balances(&mut deps, 20_000, 0);
+        let batch = |deposits: &[(&str, u128)]| ExecuteMsg::BatchDeposit {
+            deposits: deposits
+                .iter()
+                .map(|(recipient, amount)| (recipient.to_string(), Uint128::new(*amount)))
+                .collect(),
+        };
+
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("distributor", &[]),
+            batch(&[("alice", 1_000), ("bob", 2_000), ("carol", 4_000)]),
+        )
+        .unwrap();
+        // later entries see the earlier ones' tokens, so the price holds across the batch
+        assert_eq!(share_balance(&deps, "alice"), 500);
+        assert_eq!(share_balance(&deps, "bob"), 1_000);
+        assert_eq!(share_balance(&deps, "carol"), 2_000);
+        assert_eq!(share_balance(&deps, "distributor"), 0);
+        assert_eq!(query_total_supply(&deps), 13_500);
+        assert_eq!(
+            res.messages,
+            vec![SubMsg::new(WasmMsg::Execute {
+                contract_addr: ADDR2.to_string(),
+                msg: to_json_binary(&Cw20ExecuteMsg::TransferFrom {
+                    owner: "distributor".to_string(),
+                    recipient: MOCK_CONTRACT_ADDR.to_string(),
+                    amount: Uint128::new(7_000),
+                })
+                .unwrap(),
+                funds: vec![],
+            })]
+        );
+
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("distributor", &[]),
+            batch(&[("alice", 1); MAX_BATCH_DEPOSITS + 1]),
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::BatchTooLarge { len, max } if len == MAX_BATCH_DEPOSITS + 1 && max == MAX_BATCH_DEPOSITS
+        ));
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info("distributor", &[]),
+            batch(&[]),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::ZeroAmount {}));
+    }
+
+    #[test]
+    fn test_deposit_referrer() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            default_instantiate_msg(),
+        )
+        .unwrap();
+        mock_balances(&mut deps, 0, 0);
+        let deposit = |deps: &mut OwnedDeps<_, _, _>, amount: u128, referrer: Option<&str>| {
+            execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info(ADDR1, &[]),
+                ExecuteMsg::Deposit {
+                    amount: Uint128::new(amount),
+                    min_shares: None,
+                    referrer: referrer.map(str::to_string),
+                },
+            )
+        };
+        let volume = |deps: &OwnedDeps<_, _, _>| {
+            query_u128(
+                deps,
+                QueryMsg::ReferralVolume {
+                    referrer: Addr::unchecked("referrer"),
+                },
+            )
+        };
+
+        let res = deposit(&mut deps, 10_000, None).unwrap();
+        assert!(!res.attributes.iter().any(|attr| attr.key == "referrer"));
+        assert_eq!(volume(&deps), 0);
+        mock_balances(&mut deps, 10_000, 0);
+
+        let res = deposit(&mut deps, 2_000, Some("referrer")).unwrap();
+        assert!(res.attributes.contains(&attr("referrer", "referrer")));
+        deposit(&mut deps, 500, Some("referrer")).unwrap();
+        assert_eq!(volume(&deps), 2_500);
+
+        let err = deposit(&mut deps, 500, Some("Referrer")).unwrap_err();
+        assert!(matches!(err, ContractError::Std(_)));
+        assert_eq!(volume(&deps), 2_500);
+    }
+
+    #[test]
+    fn test_deposit_min_shares() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            default_instantiate_msg(),
+        )
+        .unwrap();
+        seed_vault(&mut deps, 10_000);
+        // a harvest doubled the share price after the depositor quoted 1000 shares
+        mock_balances(&mut deps, 20_000, 0);
+        let deposit = |min_shares: u128| ExecuteMsg::Deposit {
+            amount: Uint128::new(1_000),
+            min_shares: Some(Uint128::new(min_shares)),
+            referrer: None,
+        };
+
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR2, &[]),
+            deposit(500),
+        )
+        .unwrap();
+        assert!(res.attributes.contains(&attr("shares_minted", "500")));
+        assert_eq!(share_balance(&deps, ADDR2), 500);
+
+        mock_balances(&mut deps, 21_000, 0);
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR2, &[]),
+            deposit(1_000),
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::SlippageExceeded { min, actual }
+                if min == Uint128::new(1_000) && actual == Uint128::new(500)
+        ));
+    }
+
+    #[test]
+    fn test_pending_withdrawals() {
+        let mut deps = mock_dependencies();
+        let msg = InstantiateMsg {
+            withdraw_delay: 100,
+            ..default_instantiate_msg()
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info(ADDR1, &[]), msg).unwrap();
+        seed_vault(&mut deps, 10_000);
+        let at = |offset: u64| {
+            let mut env = mock_env();
+            env.block.time = env.block.time.plus_seconds(offset);
+            env
+        };
+        let pending = |deps: &OwnedDeps<_, _, _>, offset: u64| {
+            from_json::<PendingWithdrawalsResponse>(
+                query(
+                    deps.as_ref(),
+                    at(offset),
+                    QueryMsg::PendingWithdrawals {
+                        address: Addr::unchecked(ADDR1),
+                    },
+                )
+                .unwrap(),
+            )
+            .unwrap()
+            .withdrawals
+        };
+        assert!(pending(&deps, 0).is_empty());
+
+        for (offset, share) in [(0, 4_000u128), (50, 1_000)] {
+            execute(
+                deps.as_mut(),
+                at(offset),
+                mock_info(ADDR1, &[]),
+                ExecuteMsg::RequestWithdraw {
+                    share: Uint128::new(share),
+                },
+            )
+            .unwrap();
+        }
+        let start = mock_env().block.time.seconds();
+        let entry =
+            |request_id: u64, unlock_time: u64, amount: u128, claimable: bool| PendingWithdrawal {
+                request_id,
+                amount: Uint128::new(amount),
+                unlock_time,
+                claimable,
+            };
+
+        // only the first request has unlocked 120 seconds in
+        assert_eq!(
+            pending(&deps, 120),
+            vec![
+                entry(0, start + 100, 4_000, true),
+                entry(1, start + 150, 1_000, false)
+            ]
+        );
+        assert_eq!(
+            pending(&deps, 150),
+            vec![
+                entry(0, start + 100, 4_000, true),
+                entry(1, start + 150, 1_000, true)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_withdraw_queue() {
+        let mut deps = mock_dependencies();
+        let msg = InstantiateMsg {
+            withdraw_delay: 100,
+            ..default_instantiate_msg()
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info(ADDR1, &[]), msg).unwrap();
+        seed_vault(&mut deps, 10_000);
+        let held = share_balance(&deps, ADDR1);
+        let at = |offset: u64| {
+            let mut env = mock_env();
+            env.block.time = env.block.time.plus_seconds(offset);
+            env
+        };
+        let request = |deps: &mut OwnedDeps<_, _, _>, offset: u64, share: u128| {
+            execute(
+                deps.as_mut(),
+                at(offset),
+                mock_info(ADDR1, &[]),
+                ExecuteMsg::RequestWithdraw {
+                    share: Uint128::new(share),
+                },
+            )
+        };
+        let claim = |deps: &mut OwnedDeps<_, _, _>, offset: u64| {
+            execute(
+                deps.as_mut(),
+                at(offset),
+                mock_info(ADDR1, &[]),
+                ExecuteMsg::ClaimWithdraw {},
+            )
+        };
+        let start = mock_env().block.time.seconds();
+
+        let err = withdraw(&mut deps, ADDR1, 1_000).unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::WithdrawalsQueued { delay: 100 }
+        ));
+        let err = claim(&mut deps, 0).unwrap_err();
+        assert!(matches!(err, ContractError::NothingToClaim {}));
+
+        // the shares are gone at once and their tokens stop backing the rest
+        let res = request(&mut deps, 0, 4_000).unwrap();
+        assert!(res.messages.is_empty());
+        assert!(res
+            .attributes
+            .contains(&attr("unlock_at", (start + 100).to_string())));
+        assert_eq!(share_balance(&deps, ADDR1), held - 4_000);
+        assert_eq!(query_u128(&deps, QueryMsg::TotalAssets {}), 6_000);
+        assert_eq!(
+            query(deps.as_ref(), mock_env(), QueryMsg::PricePerShare {}).unwrap(),
+            to_json_binary(&Decimal::one()).unwrap()
+        );
+        request(&mut deps, 50, 1_000).unwrap();
+
+        let err = claim(&mut deps, 99).unwrap_err();
+        assert!(
+            matches!(err, ContractError::WithdrawNotReady { unlock_at } if unlock_at == start + 100)
+        );
+
+        // only the request that unlocked is paid; the later one stays queued
+        let res = claim(&mut deps, 100).unwrap();
+        assert_eq!(withdrawn_amount(&res), Uint128::new(4_000));
+        assert!(res.attributes.contains(&attr("still_queued", "1")));
+        mock_balances(&mut deps, 6_000, 0);
+        assert_eq!(query_u128(&deps, QueryMsg::TotalAssets {}), 5_000);
+        let err = claim(&mut deps, 100).unwrap_err();
+        assert!(
+            matches!(err, ContractError::WithdrawNotReady { unlock_at } if unlock_at == start + 150)
+        );
+
+        let res = claim(&mut deps, 150).unwrap();
+        assert_eq!(withdrawn_amount(&res), Uint128::new(1_000));
+        mock_balances(&mut deps, 5_000, 0);
+        assert_eq!(query_u128(&deps, QueryMsg::TotalAssets {}), 5_000);
+        let err = claim(&mut deps, 150).unwrap_err();
+        assert!(matches!(err, ContractError::NothingToClaim {}));
+    }
+
+    #[test]
+    fn test_cancel_withdraw() {
+        let mut deps = mock_dependencies();
+        let msg = InstantiateMsg {
+            withdraw_delay: 100,
+            withdraw_fee_bps: 100,
+            ..fee_instantiate_msg(0)
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info(ADDR1, &[]), msg).unwrap();
+        seed_vault(&mut deps, 10_000);
+        let held = share_balance(&deps, ADDR1);
+        let request = |deps: &mut OwnedDeps<_, _, _>, share: u128| {
+            execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info(ADDR1, &[]),
+                ExecuteMsg::RequestWithdraw {
+                    share: Uint128::new(share),
+                },
+            )
+        };
+        let cancel = |deps: &mut OwnedDeps<_, _, _>, sender: &str, request_id: u64| {
+            execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info(sender, &[]),
+                ExecuteMsg::CancelWithdraw { request_id },
+            )
+        };
+        let set_paused = |deps: &mut OwnedDeps<_, _, _>, paused: bool| {
+            execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info(ADDR1, &[]),
+                ExecuteMsg::SetPaused {
+                    paused,
+                    allow_withdrawals: false,
+                },
+            )
+            .unwrap();
+        };
+
+        // two requests in one block still get ids of their own, and hold their fee back
+        let res = request(&mut deps, 4_000).unwrap();
+        assert!(res.messages.is_empty());
+        assert!(res.attributes.contains(&attr("request_id", "0")));
+        let res = request(&mut deps, 1_000).unwrap();
+        assert!(res.attributes.contains(&attr("request_id", "1")));
+        assert_eq!(share_balance(&deps, ADDR1), held - 5_000);
+
+        // only the requester's own entry under that id can be cancelled
+        let err = cancel(&mut deps, ADDR1, 2).unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::NoWithdrawRequest { request_id: 2 }
+        ));
+        let err = cancel(&mut deps, "bob", 0).unwrap_err();
+        assert!(matches!(err, ContractError::NoWithdrawRequest { .. }));
+
+        set_paused(&mut deps, true);
+        let err = cancel(&mut deps, ADDR1, 0).unwrap_err();
+        assert!(matches!(err, ContractError::Paused {}));
+        set_paused(&mut deps, false);
+
+        // yield while queued doubles the share price; the cancel still restores the
+        // burned shares one for one and returns the held-back fee to the vault
+        mock_balances(&mut deps, 15_000, 0);
+        let res = cancel(&mut deps, ADDR1, 0).unwrap();
+        assert!(res.attributes.contains(&attr("shares_minted", "4000")));
+        assert_eq!(share_balance(&deps, ADDR1), held - 1_000);
+        assert_eq!(query_total_supply(&deps), 9_000);
+        assert_eq!(query_u128(&deps, QueryMsg::TotalAssets {}), 14_000);
+        let err = cancel(&mut deps, ADDR1, 0).unwrap_err();
+        assert!(matches!(err, ContractError::NoWithdrawRequest { .. }));
+
+        // the other request is untouched and pays its fee out when claimed
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(100);
+        let res = execute(
+            deps.as_mut(),
+            env,
+            mock_info(ADDR1, &[]),
+            ExecuteMsg::ClaimWithdraw {},
+        )
+        .unwrap();
+        assert_eq!(withdrawn_amount(&res), Uint128::new(990));
+        assert_eq!(
+            res.messages[1].msg,
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: ADDR2.to_string(),
+                msg: to_json_binary(&Cw20ExecuteMsg::Transfer {
+                    recipient: FEE_RECIPIENT.to_string(),
+                    amount: Uint128::new(10),
+                })
+                .unwrap(),
+                funds: vec![],
+            })
+        );
+    }
+
+    #[test]
+    fn test_cancel_withdraw_with_no_assets_left() {
+        let mut deps = mock_dependencies();
+        let msg = InstantiateMsg {
+            withdraw_delay: 100,
+            ..default_instantiate_msg()
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info(ADDR1, &[]), msg).unwrap();
+        seed_vault(&mut deps, 10_000);
+        let held = share_balance(&deps, ADDR1);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            ExecuteMsg::RequestWithdraw {
+                share: Uint128::new(held),
+            },
+        )
+        .unwrap();
+        // a loss leaves the dead shares backed by nothing
+        mock_balances(&mut deps, held, 0);
+        assert_eq!(query_u128(&deps, QueryMsg::TotalAssets {}), 0);
+
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            ExecuteMsg::CancelWithdraw { request_id: 0 },
+        )
+        .unwrap();
+        assert!(res
+            .attributes
+            .contains(&attr("shares_minted", held.to_string())));
+        assert_eq!(share_balance(&deps, ADDR1), held);
+    }
+
+    #[test]
+    fn test_cancel_withdraw_returns_share_fee() {
+        let mut deps = mock_dependencies();
+        let msg = InstantiateMsg {
+            withdraw_delay: 100,
+            withdraw_fee_bps: 100,
+            ..fee_instantiate_msg(100)
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info(ADDR1, &[]), msg).unwrap();
+        seed_vault(&mut deps, 10_000);
+        let held = share_balance(&deps, ADDR1);
+        let fee_held = share_balance(&deps, FEE_RECIPIENT);
+        let request = |deps: &mut OwnedDeps<_, _, _>| {
+            execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info(ADDR1, &[]),
+                ExecuteMsg::RequestWithdraw {
+                    share: Uint128::new(4_000),
+                },
+            )
+            .unwrap();
+        };
+        let consistent = |deps: &OwnedDeps<MockStorage, MockApi, MockQuerier>| {
+            from_json::<InvariantResponse>(
+                query(deps.as_ref(), mock_env(), QueryMsg::VerifyInvariant {}).unwrap(),
+            )
+            .unwrap()
+            .consistent
+        };
+
+        // the share fee waits with the vault instead of reaching the recipient
+        request(&mut deps);
+        assert_eq!(share_balance(&deps, ADDR1), held - 4_000);
+        assert_eq!(share_balance(&deps, FEE_RECIPIENT), fee_held);
+        assert_eq!(share_balance(&deps, MOCK_CONTRACT_ADDR), 40);
+        assert!(consistent(&deps));
+
+        // a cancel gives back every share the request took
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            ExecuteMsg::CancelWithdraw { request_id: 0 },
+        )
+        .unwrap();
+        assert!(res.attributes.contains(&attr("shares_minted", "3960")));
+        assert!(res.attributes.contains(&attr("fee_shares_returned", "40")));
+        assert_eq!(share_balance(&deps, ADDR1), held);
+        assert_eq!(share_balance(&deps, FEE_RECIPIENT), fee_held);
+        assert_eq!(share_balance(&deps, MOCK_CONTRACT_ADDR), 0);
+        assert!(consistent(&deps));
+
+        // a claim pays the share fee to the recipient
+        request(&mut deps);
+        let mut env = mock_env();
+        env.block.time = env.block.time.plus_seconds(100);
+        execute(
+            deps.as_mut(),
+            env,
+            mock_info(ADDR1, &[]),
+            ExecuteMsg::ClaimWithdraw {},
+        )
+        .unwrap();
+        assert_eq!(share_balance(&deps, ADDR1), held - 4_000);
+        assert_eq!(share_balance(&deps, FEE_RECIPIENT), fee_held + 40);
+        assert_eq!(share_balance(&deps, MOCK_CONTRACT_ADDR), 0);
+        assert!(consistent(&deps));
+    }
+
+    #[test]
+    fn test_max_redeem_and_withdraw() {
+        let mut deps = mock_dependencies();
+        let msg = InstantiateMsg {
+            withdraw_fee_bps: 50,
+            ..fee_instantiate_msg(100)
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info(ADDR1, &[]), msg).unwrap();
+        seed_vault(&mut deps, 10_000);
+        // yield, so the redeemed amount is not just the share count
+        mock_balances(&mut deps, 12_000, 0);
+        let max_redeem = |deps: &OwnedDeps<MockStorage, MockApi, MockQuerier>, address: &str| {
+            query_u128(
+                deps,
+                QueryMsg::MaxRedeem {
+                    address: Addr::unchecked(address),
+                },
+            )
+        };
+        let max_withdraw = |deps: &OwnedDeps<MockStorage, MockApi, MockQuerier>, address: &str| {
+            query_u128(
+                deps,
+                QueryMsg::MaxWithdraw {
+                    address: Addr::unchecked(address),
+                },
+            )
+        };
+
+        assert_eq!(max_redeem(&deps, ADDR1), share_balance(&deps, ADDR1));
+        assert_eq!(max_redeem(&deps, "nobody"), 0);
+        assert_eq!(max_withdraw(&deps, "nobody"), 0);
+
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            ExecuteMsg::SetPaused {
+                paused: true,
+                allow_withdrawals: false,
+            },
+        )
+        .unwrap();
+        assert_eq!(max_redeem(&deps, ADDR1), 0);
+        assert_eq!(max_withdraw(&deps, ADDR1), 0);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            ExecuteMsg::SetPaused {
+                paused: false,
+                allow_withdrawals: false,
+            },
+        )
+        .unwrap();
+
+        // the preview matches what redeeming everything actually pays out
+        let expected = max_withdraw(&deps, ADDR1);
+        let share = max_redeem(&deps, ADDR1);
+        let res = withdraw(&mut deps, ADDR1, share).unwrap();
+        assert!(res
+            .attributes
+            .contains(&attr("assets_returned", expected.to_string())));
+        assert_eq!(share_balance(&deps, ADDR1), 0);
+    }
+
+    #[test]
+    fn test_rounding_never_leaks_value() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            default_instantiate_msg(),
+        )
+        .unwrap();
+        let users = [ADDR1, "user2", "user3"];
+        let mut balance = 0u128;
+        let mut seed = 7u64;
+        let mut next = |bound: u128| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 33) as u128 % bound
+        };
+        let supply = |deps: &OwnedDeps<MockStorage, MockApi, MockQuerier>| {
+            TOTAL_SUPPLY.load(deps.as_ref().storage).unwrap().u128()
+        };
+
+        let mut price = (0u128, 0u128);
+        for step in 0..300 {
+            let user = users[step % users.len()];
+            let held = share_balance(&deps, user);
+            if held == 0 || next(3) == 0 {
+                let amount = 1_000 + next(100_000);
+                mock_balances(&mut deps, balance, 0);
+                execute(
+                    deps.as_mut(),
+                    mock_env(),
+                    mock_info(user, &[]),
+                    ExecuteMsg::Deposit {
+                        amount: Uint128::new(amount),
+                        min_shares: None,
+                        referrer: None,
+                    },
+                )
+                .unwrap();
+                balance += amount;
+            } else {
+                mock_balances(&mut deps, balance, 0);
+                let res = withdraw(&mut deps, user, 1 + next(held)).unwrap();
+                let returned = res
+                    .attributes
+                    .iter()
+                    .find(|attr| attr.key == "assets_returned")
+                    .unwrap();
+                balance -= returned.value.parse::<u128>().unwrap();
+            }
+            if next(10) == 0 {
+                // yield makes the share price a fraction that every later division rounds
+                balance += next(997);
+            }
+
+            // assets per share never fall: new_assets / new_supply >= old_assets / old_supply
+            let (old_assets, old_supply) = price;
+            let new_supply = supply(&deps);
+            assert!(
+                balance * old_supply >= old_assets * new_supply,
+                "step {}",
+                step
+            );
+            price = (balance, new_supply);
+        }
+
+        // everyone leaves; the dead shares are still fully backed
+        for user in users {
+            let held = share_balance(&deps, user);
+            if held > 0 {
+                mock_balances(&mut deps, balance, 0);
+                let res = withdraw(&mut deps, user, held).unwrap();
+                let returned = res
+                    .attributes
+                    .iter()
+                    .find(|attr| attr.key == "assets_returned")
+                    .unwrap();
+                balance -= returned.value.parse::<u128>().unwrap();
+            }
+        }
+        assert_eq!(supply(&deps), DEAD_SHARE_AMOUNT.u128());
+        assert!(balance >= DEAD_SHARE_AMOUNT.u128());
+    }
+
+    #[test]
+    fn test_fee_drag_matches_configured_fees() {
+        let mut deps = mock_dependencies();
+        // 1% in, 1% out
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            fee_instantiate_msg(100),
+        )
+        .unwrap();
+
+        let drag: FeeDragResponse = from_json(
+            query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::FeeDrag {
+                    horizon_seconds: 365 * 24 * 60 * 60 / 2,
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(drag.deposit_fee, Decimal::percent(1));
+        assert_eq!(drag.withdraw_fee, Decimal::percent(1));
+        assert_eq!(drag.performance_fee, Decimal::zero());
+        // 1 - 0.99 * 0.99, paid over half a year
+        assert_eq!(drag.total_drag, Decimal::from_ratio(199u128, 10_000u128));
+        assert_eq!(
+            drag.annualized_drag,
+            Decimal::from_ratio(398u128, 10_000u128)
+        );
+
+        // the round trip really costs that much, once the first deposit has locked its
+        // dead shares
+        seed_vault(&mut deps, 10_000);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR2, &[]),
+            ExecuteMsg::Deposit {
+                amount: Uint128::new(10_000),
+                min_shares: None,
+                referrer: None,
+            },
+        )
+        .unwrap();
+        mock_balances(&mut deps, 20_000, 0);
+        let share = Uint128::new(share_balance(&deps, ADDR2));
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR2, &[]),
+            ExecuteMsg::Withdraw {
+                share,
+                recipient: None,
+                send_to: None,
+            },
+        )
+        .unwrap();
+        assert_eq!(withdrawn_amount(&res), Uint128::new(10_000 - 199));
+    }
+
+    fn vault_info(deps: &OwnedDeps<MockStorage, MockApi, MockQuerier>) -> VaultInfoResponse {
+        from_json(query(deps.as_ref(), mock_env(), QueryMsg::VaultInfo {}).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_metadata_uri() {
+        let mut deps = mock_dependencies();
+        let msg = InstantiateMsg {
+            metadata_uri: Some("ipfs://bafy/vault.json".to_string()),
+            ..default_instantiate_msg()
+        };
+        instantiate(deps.as_mut(), mock_env(), mock_info(ADDR1, &[]), msg).unwrap();
+        let info = vault_info(&deps);
+        assert_eq!(info.owner, Addr::unchecked(ADDR1));
+        assert_eq!(info.metadata_uri.as_deref(), Some("ipfs://bafy/vault.json"));
+
+        let set = |uri: Option<&str>| ExecuteMsg::SetMetadataUri {
+            metadata_uri: uri.map(str::to_string),
+        };
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR2, &[]),
+            set(Some("https://example.com/vault.json")),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            set(Some("https://example.com/vault.json")),
+        )
+        .unwrap();
+        assert_eq!(
+            vault_info(&deps).metadata_uri.as_deref(),
+            Some("https://example.com/vault.json")
+        );
+
+        let too_long = format!("https://{}", "a".repeat(MAX_METADATA_URI_LEN));
+        for bad in [
+            "",
+            "example.com",
+            "https://",
+            "https://a b",
+            too_long.as_str(),
+        ] {
+            let err = execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info(ADDR1, &[]),
+                set(Some(bad)),
+            )
+            .unwrap_err();
+            assert!(matches!(err, ContractError::InvalidMetadataUri { .. }));
+        }
+        let msg = InstantiateMsg {
+            metadata_uri: Some(String::new()),
+            ..default_instantiate_msg()
+        };
+        let err = instantiate(deps.as_mut(), mock_env(), mock_info(ADDR1, &[]), msg).unwrap_err();
+        assert!(matches!(err, ContractError::InvalidMetadataUri { .. }));
+
+        execute(deps.as_mut(), mock_env(), mock_info(ADDR1, &[]), set(None)).unwrap();
+        assert_eq!(vault_info(&deps).metadata_uri, None);
+    }
+
+    #[test]
+    fn test_migrate_backfills_snapshots_in_batches() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            default_instantiate_msg(),
+        )
+        .unwrap();
+        // balances written before snapshots existed, plus one holder who has moved since
+        let holders = [
+            ("holder1", 100),
+            ("holder2", 200),
+            ("holder3", 300),
+            ("holder4", 400),
+        ];
+        for (address, balance) in holders {
+            BALANCE_OF
+                .save(
+                    deps.as_mut().storage,
+                    Addr::unchecked(address),
+                    &Uint128::new(balance),
+                )
+                .unwrap();
+        }
+        SNAPSHOTS
+            .save(
+                deps.as_mut().storage,
+                (Addr::unchecked("holder2"), 1),
+                &Uint128::new(200),
+            )
+            .unwrap();
+        cw2::set_contract_version(deps.as_mut().storage, CONTRACT_NAME, "0.0.1").unwrap();
+
+        let batch = MigrateMsg {
+            balance_batch_limit: Some(3),
+            ..MigrateMsg::default()
+        };
+        let res = migrate(deps.as_mut(), mock_env(), batch.clone()).unwrap();
+        assert!(res.attributes.contains(&attr("snapshots_backfilled", "2")));
+        assert!(res
+            .attributes
+            .contains(&attr("migration_complete", "false")));
+        let stored = cw2::get_contract_version(deps.as_ref().storage).unwrap();
+        assert_eq!(stored.version, "0.0.1");
+
+        // the next call resumes after the cursor and finishes the upgrade
+        let res = migrate(deps.as_mut(), mock_env(), batch).unwrap();
+        assert!(res.attributes.contains(&attr("snapshots_backfilled", "1")));
+        assert!(res
+            .attributes
+            .contains(&attr("to_version", CONTRACT_VERSION)));
+        let stored = cw2::get_contract_version(deps.as_ref().storage).unwrap();
+        assert_eq!(stored.version, CONTRACT_VERSION);
+        assert!(SNAPSHOT_BACKFILL_CURSOR
+            .may_load(deps.as_ref().storage)
+            .unwrap()
+            .is_none());
+
+        let height = mock_env().block.height;
+        for (address, balance) in holders {
+            assert_eq!(share_balance(&deps, address), balance);
+            let at: Uint128 = from_json(
+                query(
+                    deps.as_ref(),
+                    mock_env(),
+                    QueryMsg::BalanceAt {
+                        address: Addr::unchecked(address),
+                        height,
+                    },
+                )
+                .unwrap(),
+            )
+            .unwrap();
+            assert_eq!(at, Uint128::new(balance));
+        }
+        // an existing history is left alone
+        let keys = SNAPSHOTS
+            .prefix(Addr::unchecked("holder2"))
+            .keys(deps.as_ref().storage, None, None, Order::Ascending)
+            .collect::<StdResult<Vec<_>>>()
+            .unwrap();
+        assert_eq!(keys, vec![1]);
+    }
+
+    #[test]
+    fn test_migrate() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            default_instantiate_msg(),
+        )
+        .unwrap();
+        cw2::set_contract_version(deps.as_mut().storage, CONTRACT_NAME, "0.0.1").unwrap();
+
+        let res = migrate(
+            deps.as_mut(),
+            mock_env(),
+            MigrateMsg {
+                metadata_uri: Some("ipfs://bafy/vault.json".to_string()),
+                ..MigrateMsg::default()
+            },
+        )
+        .unwrap();
+        let attr = |key: &str| {
+            res.attributes
+                .iter()
+                .find(|attr| attr.key == key)
+                .map(|attr| attr.value.clone())
+        };
+        assert_eq!(attr("from_version").as_deref(), Some("0.0.1"));
+        assert_eq!(attr("to_version").as_deref(), Some(CONTRACT_VERSION));
+        let stored = cw2::get_contract_version(deps.as_ref().storage).unwrap();
+        assert_eq!(stored.version, CONTRACT_VERSION);
+        assert_eq!(
+            vault_info(&deps).metadata_uri.as_deref(),
+            Some("ipfs://bafy/vault.json")
+        );
+
+        // migrating to the same version again is a no-op upgrade
+        migrate(deps.as_mut(), mock_env(), MigrateMsg::default()).unwrap();
+
+        cw2::set_contract_version(deps.as_mut().storage, CONTRACT_NAME, "99.0.0").unwrap();
+        let err = migrate(deps.as_mut(), mock_env(), MigrateMsg::default()).unwrap_err();
+        assert!(matches!(err, ContractError::MigrationDowngrade { from, .. } if from == "99.0.0"));
+
+        cw2::set_contract_version(deps.as_mut().storage, "crates.io:other", "0.0.1").unwrap();
+        let err = migrate(deps.as_mut(), mock_env(), MigrateMsg::default()).unwrap_err();
+        assert!(matches!(
+            err,
+            ContractError::MigrationContractMismatch { contract } if contract == "crates.io:other"
+        ));
+    }
+
+    /// The `admin_action` and the remaining attributes of the single `admin` event in `res`.
+    fn admin_event(res: &Response) -> (String, Vec<(String, String)>) {
+        let events: Vec<_> = res.events.iter().filter(|ev| ev.ty == "admin").collect();
+        assert_eq!(events.len(), 1);
+        let (action, fields) = events[0].attributes.split_first().unwrap();
+        assert_eq!(action.key, "admin_action");
+        (
+            action.value.clone(),
+            fields
+                .iter()
+                .map(|attr| (attr.key.clone(), attr.value.clone()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_update_config() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            default_instantiate_msg(),
+        )
+        .unwrap();
+        let update = |deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>,
+                      sender: &str,
+                      new_owner: Option<&str>,
+                      new_token: Option<&str>| {
+            execute(
+                deps.as_mut(),
+                mock_env(),
+                mock_info(sender, &[]),
+                ExecuteMsg::UpdateConfig {
+                    new_owner: new_owner.map(str::to_string),
+                    new_token: new_token.map(str::to_string),
+                },
+            )
+        };
+
+        let err = update(&mut deps, ADDR2, Some(ADDR2), None).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+        assert!(update(&mut deps, ADDR1, Some(""), None).is_err());
+
+        update(&mut deps, ADDR1, Some(ADDR2), Some("newtoken")).unwrap();
+        let info = vault_info(&deps);
+        assert_eq!(info.owner, ADDR2);
+        assert_eq!(info.token, AssetKind::Cw20(Addr::unchecked("newtoken")));
+
+        // the previous owner has lost control
+        let err = update(&mut deps, ADDR1, Some(ADDR1), None).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+
+        // shares backed by the current token pin it in place
+        TOTAL_SUPPLY
+            .save(deps.as_mut().storage, &Uint128::new(1))
+            .unwrap();
+        let err = update(&mut deps, ADDR2, None, Some(ADDR2)).unwrap_err();
+        assert!(matches!(err, ContractError::TokenChangeWithSupply { .. }));
+        assert_eq!(
+            vault_info(&deps).token,
+            AssetKind::Cw20(Addr::unchecked("newtoken"))
+        );
+    }
+
+    #[test]
+    fn test_two_step_ownership_transfer() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            default_instantiate_msg(),
+        )
+        .unwrap();
+        let run = |deps: &mut OwnedDeps<MockStorage, MockApi, MockQuerier>,
+                   sender: &str,
+                   msg: ExecuteMsg| {
+            execute(deps.as_mut(), mock_env(), mock_info(sender, &[]), msg)
+        };
+        let propose = |address: &str| ExecuteMsg::ProposeNewOwner {
+            address: address.to_string(),
+        };
+
+        let err = run(&mut deps, ADDR1, ExecuteMsg::AcceptOwnership {}).unwrap_err();
+        assert!(matches!(err, ContractError::NoPendingOwner {}));
+        let err = run(&mut deps, ADDR2, propose(ADDR2)).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+
+        // a proposal alone changes nothing, and only the proposed address can accept it
+        run(&mut deps, ADDR1, propose(ADDR2)).unwrap();
+        assert_eq!(vault_info(&deps).owner, ADDR1);
+        for sender in [ADDR1, STAKING] {
+            let err = run(&mut deps, sender, ExecuteMsg::AcceptOwnership {}).unwrap_err();
+            assert!(matches!(err, ContractError::Unauthorized {}));
+        }
+
+        run(&mut deps, ADDR2, ExecuteMsg::AcceptOwnership {}).unwrap();
+        assert_eq!(vault_info(&deps).owner, ADDR2);
+        let err = run(&mut deps, ADDR2, ExecuteMsg::AcceptOwnership {}).unwrap_err();
+        assert!(matches!(err, ContractError::NoPendingOwner {}));
+        let err = run(&mut deps, ADDR1, propose(ADDR1)).unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+    }
+
+    #[test]
+    fn test_admin_actions_emit_events() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            default_instantiate_msg(),
+        )
+        .unwrap();
+        let field = |key: &str, value: &str| (key.to_string(), value.to_string());
+        let cases = vec![
+            (
+                ExecuteMsg::SetMetadataUri {
+                    metadata_uri: Some("ipfs://bafy/vault.json".to_string()),
+                },
+                "set_metadata_uri",
+                vec![
+                    field("old_metadata_uri", ""),
+                    field("new_metadata_uri", "ipfs://bafy/vault.json"),
+                ],
+            ),
+            (
+                ExecuteMsg::SetMetadataUri { metadata_uri: None },
+                "set_metadata_uri",
+                vec![
+                    field("old_metadata_uri", "ipfs://bafy/vault.json"),
+                    field("new_metadata_uri", ""),
+                ],
+            ),
+            (
+                ExecuteMsg::SetPrivateMode { enabled: true },
+                "set_private_mode",
+                vec![
+                    field("old_private_mode", "false"),
+                    field("new_private_mode", "true"),
+                ],
+            ),
+            (
+                ExecuteMsg::UpdateAllowlist {
+                    add: vec![ADDR1.to_string(), ADDR2.to_string()],
+                    remove: vec![STAKING.to_string()],
+                },
+                "update_allowlist",
+                vec![field("added", "addr1,addr2"), field("removed", "staking")],
+            ),
+            (
+                ExecuteMsg::SetPaused {
+                    paused: true,
+                    allow_withdrawals: true,
+                },
+                "set_paused",
+                vec![
+                    field("old_paused", "false"),
+                    field("new_paused", "true"),
+                    field("allow_withdrawals", "true"),
+                ],
+            ),
+            (
+                ExecuteMsg::SetCap {
+                    cap: Some(Uint128::new(500)),
+                },
+                "set_cap",
+                vec![field("old_cap", ""), field("new_cap", "500")],
+            ),
+            (
+                ExecuteMsg::UpdateConfig {
+                    new_owner: Some(ADDR2.to_string()),
+                    new_token: None,
+                },
+                "update_config",
+                vec![field("old_owner", ADDR1), field("new_owner", ADDR2)],
+            ),
+        ];
+        for (msg, action, fields) in cases {
+            let res = execute(deps.as_mut(), mock_env(), mock_info(ADDR1, &[]), msg).unwrap();
+            assert_eq!(admin_event(&res), (action.to_string(), fields));
+        }
+    }
+
+    #[test]
+    fn test_sweep_token() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            default_instantiate_msg(),
+        )
+        .unwrap();
+        // 42 of an unrelated token landed in the vault next to 1000 of its own
+        deps.querier.update_wasm(|query| match query {
+            WasmQuery::Smart { contract_addr, .. } => {
+                let balance = if contract_addr == ADDR2 { 1_000 } else { 42 };
+                SystemResult::Ok(ContractResult::Ok(
+                    to_json_binary(&BalanceResponse {
+                        balance: Uint128::new(balance),
+                    })
+                    .unwrap(),
+                ))
+            }
+            _ => SystemResult::Err(SystemError::UnsupportedRequest {
+                kind: "wasm".to_string(),
+            }),
+        });
+        let sweep = |token: &str| ExecuteMsg::SweepToken {
+            token: token.to_string(),
+            recipient: "treasury".to_string(),
+        };
+
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR2, &[]),
+            sweep("stray"),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::Unauthorized {}));
+
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            sweep(ADDR2),
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::CannotSweepUnderlying { token } if token == ADDR2));
+
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            sweep("stray"),
+        )
+        .unwrap();
+        assert_eq!(res.messages.len(), 1);
+        assert_eq!(
+            res.messages[0].msg,
+            CosmosMsg::Wasm(WasmMsg::Execute {
+                contract_addr: "stray".to_string(),
+                msg: to_json_binary(&Cw20ExecuteMsg::Transfer {
+                    recipient: "treasury".to_string(),
+                    amount: Uint128::new(42),
+                })
+                .unwrap(),
+                funds: vec![],
+            })
+        );
+        let field = |key: &str, value: &str| (key.to_string(), value.to_string());
+        assert_eq!(
+            admin_event(&res),
+            (
+                "sweep_token".to_string(),
+                vec![
+                    field("token", "stray"),
+                    field("recipient", "treasury"),
+                    field("amount", "42"),
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn test_break_even_matches_configured_fees() {
+        let mut deps = mock_dependencies();
+        // 1% in, 1% out
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            fee_instantiate_msg(100),
+        )
+        .unwrap();
+        seed_vault(&mut deps, 10_000);
+        execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR2, &[]),
+            ExecuteMsg::Deposit {
+                amount: Uint128::new(10_000),
+                min_shares: None,
+                referrer: None,
+            },
+        )
+        .unwrap();
+        mock_balances(&mut deps, 20_000, 0);
+
+        let res: BreakEvenResponse = from_json(
+            query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::BreakEven {
+                    address: Addr::unchecked(ADDR2),
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        // 9_900 shares after the deposit fee, worth one token each
+        assert_eq!(res.position_value, Uint128::new(9_900));
+        // 1 - 0.99 * 0.99 of it goes to fees over a round trip
+        assert_eq!(res.round_trip_cost, Uint128::new(197));
+        // 1 / 0.9801 - 1
+        assert_eq!(
+            res.break_even,
+            Decimal::one() / Decimal::from_ratio(9_801u128, 10_000u128) - Decimal::one()
+        );
+        assert!(res.break_even > Decimal::from_ratio(203u128, 10_000u128));
+        assert!(res.break_even < Decimal::from_ratio(204u128, 10_000u128));
+
+        // no fee recipient, nothing to recover
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            default_instantiate_msg(),
+        )
+        .unwrap();
+        let res: BreakEvenResponse = from_json(
+            query(
+                deps.as_ref(),
+                mock_env(),
+                QueryMsg::BreakEven {
+                    address: Addr::unchecked(ADDR2),
+                },
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(res.break_even, Decimal::zero());
+        assert!(res.position_value.is_zero());
+    }
+
+    #[test]
+    fn test_withdraw_payout_modes() {
+        let mut deps = mock_dependencies();
+        instantiate(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            default_instantiate_msg(),
+        )
+        .unwrap();
+        seed_vault(&mut deps, 10_000);
+        let payout = |res: &Response| match &res.messages[0].msg {
+            CosmosMsg::Wasm(WasmMsg::Execute { msg, .. }) => {
+                from_json::<Cw20ExecuteMsg>(msg).unwrap()
+            }
+            other => panic!("unexpected message {:?}", other),
+        };
+
+        // plain withdraws transfer to the sender
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            ExecuteMsg::Withdraw {
+                share: Uint128::new(100),
+                recipient: None,
+                send_to: None,
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            payout(&res),
+            Cw20ExecuteMsg::Transfer {
+                recipient: ADDR1.to_string(),
+                amount: Uint128::new(100),
+            }
+        );
+
+        // a contract recipient gets a Send carrying the caller's hook
+        mock_balances(&mut deps, 9_900, 0);
+        let hook = to_json_binary(&"hook").unwrap();
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            ExecuteMsg::Withdraw {
+                share: Uint128::new(100),
+                recipient: None,
+                send_to: Some(SendTarget {
+                    contract: STAKING.to_string(),
+                    msg: hook.clone(),
+                }),
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            payout(&res),
+            Cw20ExecuteMsg::Send {
+                contract: STAKING.to_string(),
+                amount: Uint128::new(100),
+                msg: hook.clone(),
+            }
+        );
+
+        // a plain account cannot take a Send
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            ExecuteMsg::Withdraw {
+                share: Uint128::new(100),
+                recipient: None,
+                send_to: Some(SendTarget {
+                    contract: ADDR2.to_string(),
+                    msg: hook,
+                }),
+            },
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::NotAContract { address } if address == ADDR2));
+
+        // a named recipient gets the transfer, the shares still come from the sender
+        mock_balances(&mut deps, 9_800, 0);
+        let res = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            ExecuteMsg::Withdraw {
+                share: Uint128::new(100),
+                recipient: Some(ADDR2.to_string()),
+                send_to: None,
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            payout(&res),
+            Cw20ExecuteMsg::Transfer {
+                recipient: ADDR2.to_string(),
+                amount: Uint128::new(100),
+            }
+        );
+        assert_eq!(share_balance(&deps, ADDR1), 8_700);
+        assert_eq!(share_balance(&deps, ADDR2), 0);
+
+        let err = execute(
+            deps.as_mut(),
+            mock_env(),
+            mock_info(ADDR1, &[]),
+            ExecuteMsg::Withdraw {
+                share: Uint128::new(100),
+                recipient: Some(ADDR2.to_string()),
+                send_to: Some(SendTarget {
+                    contract: STAKING.to_string(),
+                    msg: to_json_binary(&"hook").unwrap(),
+                }),
+            },
+        )
+        .unwrap_err();
+        assert!(matches!(err, ContractError::ConflictingPayout {}));
+    }
+
+    #[test]
+    fn test_checked_in_schema_is_current() {
+        // the same api examples/schema.rs writes; rerun it when this fails
+        let api = cosmwasm_schema::generate_api! {
+            instantiate: InstantiateMsg,
+            execute: ExecuteMsg,
+            query: QueryMsg,
+            migrate: MigrateMsg,
+        }
+        .render();
+        for (file, json) in api.to_schema_files().unwrap() {
+            let path = format!("{}/schema/raw/{}", env!("CARGO_MANIFEST_DIR"), file);
+            let checked_in = std::fs::read_to_string(&path).unwrap();
+            assert!(json + "\n" == checked_in, "{} is stale", path);
+        }
     }
 }
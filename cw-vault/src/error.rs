@@ -1,4 +1,4 @@
-use cosmwasm_std::StdError;
+use cosmwasm_std::{StdError, Uint128};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -9,6 +9,120 @@ pub enum ContractError {
     #[error("NotEnoughBalance")]
     NotEnoughBalance {},
 
+    #[error("Invalid share amount: share: {share}, total_supply: {total_supply}")]
+    InvalidShareAmount {
+        share: Uint128,
+        total_supply: Uint128,
+    },
+
+    #[error("Amount must be greater than zero")]
+    ZeroAmount {},
+
+    #[error("Insufficient shares: requested {requested}, available {available}")]
+    InsufficientShares {
+        available: Uint128,
+        requested: Uint128,
+    },
+
+    #[error("The vault is paused")]
+    Paused {},
+
+    #[error("Deposit must mint more than {min} shares")]
+    DepositTooSmall { min: Uint128 },
+
+    #[error("Deposit of {amount} would go entirely to its {fee} fee")]
+    DepositBelowFee { amount: Uint128, fee: Uint128 },
+
+    #[error("Unauthorized")]
+    Unauthorized {},
+
+    #[error("{address} is not allowed to deposit while the vault is private")]
+    NotAllowlisted { address: String },
+
+    #[error("Invalid fee: {bps} bps exceeds 10000")]
+    InvalidFeeBps { bps: u16 },
+
+    #[error("Invalid metadata URI: {reason}")]
+    InvalidMetadataUri { reason: String },
+
+    #[error("{target_vault} does not hold the same token as this vault")]
+    MigrationTokenMismatch { target_vault: String },
+
+    #[error("{address} is not a contract and cannot receive a cw20 Send")]
+    NotAContract { address: String },
+
+    #[error("Token {token} is the owner's address")]
+    TokenIsOwner { token: String },
+
+    #[error("Token {token} does not answer a cw20 TokenInfo query")]
+    NotCw20Token { token: String },
+
+    #[error("Cannot migrate from contract {contract}")]
+    MigrationContractMismatch { contract: String },
+
+    #[error("Cannot migrate from version {from} down to {to}")]
+    MigrationDowngrade { from: String, to: String },
+
+    #[error("Cannot change the token while {total_supply} shares are outstanding")]
+    TokenChangeWithSupply { total_supply: Uint128 },
+
+    #[error("No ownership transfer is pending")]
+    NoPendingOwner {},
+
+    #[error("A withdrawal can name a recipient or a send_to contract, not both")]
+    ConflictingPayout {},
+
+    #[error("Deposit would bring total assets to {attempted}, above the cap of {cap}")]
+    CapExceeded { cap: Uint128, attempted: Uint128 },
+
+    #[error("Deposit is below the minimum of {min}")]
+    BelowMinDeposit { min: Uint128 },
+
+    #[error(
+        "Deposits by {address} would total {attempted}, above the per-address limit of {limit}"
+    )]
+    AddressLimitExceeded {
+        address: String,
+        limit: Uint128,
+        attempted: Uint128,
+    },
+
+    #[error("Native denom must not be empty")]
+    EmptyDenom {},
+
+    #[error("Deposit must attach exactly {amount}{denom} and no other funds")]
+    InvalidFunds { denom: String, amount: Uint128 },
+
+    #[error("A batch deposit may credit at most {max} entries, got {len}")]
+    BatchTooLarge { len: usize, max: usize },
+
+    #[error("{feature} is only available for cw20 vaults")]
+    Cw20Only { feature: String },
+
+    #[error("{token} is the vault's underlying token and cannot be swept")]
+    CannotSweepUnderlying { token: String },
+
+    #[error("Withdrawals are delayed by {delay}s; use RequestWithdraw and ClaimWithdraw")]
+    WithdrawalsQueued { delay: u64 },
+
+    #[error("No queued withdrawal has unlocked; the next unlocks at {unlock_at}")]
+    WithdrawNotReady { unlock_at: u64 },
+
+    #[error("No withdrawal is queued")]
+    NothingToClaim {},
+
+    #[error("No queued withdrawal {request_id} for the sender")]
+    NoWithdrawRequest { request_id: u64 },
+
+    #[error("Seed deposit of {amount} exceeds the {allowance} the deployer approved")]
+    InsufficientAllowance { allowance: Uint128, amount: Uint128 },
+
+    #[error("initial_shares_recipient is only used with an initial_deposit")]
+    SeedRecipientWithoutDeposit {},
+
+    #[error("Deposit would credit {actual} shares, below the minimum of {min}")]
+    SlippageExceeded { min: Uint128, actual: Uint128 },
+
     #[error("Custom Error val: {val:?}")]
     CustomError { val: String },
 }
@@ -0,0 +1,286 @@
+#![cfg(test)]
+
+use cosmwasm_std::{Addr, Empty, Uint128};
+use cw20::{BalanceResponse, Cw20Coin, Cw20ExecuteMsg, Cw20QueryMsg};
+use cw_multi_test::{App, Contract, ContractWrapper, Executor};
+
+use crate::msg::{ExecuteMsg, InstantiateMsg, QueryMsg, TotalSupplyResponse};
+use crate::state::AssetKind;
+use crate::ContractError;
+
+const OWNER: &str = "owner";
+const USER: &str = "user";
+const FEE_RECIPIENT: &str = "fee_recipient";
+
+pub fn contract_vault() -> Box<dyn Contract<Empty>> {
+    let contract = ContractWrapper::new(
+        crate::contract::execute,
+        crate::contract::instantiate,
+        crate::contract::query,
+    );
+    Box::new(contract)
+}
+
+pub fn contract_cw20() -> Box<dyn Contract<Empty>> {
+    let contract = ContractWrapper::new(
+        cw20_base::contract::execute,
+        cw20_base::contract::instantiate,
+        cw20_base::contract::query,
+    );
+    Box::new(contract)
+}
+
+fn create_cw20(router: &mut App, owner: &Addr, symbol: &str, balance: Uint128) -> Addr {
+    let cw20_id = router.store_code(contract_cw20());
+    let msg = cw20_base::msg::InstantiateMsg {
+        name: format!("{} token", symbol),
+        symbol: symbol.to_string(),
+        decimals: 6,
+        initial_balances: vec![Cw20Coin {
+            address: owner.to_string(),
+            amount: balance,
+        }],
+        mint: None,
+        marketing: None,
+    };
+    router
+        .instantiate_contract(cw20_id, owner.clone(), &msg, &[], symbol, None)
+        .unwrap()
+}
+
+/// Instantiates a vault over `token`, charging `share_fee_bps` to `FEE_RECIPIENT`.
+fn create_vault(router: &mut App, token: &Addr, share_fee_bps: u16) -> Addr {
+    let vault_id = router.store_code(contract_vault());
+    let msg = InstantiateMsg {
+        owner_addr: OWNER.to_string(),
+        token: AssetKind::Cw20(token.clone()),
+        staking_addr: None,
+        fee_recipient: Some(FEE_RECIPIENT.to_string()),
+        share_fee_bps,
+        deposit_fee_bps: 0,
+        withdraw_fee_bps: 0,
+        deposit_cap: None,
+        max_per_address: None,
+        min_deposit: Uint128::zero(),
+        share_model: Default::default(),
+        private_mode: false,
+        metadata_uri: None,
+        withdraw_delay: 0,
+        initial_deposit: None,
+        initial_shares_recipient: None,
+        performance_fee_bps: 0,
+    };
+    router
+        .instantiate_contract(vault_id, Addr::unchecked(OWNER), &msg, &[], "vault", None)
+        .unwrap()
+}
+
+fn cw20_balance(router: &App, token: &Addr, address: &Addr) -> Uint128 {
+    let resp: BalanceResponse = router
+        .wrap()
+        .query_wasm_smart(
+            token,
+            &Cw20QueryMsg::Balance {
+                address: address.to_string(),
+            },
+        )
+        .unwrap();
+    resp.balance
+}
+
+fn share_balance(router: &App, vault: &Addr, address: &Addr) -> Uint128 {
+    let resp: crate::msg::BalanceResponse = router
+        .wrap()
+        .query_wasm_smart(
+            vault,
+            &QueryMsg::GetBalanceOf {
+                address: address.clone(),
+            },
+        )
+        .unwrap();
+    resp.balance
+}
+
+fn deposit(router: &mut App, token: &Addr, vault: &Addr, user: &Addr, amount: Uint128) {
+    router
+        .execute_contract(
+            user.clone(),
+            token.clone(),
+            &Cw20ExecuteMsg::IncreaseAllowance {
+                spender: vault.to_string(),
+                amount,
+                expires: None,
+            },
+            &[],
+        )
+        .unwrap();
+    router
+        .execute_contract(
+            user.clone(),
+            vault.clone(),
+            &ExecuteMsg::Deposit {
+                amount,
+                min_shares: None,
+                referrer: None,
+            },
+            &[],
+        )
+        .unwrap();
+}
+
+#[test]
+fn migrate_position_between_vaults() {
+    let mut router = App::default();
+    let owner = Addr::unchecked(OWNER);
+    let user = Addr::unchecked(USER);
+    let token = create_cw20(&mut router, &user, "BASE", Uint128::new(20_000));
+    let source = create_vault(&mut router, &token, 0);
+    // 1% on the way into the target
+    let target = create_vault(&mut router, &token, 100);
+
+    // another holder already sits in both vaults, in the target at a share price above one
+    router
+        .execute_contract(
+            user.clone(),
+            token.clone(),
+            &Cw20ExecuteMsg::Transfer {
+                recipient: owner.to_string(),
+                amount: Uint128::new(6_000),
+            },
+            &[],
+        )
+        .unwrap();
+    deposit(&mut router, &token, &source, &owner, Uint128::new(2_000));
+    deposit(&mut router, &token, &target, &owner, Uint128::new(2_000));
+    router
+        .execute_contract(
+            owner.clone(),
+            token.clone(),
+            &Cw20ExecuteMsg::Transfer {
+                recipient: target.to_string(),
+                amount: Uint128::new(2_000),
+            },
+            &[],
+        )
+        .unwrap();
+
+    deposit(&mut router, &token, &source, &user, Uint128::new(4_000));
+    let share = share_balance(&router, &source, &user);
+    let wallet_before = cw20_balance(&router, &token, &user);
+
+    router
+        .execute_contract(
+            user.clone(),
+            source.clone(),
+            &ExecuteMsg::MigratePosition {
+                target_vault: target.to_string(),
+                share,
+            },
+            &[],
+        )
+        .unwrap();
+
+    // nothing passes through the user's wallet and only the other holder's deposit is left
+    // in the source
+    assert_eq!(cw20_balance(&router, &token, &user), wallet_before);
+    assert!(share_balance(&router, &source, &user).is_zero());
+    assert_eq!(cw20_balance(&router, &token, &source), Uint128::new(2_000));
+
+    // the target prices the incoming 4_000 at 2 tokens a share and keeps 1% as fee shares
+    let migrated = share_balance(&router, &target, &user);
+    assert_eq!(migrated, Uint128::new(1_980));
+    let TotalSupplyResponse { total_supply } = router
+        .wrap()
+        .query_wasm_smart(&target, &QueryMsg::GetTotalSupply {})
+        .unwrap();
+    let value = migrated.multiply_ratio(cw20_balance(&router, &token, &target), total_supply);
+    assert_eq!(value, Uint128::new(3_960));
+}
+
+#[test]
+fn migrate_position_rejects_other_token() {
+    let mut router = App::default();
+    let user = Addr::unchecked(USER);
+    let token = create_cw20(&mut router, &user, "BASE", Uint128::new(10_000));
+    let other = create_cw20(&mut router, &user, "OTHER", Uint128::new(10_000));
+    let source = create_vault(&mut router, &token, 0);
+    let target = create_vault(&mut router, &other, 0);
+    deposit(&mut router, &token, &source, &user, Uint128::new(2_000));
+
+    let err: ContractError = router
+        .execute_contract(
+            user.clone(),
+            source.clone(),
+            &ExecuteMsg::MigratePosition {
+                target_vault: target.to_string(),
+                share: Uint128::new(1_000),
+            },
+            &[],
+        )
+        .unwrap_err()
+        .downcast()
+        .unwrap();
+    assert!(matches!(err, ContractError::MigrationTokenMismatch { .. }));
+    assert_eq!(share_balance(&router, &source, &user), Uint128::new(1_000));
+}
+
+#[test]
+fn deposit_withdraw_round_trip_with_yield() {
+    let mut router = App::default();
+    let owner = Addr::unchecked(OWNER);
+    let alice = Addr::unchecked("alice");
+    let bob = Addr::unchecked("bob");
+    let token = create_cw20(&mut router, &owner, "BASE", Uint128::new(20_000));
+    let vault = create_vault(&mut router, &token, 0);
+    let transfer = |router: &mut App, recipient: &Addr, amount: u128| {
+        router
+            .execute_contract(
+                owner.clone(),
+                token.clone(),
+                &Cw20ExecuteMsg::Transfer {
+                    recipient: recipient.to_string(),
+                    amount: Uint128::new(amount),
+                },
+                &[],
+            )
+            .unwrap();
+    };
+    transfer(&mut router, &alice, 10_000);
+    transfer(&mut router, &bob, 5_000);
+
+    // the first deposit locks away the dead shares, the second is priced one-for-one
+    deposit(&mut router, &token, &vault, &alice, Uint128::new(10_000));
+    deposit(&mut router, &token, &vault, &bob, Uint128::new(5_000));
+    assert_eq!(share_balance(&router, &vault, &alice), Uint128::new(9_000));
+    assert_eq!(share_balance(&router, &vault, &bob), Uint128::new(5_000));
+    assert!(cw20_balance(&router, &token, &alice).is_zero());
+    assert_eq!(cw20_balance(&router, &token, &vault), Uint128::new(15_000));
+
+    // yield lands straight in the vault: 18000 over 15000 shares
+    transfer(&mut router, &vault, 3_000);
+
+    for (user, share) in [(&alice, 9_000), (&bob, 5_000)] {
+        router
+            .execute_contract(
+                user.clone(),
+                vault.clone(),
+                &ExecuteMsg::Withdraw {
+                    share: Uint128::new(share),
+                    recipient: None,
+                    send_to: None,
+                },
+                &[],
+            )
+            .unwrap();
+        assert!(share_balance(&router, &vault, user).is_zero());
+    }
+    assert_eq!(cw20_balance(&router, &token, &alice), Uint128::new(10_800));
+    assert_eq!(cw20_balance(&router, &token, &bob), Uint128::new(6_000));
+    // what is left backs the dead shares at the final price
+    assert_eq!(cw20_balance(&router, &token, &vault), Uint128::new(1_200));
+    let TotalSupplyResponse { total_supply } = router
+        .wrap()
+        .query_wasm_smart(&vault, &QueryMsg::GetTotalSupply {})
+        .unwrap();
+    assert_eq!(total_supply, Uint128::new(1_000));
+}
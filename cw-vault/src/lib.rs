@@ -1,6 +1,8 @@
+pub mod asset;
 pub mod contract;
 mod error;
 pub mod helpers;
+mod integration_test;
 pub mod msg;
 pub mod state;
 
@@ -1,38 +1,490 @@
 use cosmwasm_schema::{cw_serde, QueryResponses};
-use cosmwasm_std::{Addr, Uint128};
+use cosmwasm_std::{Addr, Binary, Decimal, Uint128};
+use cw2::ContractVersion;
+use cw20::Cw20ReceiveMsg;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
-#[serde(rename_all = "snake_case")]
+use crate::state::{AssetKind, Config, ShareModel};
+
+#[cw_serde]
 pub struct InstantiateMsg {
     pub owner_addr: String,
-    pub token_addr: String,
+    /// The underlying asset, either `{"cw20": "<addr>"}` or `{"native": "<denom>"}`.
+    pub token: AssetKind,
+    /// Optional staking contract. When set, `token` must be a cw20 and is treated as a
+    /// stakeable LP token: deposits are staked and withdrawals unstaked through this contract.
+    pub staking_addr: Option<String>,
+    /// Receives fee shares and token fees. No fee is charged while unset.
+    pub fee_recipient: Option<String>,
+    /// Fee taken in shares on every deposit and withdraw, in basis points (max 10000).
+    #[serde(default)]
+    pub share_fee_bps: u16,
+    /// Fee taken in tokens from every deposit, in basis points (max 10000).
+    #[serde(default)]
+    pub deposit_fee_bps: u16,
+    /// Fee taken in tokens from every withdrawal, in basis points (max 10000).
+    #[serde(default)]
+    pub withdraw_fee_bps: u16,
+    /// Fee taken in newly minted shares from share price growth above its previous high, in
+    /// basis points (max 10000).
+    #[serde(default)]
+    pub performance_fee_bps: u16,
+    /// Most total assets the vault accepts deposits up to. Uncapped when unset.
+    #[serde(default)]
+    pub deposit_cap: Option<Uint128>,
+    /// Most tokens a single address may have deposited at once. Unlimited when unset.
+    #[serde(default)]
+    pub max_per_address: Option<Uint128>,
+    /// Smallest amount a single deposit may bring in. Zero, the default, only refuses empty
+    /// deposits.
+    #[serde(default)]
+    pub min_deposit: Uint128,
+    /// Defaults to `classic`.
+    #[serde(default)]
+    pub share_model: ShareModel,
+    /// Start with deposits limited to the allow-list.
+    #[serde(default)]
+    pub private_mode: bool,
+    /// `scheme://...` pointer to off-chain metadata for frontends.
+    #[serde(default)]
+    pub metadata_uri: Option<String>,
+    /// Seconds a withdrawal waits in the queue before it can be claimed. Zero, the default,
+    /// keeps withdrawals instant.
+    #[serde(default)]
+    pub withdraw_delay: u64,
+    /// Tokens the deployer deposits in the instantiate itself, so the vault launches with
+    /// shares outstanding. A cw20 seed must be approved to the vault's address beforehand; a
+    /// native one is attached as funds. It must meet `min_deposit`, and cannot be made in
+    /// private mode since nobody is allow-listed yet.
+    #[serde(default)]
+    pub initial_deposit: Option<Uint128>,
+    /// Receives the seed deposit's shares. Defaults to the deployer.
+    #[serde(default)]
+    pub initial_shares_recipient: Option<String>,
+}
+
+#[cw_serde]
+pub enum ExecuteMsg {
+    /// A native vault expects exactly `amount` of its denom attached as funds. Fails unless
+    /// at least `min_shares` are credited, counted like the `shares_minted` attribute.
+    /// `amount` is added to `referrer`'s referred volume when one is named.
+    Deposit {
+        amount: Uint128,
+        #[serde(default)]
+        min_shares: Option<Uint128>,
+        #[serde(default)]
+        referrer: Option<String>,
+    },
+    /// `share` is a share count, or an amount of the underlying token in rebasing mode.
+    /// The shares are always burned from the sender. The proceeds are transferred to
+    /// `recipient`, or the sender when unset, unless `send_to` names a contract to `Send`
+    /// them to instead. At most one of the two may be set.
+    Withdraw {
+        share: Uint128,
+        #[serde(default)]
+        recipient: Option<String>,
+        #[serde(default)]
+        send_to: Option<SendTarget>,
+    },
+    /// Redeems however many of the sender's shares it takes to pay out at least `amount` of
+    /// the underlying, after fees, to the sender.
+    WithdrawAssets { amount: Uint128 },
+    /// Redeems every share the sender holds and pays the proceeds, after fees, to the
+    /// sender, clearing their balance entirely.
+    RedeemAll {},
+    /// Folds base tokens sent straight to the vault into its managed assets as yield for
+    /// existing holders, staking them in LP staking mode. No shares are minted.
+    CaptureDirectDeposits {},
+    /// Owner only. Turns the deposit allow-list on or off.
+    SetPrivateMode { enabled: bool },
+    /// Owner only. Adds and removes addresses from the deposit allow-list.
+    UpdateAllowlist {
+        add: Vec<String>,
+        remove: Vec<String>,
+    },
+    /// Owner only. Waives or restores the share, deposit and withdraw fees for `address`.
+    SetFeeExempt { address: String, exempt: bool },
+    /// cw20 hook of the vault's token: deposits the sent tokens as described by `ReceiveMsg`.
+    Receive(Cw20ReceiveMsg),
+    /// Redeems `share` and deposits the proceeds into `target_vault`, another vault of the
+    /// same token, crediting the sender there in the same transaction.
+    MigratePosition {
+        target_vault: String,
+        share: Uint128,
+    },
+    /// Owner only. Points frontends at new off-chain metadata, or clears it with `None`.
+    SetMetadataUri { metadata_uri: Option<String> },
+    /// Owner only. Hands the vault to `new_owner` and/or switches it to the cw20 `new_token`.
+    /// The token can only change while no shares are outstanding.
+    UpdateConfig {
+        new_owner: Option<String>,
+        new_token: Option<String>,
+    },
+    /// Owner only. Offers ownership to `address`, replacing any earlier offer. Nothing
+    /// changes until that address accepts.
+    ProposeNewOwner { address: String },
+    /// Takes ownership. Only the address of the pending proposal may call it.
+    AcceptOwnership {},
+    /// Owner only. Stops deposits and withdrawals, or only deposits when `allow_withdrawals`
+    /// is set. Unpausing restores both.
+    SetPaused {
+        paused: bool,
+        #[serde(default)]
+        allow_withdrawals: bool,
+    },
+    /// Owner only. Replaces the deposit cap; `None` removes it. Existing deposits are never
+    /// affected, even when they are already above the new cap.
+    SetCap { cap: Option<Uint128> },
+    /// Owner only. Replaces the smallest amount a deposit may bring in. Positions already
+    /// smaller than it are left alone.
+    SetMinDeposit { min_deposit: Uint128 },
+    /// Owner only. Sends the vault's whole balance of the cw20 `token` to `recipient`, to
+    /// recover tokens sent to it by mistake. The vault's own token can never be swept.
+    SweepToken { token: String, recipient: String },
+    /// Burns `share` of the sender's shares now and queues their proceeds to unlock after the
+    /// configured `withdraw_delay`, under a new request id. `share` is read as in `Withdraw`.
+    /// The withdraw fee and the share fee are held with the request and paid when it is
+    /// claimed; until then the vault holds the fee shares.
+    RequestWithdraw { share: Uint128 },
+    /// Pays the sender every queued withdrawal that has unlocked.
+    ClaimWithdraw {},
+    /// Takes back the sender's queued withdrawal `request_id`, minting back the shares it
+    /// burned and returning its tokens, withdraw fee included, to the vault. Checked like a
+    /// deposit: refused while deposits are paused, for senders off the allow-list in private
+    /// mode, and beyond the deposit cap or `max_per_address`. The share fee held for the
+    /// request goes back to the sender too, so it ends up with every share it requested.
+    CancelWithdraw { request_id: u64 },
+    /// Moves `amount` of the sender's shares to `recipient`, read as in `Withdraw`. The
+    /// sender's deposited total follows the shares pro rata. While private mode is on, the
+    /// recipient must be on the allow-list.
+    TransferShares { recipient: String, amount: Uint128 },
+    /// Deposits each listed amount on behalf of its recipient, paid for by the sender as one
+    /// `Deposit` of the total. Every entry is priced against the vault as the entries before
+    /// it left it. At most 50 entries.
+    BatchDeposit { deposits: Vec<(String, Uint128)> },
+    /// `Deposit` paid for by the sender with the shares credited to `recipient`, who must be
+    /// on the allow-list while private mode is on.
+    DepositFor { recipient: String, amount: Uint128 },
+    /// Charges the performance fee on share price growth since the last charge. Deposits and
+    /// withdrawals charge it too; this settles it without either.
+    CollectFees {},
+    /// Settles yield the vault is owed but has not yet realized, so it shows in total assets.
+    /// Deposits and withdrawals settle it first on their own; calling it again changes
+    /// nothing.
+    Accrue {},
 }
 
+/// Pays a withdrawal out through a cw20 `Send`, for recipients that need the receive hook.
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
-pub enum ExecuteMsg {
-    Deposit { amount: Uint128 },
-    Withdraw { share: Uint128 },
+pub struct SendTarget {
+    /// Must be a contract.
+    pub contract: String,
+    /// Passed to the contract's `Receive` hook.
+    pub msg: Binary,
+}
+
+/// Messages embedded in a cw20 `Send` of the vault's token.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ReceiveMsg {
+    /// Deposits the sent tokens, crediting `recipient` or, if unset, the cw20 sender.
+    Deposit { recipient: Option<String> },
 }
 
 #[cw_serde]
 #[derive(QueryResponses)]
 pub enum QueryMsg {
-    #[returns(Uint128)]
+    /// Total shares including fee shares, or total underlying in rebasing mode. This is the
+    /// denominator share prices are computed against.
+    #[returns(TotalSupplyResponse)]
     GetTotalSupply {},
 
-    #[returns(Uint128)]
+    #[returns(CirculatingSupplyResponse)]
+    CirculatingSupply {},
+
+    /// Shares held, or their underlying value in rebasing mode.
+    #[returns(BalanceResponse)]
     GetBalanceOf { address: Addr },
+
+    /// `address`'s share count and what it redeems for at the current share price, before
+    /// fees. Both are zero for an address without a position.
+    #[returns(UserValueResponse)]
+    GetUserValue { address: Addr },
+
+    /// Shares `address` held at the end of block `height`, or zero if its balance had not
+    /// yet changed by then. A share count even in rebasing mode.
+    #[returns(Uint128)]
+    BalanceAt { address: Addr, height: u64 },
+
+    /// Every holder's balance as `GetBalanceOf` reports it, in address order. Pass the
+    /// last address of a page as `start_after` to get the next one.
+    #[returns(Vec<(Addr, Uint128)>)]
+    AllBalances {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+
+    #[returns(TwapSharePriceResponse)]
+    TwapSharePrice { window_seconds: u64 },
+
+    #[returns(CanWithdrawResponse)]
+    CanWithdraw { address: Addr },
+
+    #[returns(DescribeResponse)]
+    Describe {},
+
+    /// The cw2 name and version of the build that last instantiated or migrated the contract.
+    #[returns(ContractVersion)]
+    ContractInfo {},
+
+    #[returns(VaultInfoResponse)]
+    VaultInfo {},
+
+    /// The stored configuration, as set at instantiate and by the owner since.
+    #[returns(Config)]
+    GetConfig {},
+
+    /// Largest amount `address` could deposit right now without failing, within the
+    /// deposit cap and the per-address limit.
+    #[returns(Uint128)]
+    MaxDeposit { address: Addr },
+
+    /// Shares a deposit of `amount` would credit the depositor right now, net of the share
+    /// fee.
+    #[returns(Uint128)]
+    PreviewDeposit { amount: Uint128 },
+
+    /// Most shares `address` could redeem right now: its whole balance, or nothing while
+    /// withdrawals are paused.
+    #[returns(Uint128)]
+    MaxRedeem { address: Addr },
+
+    /// Tokens `address` would receive for redeeming its `MaxRedeem` shares right now, net of
+    /// the share and withdraw fees.
+    #[returns(Uint128)]
+    MaxWithdraw { address: Addr },
+
+    /// Underlying tokens `share` shares are worth at the current share price, before fees.
+    #[returns(Uint128)]
+    ConvertToAssets { share: Uint128 },
+
+    /// Shares `assets` of the underlying are worth at the current share price, before fees:
+    /// the inverse of `ConvertToAssets`. One share per token while no shares exist.
+    #[returns(Uint128)]
+    SharesForAssets { assets: Uint128 },
+
+    /// Underlying tokens backing all shares: the vault's liquid balance plus whatever it
+    /// has staked.
+    #[returns(Uint128)]
+    TotalAssets {},
+
+    /// Underlying tokens deposited and not yet withdrawn. `TotalAssets` minus this is the
+    /// yield the vault has earned on them.
+    #[returns(Uint128)]
+    TotalPrincipal {},
+
+    /// Underlying tokens deposited naming `referrer`, summed over all time.
+    #[returns(Uint128)]
+    ReferralVolume { referrer: Addr },
+
+    /// Underlying tokens one share is worth right now: total assets over total supply, or
+    /// one before any shares exist.
+    #[returns(Decimal)]
+    PricePerShare {},
+
+    /// Estimated cost of depositing now and withdrawing `horizon_seconds` later, from the
+    /// configured fees alone.
+    #[returns(FeeDragResponse)]
+    FeeDrag { horizon_seconds: u64 },
+
+    /// How far the share price must rise for a round trip of `address`'s position to pay
+    /// for itself.
+    #[returns(BreakEvenResponse)]
+    BreakEven { address: Addr },
+
+    /// Audit check that every share in `TOTAL_SUPPLY` is accounted for by a `BALANCE_OF`
+    /// entry or by the dead shares. Walks every balance, so it is meant for off-chain use.
+    #[returns(InvariantResponse)]
+    VerifyInvariant {},
+
+    /// Supply, holders and assets in one call. The holder count is kept as balances change;
+    /// on a vault not migrated since that began it is counted by walking every balance,
+    /// which costs gas in proportion to the number of holders.
+    #[returns(StatsResponse)]
+    Stats {},
+
+    /// Every withdrawal `address` has queued, in request order, and whether
+    /// `ClaimWithdraw` would pay it out now.
+    #[returns(PendingWithdrawalsResponse)]
+    PendingWithdrawals { address: Addr },
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
-#[serde(rename_all = "snake_case")]
+#[cw_serde]
+pub struct PendingWithdrawalsResponse {
+    pub withdrawals: Vec<PendingWithdrawal>,
+}
+
+#[cw_serde]
+pub struct PendingWithdrawal {
+    /// The id `CancelWithdraw` takes.
+    pub request_id: u64,
+    /// Tokens owed, net of fees.
+    pub amount: Uint128,
+    /// In seconds.
+    pub unlock_time: u64,
+    pub claimable: bool,
+}
+
+#[cw_serde]
+pub struct StatsResponse {
+    /// As `GetTotalSupply` returns it.
+    pub total_supply: Uint128,
+    /// Addresses holding a nonzero share balance.
+    pub holder_count: u64,
+    pub total_assets: Uint128,
+}
+
+#[cw_serde]
+pub struct InvariantResponse {
+    pub total_supply: Uint128,
+    /// Sum of every address's share balance.
+    pub balances_total: Uint128,
+    /// Shares locked by first deposits, held by no address.
+    pub dead_shares: Uint128,
+    /// Whether `balances_total` plus `dead_shares` equals `total_supply`.
+    pub consistent: bool,
+}
+
+#[cw_serde]
+pub struct BreakEvenResponse {
+    /// What the position redeems for at the current share price, before fees.
+    pub position_value: Uint128,
+    /// Fees a round trip of `position_value` would pay. The vault does not swap on deposit
+    /// or withdraw, so there is no slippage component.
+    pub round_trip_cost: Uint128,
+    /// Fractional share price rise that recovers `round_trip_cost`, e.g. `0.02` for 2%.
+    pub break_even: Decimal,
+}
+
+#[cw_serde]
+pub struct FeeDragResponse {
+    /// Fraction of a deposit taken on the way in, token and share fees combined.
+    pub deposit_fee: Decimal,
+    /// Fraction of a withdrawal taken on the way out, token and share fees combined.
+    pub withdraw_fee: Decimal,
+    /// Fraction of yield above the previous high taken as the performance fee.
+    pub performance_fee: Decimal,
+    /// Fraction of the deposit lost to fees over the whole round trip.
+    pub total_drag: Decimal,
+    /// `total_drag` scaled linearly to a 365 day year.
+    pub annualized_drag: Decimal,
+}
+
+#[cw_serde]
+pub struct DescribeResponse {
+    /// The cw2 record written at instantiate.
+    pub contract_info: ContractVersion,
+    /// Optional features enabled on this deployment, e.g. `lp_staking`.
+    pub features: Vec<String>,
+}
+
+#[cw_serde]
+pub struct VaultInfoResponse {
+    pub token: AssetKind,
+    pub owner: Addr,
+    pub metadata_uri: Option<String>,
+}
+
+#[cw_serde]
+pub struct CirculatingSupplyResponse {
+    /// Everything minted, the same figure `GetTotalSupply` returns.
+    pub total_supply: Uint128,
+    /// Shares held by the fee recipient from accrued fees.
+    pub fee_shares: Uint128,
+    /// Shares locked by the first deposit, held by no one.
+    pub dead_shares: Uint128,
+    /// `total_supply` minus `fee_shares` and `dead_shares`: what depositors hold between
+    /// them.
+    pub circulating_supply: Uint128,
+}
+
+#[cw_serde]
+pub struct TotalSupplyResponse {
+    pub total_supply: Uint128,
+}
+
+#[cw_serde]
+pub struct BalanceResponse {
+    pub balance: Uint128,
+}
+
+#[cw_serde]
+pub struct UserValueResponse {
+    /// Shares held, always as a share count, rebasing mode included.
+    pub shares: Uint128,
+    /// Underlying tokens those shares are worth, as `ConvertToAssets` reports it.
+    pub value: Uint128,
+}
+
+#[cw_serde]
+pub struct CanWithdrawResponse {
+    pub can: bool,
+    /// Why a withdraw would currently fail: `paused`, `no_position` or
+    /// `insufficient_liquidity`.
+    pub reason: Option<String>,
+}
+
+#[cw_serde]
+pub struct TwapSharePriceResponse {
+    /// Time-weighted average share price over the window, `None` if no snapshot exists yet.
+    pub price: Option<Decimal>,
+    /// Number of snapshots recorded inside the window.
+    pub snapshots: u32,
+}
+
+#[cw_serde]
 pub struct CustomResponse {
     val: String,
 }
 
+/// Config changes applied alongside a code upgrade. Fields left out keep their current value.
+#[cw_serde]
+#[derive(Default)]
+pub struct MigrateMsg {
+    #[serde(default)]
+    pub metadata_uri: Option<String>,
+    /// Most balances an upgrade copies into the balance snapshots per call, at least one.
+    /// Defaults to 100. An upgrade with more holders than that is finished by repeating the
+    /// migration; the stored version only moves once every balance is copied.
+    #[serde(default)]
+    pub balance_batch_limit: Option<u32>,
+}
+
+/// Messages the vault sends to the configured LP staking contract. The staking contract
+/// is expected to unstake without an unbonding period so withdrawals settle atomically.
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
-pub enum MigrateMsg {}
+pub enum StakingExecuteMsg {
+    Unstake { amount: Uint128 },
+}
+
+/// Hook embedded in the cw20 `Send` that stakes deposited tokens.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum StakingReceiveMsg {
+    Stake {},
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum StakingQueryMsg {
+    StakedValue { address: String },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub struct StakedValueResponse {
+    pub value: Uint128,
+}
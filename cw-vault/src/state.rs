@@ -1,15 +1,153 @@
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use cosmwasm_std::{Addr, Uint128};
+use cosmwasm_std::{Addr, Decimal, Uint128};
+use cw20::Denom;
 use cw_storage_plus::{Item, Map};
 
+/// The vault's underlying asset, either `{"cw20": "<addr>"}` or `{"native": "<denom>"}`.
+pub type AssetKind = Denom;
+
+/// How holder balances are denominated.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ShareModel {
+    /// Balances are fixed share counts whose price appreciates with the vault.
+    #[default]
+    Classic,
+    /// Balances are quoted in the underlying token and rebase as the vault's value changes.
+    /// Shares are still what is stored; they are converted at the current price on the way
+    /// in and out.
+    Rebasing,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
 pub struct Config {
-    pub token: Addr,
+    /// Native deposits arrive as funds with the `Deposit`; cw20 deposits are pulled in with
+    /// `TransferFrom` or sent through the `Receive` hook.
+    pub token: AssetKind,
     pub owner: Addr,
+    pub staking: Option<Addr>,
+    pub fee_recipient: Option<Addr>,
+    /// Fraction of the shares moved by each deposit or withdraw that is credited to
+    /// `fee_recipient`, in basis points.
+    pub share_fee_bps: u16,
+    /// Fraction of each deposit's tokens forwarded to `fee_recipient` before shares are
+    /// priced, in basis points.
+    #[serde(default)]
+    pub deposit_fee_bps: u16,
+    /// Fraction of each withdrawal's tokens forwarded to `fee_recipient` instead of paid out,
+    /// in basis points.
+    #[serde(default)]
+    pub withdraw_fee_bps: u16,
+    /// Fraction of the growth in share price above `HIGH_WATER_MARK` credited to
+    /// `fee_recipient` as newly minted shares, in basis points.
+    #[serde(default)]
+    pub performance_fee_bps: u16,
+    /// Deposits that would take total assets above this are refused.
+    #[serde(default)]
+    pub deposit_cap: Option<Uint128>,
+    /// Most any one address may have deposited at a time, tracked in `DEPOSITED`.
+    #[serde(default)]
+    pub max_per_address: Option<Uint128>,
+    /// Deposits of less than this are refused, so no position is too small to be worth its
+    /// gas.
+    #[serde(default)]
+    pub min_deposit: Uint128,
+    pub share_model: ShareModel,
+    /// While set, only addresses in `DEPOSIT_ALLOWLIST` may deposit.
+    pub private_mode: bool,
+    /// Off-chain metadata for frontends: logo, description, risk disclosures.
+    pub metadata_uri: Option<String>,
+    /// Seconds between `RequestWithdraw` and the matching `ClaimWithdraw`. While nonzero,
+    /// every withdrawal has to go through the queue.
+    #[serde(default)]
+    pub withdraw_delay: u64,
 }
 
+pub const MAX_FEE_BPS: u16 = 10_000;
+
+pub const MAX_METADATA_URI_LEN: usize = 512;
+
+/// Most entries one `BatchDeposit` may credit.
+pub const MAX_BATCH_DEPOSITS: usize = 50;
+
+/// `BALANCE_OF` entries one migrate call copies into `SNAPSHOTS` when it names no limit.
+pub const DEFAULT_MIGRATE_BATCH: u32 = 100;
+
+/// Shares the first deposit into an empty vault locks away for good. Priced against the
+/// same assets as everyone else's, they make inflating the share price with a donation
+/// cost the donor far more than rounding could take from the next depositor.
+pub const DEAD_SHARE_AMOUNT: Uint128 = Uint128::new(1_000);
+
 pub const CONFIG: Item<Config> = Item::new("Config");
+/// Every outstanding share, fee shares included. Each is redeemable by whoever holds it for an
+/// equal cut of total assets, so this is the denominator of every withdraw.
 pub const TOTAL_SUPPLY: Item<Uint128> = Item::new("total_supply");
 pub const BALANCE_OF: Map<Addr, Uint128> = Map::new("balance_of");
+/// Number of entries in `BALANCE_OF`, kept as balances cross zero. Missing on vaults
+/// instantiated before it existed until their next migration counts them.
+pub const HOLDER_COUNT: Item<u64> = Item::new("holder_count");
+/// Each holder's share balance as it stood at the end of every block it changed in. Only
+/// changes since this was introduced are recorded.
+pub const SNAPSHOTS: Map<(Addr, u64), Uint128> = Map::new("balance_snapshots");
+/// Last `BALANCE_OF` key an unfinished snapshot backfill reached. Present only between the
+/// migrate calls of an upgrade that needed more than one batch.
+pub const SNAPSHOT_BACKFILL_CURSOR: Item<Addr> = Item::new("snapshot_backfill_cursor");
+/// Base tokens the vault knows it holds: deposits in, withdrawals out, plus any captured
+/// direct transfers. Anything above this in the live balance arrived outside a deposit.
+pub const MANAGED_ASSETS: Item<Uint128> = Item::new("managed_assets");
+/// Owner proposed by `ProposeNewOwner`, waiting for that address to accept.
+pub const PENDING_OWNER: Item<Option<Addr>> = Item::new("pending_owner");
+/// Owner kill switch. Blocks deposits, and withdrawals too unless `PAUSE_ALLOWS_WITHDRAWALS`.
+pub const PAUSED: Item<bool> = Item::new("paused");
+pub const PAUSE_ALLOWS_WITHDRAWALS: Item<bool> = Item::new("pause_allows_withdrawals");
+pub const DEPOSIT_ALLOWLIST: Map<Addr, bool> = Map::new("deposit_allowlist");
+/// Addresses that deposit and withdraw without the share, deposit and withdraw fees. The
+/// performance fee is charged on the whole vault and still applies to them.
+pub const FEE_EXEMPT: Map<Addr, bool> = Map::new("fee_exempt");
+/// Tokens each address has deposited and not yet withdrawn. A withdraw takes off the same
+/// fraction as the shares it burns from the holder's balance.
+pub const DEPOSITED: Map<Addr, Uint128> = Map::new("deposited");
+/// Tokens deposited and not yet withdrawn, across all holders. A withdraw takes off the same
+/// fraction as the shares it burns from the total supply, so total assets minus this is yield.
+pub const TOTAL_PRINCIPAL: Item<Uint128> = Item::new("total_principal");
+/// Tokens deposited naming each referrer. Never decreases; withdrawals do not undo a referral.
+pub const REFERRALS: Map<Addr, Uint128> = Map::new("referrals");
+/// Shares currently held by the fee recipient because they were minted as fees. They are part
+/// of `TOTAL_SUPPLY` but not of the circulating supply.
+pub const FEE_SHARES: Item<Uint128> = Item::new("fee_shares");
+/// Shares locked by first deposits. They are part of `TOTAL_SUPPLY` but held by no address,
+/// so the assets backing them can never be withdrawn.
+pub const DEAD_SHARES: Item<Uint128> = Item::new("dead_shares");
+/// Share price (total assets / total supply) observed before each deposit and withdraw,
+/// keyed by block time in seconds.
+pub const SHARE_PRICE_SNAPSHOTS: Map<u64, Decimal> = Map::new("share_price_snapshots");
+/// Highest share price performance fees have been charged up to. Only growth above it is
+/// charged again, so a drawdown has to be recovered before any further fee. Unset means the
+/// vault's starting price of one token per share.
+pub const HIGH_WATER_MARK: Item<Decimal> = Item::new("high_water_mark");
+/// A withdrawal waiting out `withdraw_delay`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct QueuedWithdrawal {
+    /// In seconds.
+    pub unlock_at: u64,
+    /// Tokens the burned shares redeemed for, `fee` included.
+    pub amount: Uint128,
+    /// Withdraw fee held back from `amount` and paid to the fee recipient on claim.
+    pub fee: Uint128,
+    /// Shares the request burned, minted back if it is cancelled.
+    pub shares: Uint128,
+    /// Share fee held by the vault until claim, when it goes to the fee recipient. A cancel
+    /// hands it back to the requester instead.
+    #[serde(default)]
+    pub fee_shares: Uint128,
+}
+
+/// Each address's queued withdrawals by request id.
+pub const WITHDRAW_QUEUE: Map<(Addr, u64), QueuedWithdrawal> = Map::new("withdraw_queue");
+/// Id the next withdrawal request is queued under.
+pub const NEXT_WITHDRAW_ID: Item<u64> = Item::new("next_withdraw_id");
+/// Sum of `amount` over `WITHDRAW_QUEUE`. The vault still holds these tokens but they back
+/// no shares.
+pub const QUEUED_ASSETS: Item<Uint128> = Item::new("queued_assets");